@@ -81,6 +81,21 @@ fn main() {
 
             let parser_output = CodeFile::create(&dest.join("inst_parser.rs"));
             gen_parser(&instructions, parser_output).unwrap();
+
+            let writer_output = CodeFile::create(&dest.join("inst_writer.rs"));
+            gen_writer(&instructions, writer_output).unwrap();
+
+            let disasm_output = CodeFile::create(&dest.join("inst_disasm.rs"));
+            gen_disasm(&instructions, disasm_output).unwrap();
+
+            let asm_output = CodeFile::create(&dest.join("inst_asm.rs"));
+            gen_asm(&instructions, asm_output).unwrap();
+
+            let builder_output = CodeFile::create(&dest.join("inst_builder.rs"));
+            gen_builder(&instructions, builder_output).unwrap();
+
+            let visit_output = CodeFile::create(&dest.join("inst_visit.rs"));
+            gen_visit(&instructions, visit_output).unwrap();
         }
         Err(e) => {
             let mut stderr = std::io::stderr();
@@ -95,6 +110,8 @@ fn gen_insts(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
 
     // Generate the definition, each instruction is a struct variant
     try!(dest.write_line("#[derive(Clone, Debug)]"));
+    try!(dest.write_line(
+        "#[cfg_attr(feature = \"serde\", derive(::serde::Serialize, ::serde::Deserialize))]"));
     try!(dest.start_block("pub enum Instruction {"));
 
     for inst in insts {
@@ -192,118 +209,215 @@ fn gen_insts(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
     // Generate method for getting the id of the type of the instruction defines
     try!(extract_field(&mut dest, "defines_type", true, "TypeId", types, "result-type"));
 
-    // Finally generate a method for getting all the ids used by the instruction
+    // Generate a method for getting the ids used by the instruction one
+    // at a time, so they can be iterated over without allocating
     let users = insts.iter().filter(|i| {
-        for p in &i.params {
-            if p.name.starts_with("result") {
-                continue;
-            }
+        i.params.iter().any(|p| !p.name.starts_with("result") && p.ty.has_use())
+    });
+
+    try!(dest.start_block("fn nth_use(&self, n: usize) -> Option<Id> {"));
+    try!(dest.write_line("use self::Instruction::*;"));
+    try!(dest.write_line("let mut n = n;"));
+    try!(dest.start_block("match *self {"));
 
+    for u in users {
+        let params = u.params.iter().filter(|p| {
+            !p.name.starts_with("result") && p.ty.has_use()
+        }).collect::<Vec<_>>();
+
+        try!(dest.start_block(&format!("{} {{", u.name)));
+        for p in &params {
+            let name = normalize_name(&p.name);
             match p.ty {
-                ParamTy::Single(ty, _) |
-                ParamTy::Repeat(ty) => {
-                    match ty {
-                        Ty::Id | Ty::TypeId | Ty::ValueId => return true,
-                        _ => ()
-                    }
+                ParamTy::Single(Ty::ImageOperands, _) |
+                ParamTy::Repeat(..) |
+                ParamTy::RepeatMany(..) => {
+                    try!(dest.write_line(&format!("ref {},", name)));
+                }
+                ParamTy::Single(..) => {
+                    try!(dest.write_line(&format!("{},", name)));
+                }
+            }
+        }
+
+        if params.len() != u.params.len() {
+            try!(dest.write_line(".."));
+        }
+        try!(dest.new_block("} => {"));
+
+        for (i, p) in params.iter().enumerate() {
+            let name = normalize_name(&p.name);
+            let len = match p.ty {
+                ParamTy::Single(Ty::ImageOperands, _) => {
+                    try!(dest.start_block(&format!("if n < {}.values().len() {{", name)));
+                    try!(dest.write_line(&format!("return Some({}.values()[n].into());", name)));
+                    format!("{}.values().len()", name)
+                }
+                ParamTy::Single(..) => {
+                    try!(dest.start_block("if n == 0 {"));
+                    try!(dest.write_line(&format!("return Some({}.into());", name)));
+                    "1".to_owned()
+                }
+                ParamTy::Repeat(..) => {
+                    try!(dest.start_block(&format!("if n < {}.len() {{", name)));
+                    try!(dest.write_line(&format!("return Some({}[n].into());", name)));
+                    format!("{}.len()", name)
                 }
                 ParamTy::RepeatMany(ref tys) => {
-                    for &ty in tys {
-                        match ty {
-                            Ty::Id | Ty::TypeId | Ty::ValueId => return true,
-                            _ => ()
+                    let used : Vec<_> = tys.iter().enumerate().filter(|&(_, ty)| {
+                        ty.is_use()
+                    }).map(|(i, _)| i).collect();
+                    let count = used.len();
+                    let len = if count == 1 {
+                        format!("{}.len()", name)
+                    } else {
+                        format!("{}.len() * {}", name, count)
+                    };
+                    try!(dest.start_block(&format!("if n < {} {{", len)));
+                    if count == 1 {
+                        try!(dest.write_line(&format!("return Some({}[n].{}.into());", name,
+                                                      used[0])));
+                    } else {
+                        try!(dest.write_line(&format!("let x = &{}[n / {}];", name, count)));
+                        try!(dest.start_block(&format!("return Some(match n % {} {{", count)));
+                        for (j, &field) in used.iter().enumerate() {
+                            let pat = if j + 1 == count { "_".to_owned() } else { j.to_string() };
+                            try!(dest.write_line(&format!("{} => x.{}.into(),", pat, field)));
                         }
+                        try!(dest.end_block("});"));
                     }
+                    len
                 }
+            };
+            try!(dest.end_block("}"));
+            if i + 1 != params.len() {
+                try!(dest.write_line(&format!("n -= {};", len)));
             }
         }
+        try!(dest.end_block("}"));
+    }
 
-        return false;
-    });
+    try!(dest.write_line("_ => ()"));
 
-    try!(dest.start_block("pub fn uses(&self) -> Vec<Id> {"));
+    try!(dest.end_block("}"));
+    try!(dest.write_line("None"));
+    try!(dest.end_block("}"));
+
+    // Generate methods for getting the opcode and the group in core.desc
+    // of the instruction
+    try!(dest.start_block("pub fn opcode(&self) -> u16 {"));
     try!(dest.write_line("use self::Instruction::*;"));
     try!(dest.start_block("match *self {"));
+    for inst in insts {
+        let pat = if inst.params.len() > 0 { " { .. }" } else { "" };
+        try!(dest.write_line(&format!("{}{} => desc::Op::{} as u16,", inst.name, pat, inst.name)));
+    }
+    try!(dest.write_line("Unknown(op, _) => op"));
+    try!(dest.end_block("}"));
+    try!(dest.end_block("}"));
 
-    for u in users {
-        let params = u.params.iter().filter(|p| {
-            if p.name.starts_with("result") {
-                return false;
+    try!(dest.start_block("pub fn group(&self) -> Option<&'static str> {"));
+    try!(dest.write_line("use self::Instruction::*;"));
+    try!(dest.start_block("match *self {"));
+    for inst in insts {
+        let pat = if inst.params.len() > 0 { " { .. }" } else { "" };
+        match inst.group {
+            Some(ref group) => {
+                try!(dest.write_line(&format!("{}{} => Some(\"{}\"),", inst.name, pat, group)));
             }
+            None => try!(dest.write_line(&format!("{}{} => None,", inst.name, pat)))
+        }
+    }
+    try!(dest.write_line("Unknown(..) => None"));
+    try!(dest.end_block("}"));
+    try!(dest.end_block("}"));
 
-            match p.ty {
-                ParamTy::Single(ty, _) |
-                ParamTy::Repeat(ty) => {
-                    match ty {
-                        Ty::Id | Ty::TypeId | Ty::ValueId => return true,
-                        _ => ()
-                    }
-                }
-                ParamTy::RepeatMany(ref tys) => {
-                    for &ty in tys {
-                        match ty {
-                            Ty::Id | Ty::TypeId | Ty::ValueId => return true,
-                            _ => ()
-                        }
-                    }
-                }
-            }
+    // Finally generate a method that gives each id with its role, skipping
+    // missing optional ids. `Instruction::map_ids` is built on this.
+    let mappers = insts.iter().filter(|i| {
+        i.params.iter().any(|p| p.ty.has_id())
+    });
 
-            false
-        }).collect::<Vec<_>>();
+    try!(dest.start_block("pub fn for_each_id_mut<F: FnMut(&mut u32, IdRole)>(&mut self, mut f: F) {"));
+    try!(dest.write_line("use self::Instruction::*;"));
+    try!(dest.start_block("match *self {"));
 
-        try!(dest.start_block(&format!("{} {{", u.name)));
-        let mut i = 0;
+    for m in mappers {
+        let params = m.params.iter().filter(|p| p.ty.has_id()).collect::<Vec<_>>();
+        let is_type = m.group.as_ref().map(|g| g == "Type").unwrap_or(false);
+        let role = |ty: Ty| match ty {
+            Ty::ResultType if is_type => "IdRole::Result",
+            Ty::ResultType => "IdRole::ResultType",
+            Ty::ResultId => "IdRole::Result",
+            Ty::TypeId => "IdRole::Type",
+            Ty::ValueId | Ty::ImageOperands => "IdRole::Value",
+            _ => "IdRole::Any"
+        };
+
+        try!(dest.start_block(&format!("{} {{", m.name)));
         for p in &params {
             let name = normalize_name(&p.name);
-            match p.ty {
-                ParamTy::Single(..) => {
-                    try!(dest.write_line(&format!("{},", name)));
-                }
-                ParamTy::Repeat(..) |
-                ParamTy::RepeatMany(..) => {
-                    try!(dest.write_line(&format!("ref {},", name)));
-                }
-            }
-            i += 1;
+            try!(dest.write_line(&format!("ref mut {},", name)));
         }
 
-        if i != u.params.len() {
+        if params.len() != m.params.len() {
             try!(dest.write_line(".."));
         }
         try!(dest.new_block("} => {"));
-        try!(dest.write_line("let mut ids = Vec::new();"));
 
         for p in params {
             let name = normalize_name(&p.name);
             match p.ty {
-                ParamTy::Single(..) => {
-                    try!(dest.write_line(&format!("ids.push({}.into());", name)));
+                ParamTy::Single(Ty::ImageOperands, _) => {
+                    try!(dest.start_block(&format!("for x in {}.values_mut() {{", name)));
+                    try!(dest.write_line("f(&mut x.0, IdRole::Value);"));
+                    try!(dest.end_block("}"));
                 }
-                ParamTy::Repeat(..) => {
-                    try!(dest.write_line(&format!("ids.extend({}.iter().map(|i| Id::from(*i)));", name)));
+                ParamTy::Single(ty, true) => {
+                    try!(dest.write_line(&format!("if {}.is_valid() {{ f(&mut {}.0, {}); }}",
+                                                  name, name, role(ty))));
+                }
+                ParamTy::Single(ty, false) => {
+                    try!(dest.write_line(&format!("f(&mut {}.0, {});", name, role(ty))));
+                }
+                ParamTy::Repeat(ty) => {
+                    try!(dest.start_block(&format!("for x in {}.iter_mut() {{", name)));
+                    try!(dest.write_line(&format!("f(&mut x.0, {});", role(ty))));
+                    try!(dest.end_block("}"));
                 }
                 ParamTy::RepeatMany(ref tys) => {
-                    try!(dest.start_block(&format!("for x in {}.iter() {{", name)));
+                    try!(dest.start_block(&format!("for x in {}.iter_mut() {{", name)));
                     for (i, &ty) in tys.iter().enumerate() {
-                        match ty {
-                            Ty::Id | Ty::TypeId | Ty::ValueId => {
-                                try!(dest.write_line(&format!("ids.push(x.{}.into());", i)));
-                            }
-                            _ => ()
+                        if ty.is_id() || ty.is_use() {
+                            try!(dest.write_line(&format!("f(&mut (x.{}).0, {});", i, role(ty))));
                         }
                     }
                     try!(dest.end_block("}"));
                 }
             }
         }
-        try!(dest.write_line("ids"));
         try!(dest.end_block("}"));
     }
 
-    try!(dest.write_line("_ => Vec::new()"));
+    try!(dest.write_line("_ => ()"));
+
+    try!(dest.end_block("}"));
+    try!(dest.end_block("}"));
 
     try!(dest.end_block("}"));
+
+    // Generate a table of operand names, for error messages
+    try!(dest.write_line("/// Gets the names of the operands of the instruction with the given"));
+    try!(dest.write_line("/// opcode, as they are in `core.desc`. A repeated operand has a"));
+    try!(dest.write_line("/// single name."));
+    try!(dest.start_block("pub fn operand_names(opcode: u16) -> &'static [&'static str] {"));
+    try!(dest.start_block("match opcode {"));
+    for inst in insts {
+        if inst.params.len() == 0 { continue; }
+        let names : Vec<_> = inst.params.iter().map(|p| format!("{:?}", p.name)).collect();
+        try!(dest.write_line(&format!("{} => &[{}],", inst.opcode, names.join(", "))));
+    }
+    try!(dest.write_line("_ => &[]"));
     try!(dest.end_block("}"));
 
     dest.end_block("}")
@@ -312,15 +426,15 @@ fn gen_insts(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
 
 fn gen_parser(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
     try!(dest.start_block(
-        "pub fn parse_raw_instruction(raw_inst: RawInstruction) -> Result<Instruction> {"));
+        "pub fn parse_instruction(opcode: u16, params: &[u32]) -> Result<Instruction> {"));
     try!(dest.start_block(
-        "let op = if let Some(op) = desc::Op::from(raw_inst.opcode) {"));
+        "let op = if let Some(op) = desc::Op::from(opcode) {"));
     try!(dest.write_line("op"));
     try!(dest.new_block("} else {"));
-    try!(dest.write_line("return Err(ParseError::UnknownOpcode(raw_inst.opcode));"));
+    try!(dest.write_line("return Err(ParseError::UnknownOpcode(opcode));"));
     try!(dest.end_block("};"));
 
-    try!(dest.write_line("let mut p = InstructionParser { params: &raw_inst.params };"));
+    try!(dest.write_line("let mut p = InstructionParser { opcode: opcode, params: params };"));
 
     try!(dest.start_block("let inst = match op {"));
 
@@ -333,7 +447,7 @@ fn gen_parser(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
 
         try!(dest.start_block(&format!("Op::{} => {{", inst.name)));
 
-        for param in &inst.params {
+        for (idx, param) in inst.params.iter().enumerate() {
             let name = normalize_name(&param.name);
 
             if let ParamTy::Single(ty, true) = param.ty {
@@ -342,7 +456,7 @@ fn gen_parser(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
                     try!(dest.write_line(&format!("let mut {} = {}(0);", name, ty_name)));
                     try!(dest.start_block("if p.has_words() {"));
                     try!(dest.write_line(&format!(
-                        "{} = try!(p.parse::<{}>());\n", name, ty_name)));
+                        "{} = try!(p.parse_operand::<{}>({}));\n", name, ty_name, idx)));
                     try!(dest.end_block("}"));
                     continue;
                 }
@@ -356,7 +470,7 @@ fn gen_parser(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
                 try!(dest.start_block("while p.has_words() {"));
                 try!(dest.start_block(&format!("{}.push((", name)));
                 for ty in &tys {
-                    try!(dest.write_line(&format!("try!(p.parse::<{}>()),", ty)));
+                    try!(dest.write_line(&format!("try!(p.parse_operand::<{}>({})),", ty, idx)));
                 }
                 try!(dest.end_block("));"));
                 try!(dest.end_block("}"));
@@ -368,7 +482,8 @@ fn gen_parser(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
 
             let ty = param.ty.rust_type_name();
 
-            try!(dest.write_line(&format!("let {} = try!(p.parse::<{}>());", name, ty)));
+            try!(dest.write_line(&format!("let {} = try!(p.parse_operand::<{}>({}));",
+                                      name, ty, idx)));
         }
 
         try!(dest.start_block(&format!("Instruction::{} {{", inst.name)));
@@ -388,6 +503,655 @@ fn gen_parser(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
     dest.end_block("}")
 }
 
+fn gen_writer(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
+    try!(dest.start_block(
+        "pub fn encode_instruction(inst: &Instruction, words: &mut Vec<u32>) {"));
+    // Reserve the first word, it's filled in once we know the length
+    try!(dest.write_line("let start = words.len();"));
+    try!(dest.write_line("words.push(0);"));
+
+    try!(dest.start_block("let op = match *inst {"));
+
+    for inst in insts {
+        if inst.params.len() == 0 {
+            try!(dest.write_line(&format!(
+                "Instruction::{name} => Op::{name} as u16,", name=inst.name)));
+            continue;
+        }
+
+        try!(dest.start_block(&format!("Instruction::{} {{", inst.name)));
+        for param in &inst.params {
+            let name = normalize_name(&param.name);
+            try!(dest.write_line(&format!("ref {},", name)));
+        }
+        try!(dest.new_block("} => {"));
+
+        for param in &inst.params {
+            let name = normalize_name(&param.name);
+
+            match param.ty {
+                ParamTy::Single(ty, true) if ty.is_id() => {
+                    // Missing optional ids are represented by the
+                    // invalid id
+                    try!(dest.write_line(&format!(
+                        "if {name}.is_valid() {{ {name}.encode(words); }}", name=name)));
+                }
+                ParamTy::Single(Ty::ImageOperands, true) => {
+                    try!(dest.write_line(&format!(
+                        "if !{name}.is_empty() {{ {name}.encode(words); }}", name=name)));
+                }
+                ParamTy::Single(..) | ParamTy::Repeat(..) => {
+                    try!(dest.write_line(&format!("{}.encode(words);", name)));
+                }
+                ParamTy::RepeatMany(ref tys) => {
+                    try!(dest.start_block(&format!("for x in {}.iter() {{", name)));
+                    for i in 0..tys.len() {
+                        try!(dest.write_line(&format!("x.{}.encode(words);", i)));
+                    }
+                    try!(dest.end_block("}"));
+                }
+            }
+        }
+
+        try!(dest.write_line(&format!("Op::{} as u16", inst.name)));
+        try!(dest.end_block("}"));
+    }
+
+    try!(dest.start_block("Instruction::Unknown(op, ref params) => {"));
+    try!(dest.write_line("words.extend_from_slice(params);"));
+    try!(dest.write_line("op"));
+    try!(dest.end_block("}"));
+
+    try!(dest.end_block("};"));
+
+    try!(dest.write_line("let count = (words.len() - start) as u32;"));
+    try!(dest.write_line("words[start] = (count << 16) | (op as u32);"));
+
+    dest.end_block("}")
+}
+
+fn gen_disasm(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
+    try!(dest.start_block(
+        "fn write_instruction(d: &Disassembler, inst: &Instruction, out: &mut String) {"));
+    try!(dest.start_block("match *inst {"));
+
+    for inst in insts {
+        if inst.params.len() == 0 {
+            try!(dest.write_line(&format!(
+                "Instruction::{name} => out.push_str(\"Op{name}\"),", name=inst.name)));
+            continue;
+        }
+
+        // The id defined by the instruction is written first. For types,
+        // that's the result type.
+        let is_type = inst.group.as_ref().map(|g| &g[..]) == Some("Type");
+        let result = inst.params.iter().find(|p| {
+            match p.ty {
+                ParamTy::Single(Ty::ResultId, _) => true,
+                ParamTy::Single(Ty::ResultType, _) => is_type,
+                _ => false
+            }
+        }).map(|p| &p.name[..]);
+
+        try!(dest.start_block(&format!("Instruction::{} {{", inst.name)));
+        for param in &inst.params {
+            let name = normalize_name(&param.name);
+            try!(dest.write_line(&format!("ref {},", name)));
+        }
+        try!(dest.new_block("} => {"));
+
+        if let Some(result) = result {
+            try!(dest.write_line(&format!(
+                "out.push_str(&d.id_name(Id::from(*{})));", normalize_name(result))));
+            try!(dest.write_line("out.push_str(\" = \");"));
+        }
+        try!(dest.write_line(&format!("out.push_str(\"Op{}\");", inst.name)));
+
+        for param in &inst.params {
+            if Some(&param.name[..]) == result { continue; }
+            let name = normalize_name(&param.name);
+
+            match param.ty {
+                ParamTy::Single(ty, true) if ty.is_id() => {
+                    try!(dest.write_line(&format!(
+                        "if {name}.is_valid() {{ {name}.disasm(d, out); }}", name=name)));
+                }
+                ParamTy::Single(Ty::ImageOperands, true) => {
+                    try!(dest.write_line(&format!(
+                        "if !{name}.is_empty() {{ {name}.disasm(d, out); }}", name=name)));
+                }
+                ParamTy::Single(..) | ParamTy::Repeat(..) => {
+                    try!(dest.write_line(&format!("{}.disasm(d, out);", name)));
+                }
+                ParamTy::RepeatMany(ref tys) => {
+                    try!(dest.start_block(&format!("for x in {}.iter() {{", name)));
+                    for i in 0..tys.len() {
+                        try!(dest.write_line(&format!("x.{}.disasm(d, out);", i)));
+                    }
+                    try!(dest.end_block("}"));
+                }
+            }
+        }
+
+        try!(dest.end_block("}"));
+    }
+
+    try!(dest.start_block("Instruction::Unknown(op, ref params) => {"));
+    try!(dest.write_line("out.push_str(\"OpUnknown\");"));
+    try!(dest.write_line("(op as u32).disasm(d, out);"));
+    try!(dest.write_line("params.disasm(d, out);"));
+    try!(dest.end_block("}"));
+
+    try!(dest.end_block("}"));
+    dest.end_block("}")
+}
+
+fn gen_asm(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
+    try!(dest.start_block(
+        "fn assemble_instruction(a: &mut InstAssembler, op: &str) -> ::std::result::Result<Instruction, String> {"));
+    try!(dest.start_block("let inst = match op {"));
+
+    for inst in insts {
+        if inst.params.len() == 0 {
+            try!(dest.write_line(&format!(
+                "\"Op{name}\" => Instruction::{name},", name=inst.name)));
+            continue;
+        }
+
+        // Matches the disassembler, the id before the `=` is the result
+        // id, or the result type for types
+        let is_type = inst.group.as_ref().map(|g| &g[..]) == Some("Type");
+        let result = inst.params.iter().find(|p| {
+            match p.ty {
+                ParamTy::Single(Ty::ResultId, _) => true,
+                ParamTy::Single(Ty::ResultType, _) => is_type,
+                _ => false
+            }
+        }).map(|p| &p.name[..]);
+
+        try!(dest.start_block(&format!("\"Op{}\" => {{", inst.name)));
+
+        if let Some(result) = result {
+            let param = inst.params.iter().find(|p| p.name == result).unwrap();
+            if let ParamTy::Single(ty, _) = param.ty {
+                try!(dest.write_line(&format!("let {} = {}(try!(a.result()).0);",
+                                              normalize_name(result), ty.rust_type_name(false))));
+            }
+        }
+
+        for param in &inst.params {
+            if Some(&param.name[..]) == result { continue; }
+            let name = normalize_name(&param.name);
+
+            match param.ty {
+                ParamTy::Single(ty, true) if ty.is_id() => {
+                    let ty_name = ty.rust_type_name(false);
+                    try!(dest.write_line(&format!("let mut {} = {}(0);", name, ty_name)));
+                    try!(dest.start_block("if a.has_tokens() {"));
+                    try!(dest.write_line(&format!("{} = try!(a.parse::<{}>());", name, ty_name)));
+                    try!(dest.end_block("}"));
+                }
+                ParamTy::RepeatMany(ref tys) => {
+                    let tys : Vec<_> = tys.iter().map(|ty| ty.rust_type_name(false)).collect();
+                    try!(dest.write_line(&format!(
+                        "let mut {} : Vec<({})> = Vec::new();", name, tys.join(", "))));
+                    try!(dest.start_block("while a.has_tokens() {"));
+                    try!(dest.start_block(&format!("{}.push((", name)));
+                    for ty in &tys {
+                        try!(dest.write_line(&format!("try!(a.parse::<{}>()),", ty)));
+                    }
+                    try!(dest.end_block("));"));
+                    try!(dest.end_block("}"));
+                    try!(dest.write_line(&format!(
+                        "let {name} = {name}.into_boxed_slice();", name=name)));
+                }
+                _ => {
+                    let ty = param.ty.rust_type_name();
+                    try!(dest.write_line(&format!("let {} = try!(a.parse::<{}>());", name, ty)));
+                }
+            }
+        }
+
+        try!(dest.start_block(&format!("Instruction::{} {{", inst.name)));
+        for param in &inst.params {
+            let name = normalize_name(&param.name);
+            try!(dest.write_line(&format!("{name}: {name},", name=name)));
+        }
+        try!(dest.end_block("}"));
+        try!(dest.end_block("}"));
+    }
+
+    try!(dest.start_block("\"OpUnknown\" => {"));
+    try!(dest.write_line("let op = try!(a.parse::<u32>());"));
+    try!(dest.write_line("let params = try!(a.parse::<Box<[u32]>>());"));
+    try!(dest.write_line("Instruction::Unknown(op as u16, params)"));
+    try!(dest.end_block("}"));
+    try!(dest.write_line("_ => return Err(format!(\"unknown instruction `{}`\", op))"));
+
+    try!(dest.end_block("};"));
+    try!(dest.write_line("Ok(inst)"));
+
+    dest.end_block("}")
+}
+
+fn gen_builder(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
+    try!(dest.start_block("impl Builder {"));
+
+    for inst in insts {
+        // Types use their result type as the id they define
+        let defines_type = inst.group.as_ref().map(|g| g == "Type").unwrap_or(false) &&
+            inst.params.iter().any(|p| {
+                if let ParamTy::Single(Ty::ResultType, _) = p.ty { true } else { false }
+            });
+        let defines_value = inst.params.iter().any(|p| {
+            if let ParamTy::Single(Ty::ResultId, _) = p.ty { true } else { false }
+        });
+        let cached = match inst.group.as_ref().map(|g| &g[..]) {
+            // Structs, arrays and pointers can be declared more than once
+            // so each copy can have different decorations
+            Some("Type") => defines_type && match &inst.name[..] {
+                "TypeStruct" | "TypeArray" | "TypeRuntimeArray" | "TypePointer" => false,
+                _ => true
+            },
+            Some("Constant") => !inst.name.starts_with("Spec"),
+            _ => false
+        };
+
+        let mut args = Vec::new();
+        let mut fields = Vec::new();
+        for param in &inst.params {
+            let name = normalize_name(&param.name);
+            match param.ty {
+                ParamTy::Single(Ty::ResultType, _) if defines_type => {
+                    fields.push((name, "new_id.to_type_id()".to_owned()));
+                }
+                ParamTy::Single(Ty::ResultId, _) => {
+                    fields.push((name, "new_id.to_result_id()".to_owned()));
+                }
+                ParamTy::Single(ty, true) if ty.is_id() => {
+                    let ty_name = ty.rust_type_name(false);
+                    args.push(format!("{}: Option<{}>", name, ty_name));
+                    let value = format!("{}.unwrap_or({}(0))", name, ty_name);
+                    fields.push((name, value));
+                }
+                ParamTy::Single(Ty::String, false) => {
+                    args.push(format!("{}: &str", name));
+                    let value = format!("{}.to_owned()", name);
+                    fields.push((name, value));
+                }
+                ParamTy::Single(..) => {
+                    args.push(format!("{}: {}", name, param.ty.rust_type_name()));
+                    let value = name.clone().into_owned();
+                    fields.push((name, value));
+                }
+                ParamTy::Repeat(..) | ParamTy::RepeatMany(..) => {
+                    let ty = param.ty.rust_type_name();
+                    // Take a slice rather than the boxed slice
+                    args.push(format!("{}: &{}", name, &ty[4..ty.len() - 1]));
+                    let value = format!("{}.to_vec().into_boxed_slice()", name);
+                    fields.push((name, value));
+                }
+            }
+        }
+
+        let construct = |new_id: &str| -> String {
+            if fields.is_empty() {
+                return format!("Instruction::{}", inst.name);
+            }
+            let fields : Vec<_> = fields.iter().map(|&(ref name, ref value)| {
+                format!("{}: {}", name, value.replace("new_id", new_id))
+            }).collect();
+            format!("Instruction::{} {{ {} }}", inst.name, fields.join(", "))
+        };
+
+        let ret = if defines_type {
+            " -> TypeId"
+        } else if defines_value {
+            " -> ValueId"
+        } else {
+            ""
+        };
+        try!(dest.write_line(&format!("/// Adds an `Op{}` instruction", inst.name)));
+        try!(dest.start_block(&format!("pub fn {}(&mut self{}{}){} {{", method_name(&inst.name),
+                                       if args.is_empty() { "" } else { ", " }, args.join(", "),
+                                       ret)));
+
+        if cached {
+            // Types and constants are only added once, so the instruction
+            // is first built with a placeholder id to look it up
+            try!(dest.write_line(&format!("let key = cache_key(&{});", construct("Id(0)"))));
+            try!(dest.start_block("if let Some(&id) = self.cache.get(&key) {"));
+            try!(dest.write_line(&format!("return id.{};", if defines_type {
+                "to_type_id()"
+            } else {
+                "to_value_id()"
+            })));
+            try!(dest.end_block("}"));
+            try!(dest.write_line("let new_id = self.alloc_id();"));
+            try!(dest.write_line("self.cache.insert(key, new_id);"));
+        } else if defines_type || defines_value {
+            try!(dest.write_line("let new_id = self.alloc_id();"));
+        }
+
+        try!(dest.write_line(&format!("self.push({});", construct("new_id"))));
+
+        if defines_type {
+            try!(dest.write_line("new_id.to_type_id()"));
+        } else if defines_value {
+            try!(dest.write_line("new_id.to_value_id()"));
+        }
+
+        try!(dest.end_block("}\n"));
+    }
+
+    dest.end_block("}")
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum VisitMode {
+    Ref,
+    Mut,
+    Fold
+}
+
+fn gen_visit(insts: &[Instruction], mut dest: CodeFile) -> Result<()> {
+    // The id hooks come first, then the rest in the order they are
+    // first used
+    let mut tys = vec![Ty::ResultId, Ty::ResultType, Ty::TypeId, Ty::ValueId, Ty::Id];
+    for inst in insts {
+        for param in &inst.params {
+            let param_tys = match param.ty {
+                ParamTy::Single(ty, _) | ParamTy::Repeat(ty) => vec![ty],
+                ParamTy::RepeatMany(ref tys) => tys.clone()
+            };
+            for ty in param_tys {
+                if !tys.contains(&ty) {
+                    tys.push(ty);
+                }
+            }
+        }
+    }
+
+    for &mode in &[VisitMode::Ref, VisitMode::Mut, VisitMode::Fold] {
+        try!(gen_visit_trait(&tys, mode, &mut dest));
+    }
+
+    for &mode in &[VisitMode::Ref, VisitMode::Mut] {
+        try!(gen_walk(insts, mode, &mut dest));
+    }
+
+    // Folding rebuilds the instruction, rather than changing it in place
+    try!(dest.write_line("/// Folds each operand of the instruction with the matching hook"));
+    try!(dest.start_block(
+        "pub fn fold_operands<F: Fold + ?Sized>(folder: &mut F, inst: Instruction) -> Instruction {"));
+    try!(dest.write_line("use instruction::Instruction::*;"));
+    try!(dest.start_block("match inst {"));
+    for inst in insts {
+        if inst.params.is_empty() { continue; }
+
+        let names : Vec<_> = inst.params.iter().map(|p| normalize_name(&p.name)).collect();
+        try!(dest.start_block(&format!("{} {{ {} }} => {} {{", inst.name, names.join(", "),
+                                       inst.name)));
+        for (param, name) in inst.params.iter().zip(names.iter()) {
+            let expr = match param.ty {
+                ParamTy::Single(ty, true) if ty.is_id() => {
+                    format!("if {}.is_valid() {{ {} }} else {{ {} }}", name,
+                            fold_expr(inst, ty, name), name)
+                }
+                ParamTy::Single(Ty::MemoryAccess, true) | ParamTy::Single(Ty::ImageOperands, true) |
+                ParamTy::Single(_, false) => fold_expr(inst, param_ty(param), name),
+                ParamTy::Single(ty, true) => {
+                    format!("{}.map(|inner| {})", name, fold_expr(inst, ty, "inner"))
+                }
+                ParamTy::Repeat(ty) => {
+                    format!("{}.into_vec().into_iter().map(|elem| {}).collect::<Vec<_>>()\
+                             .into_boxed_slice()", name, fold_expr(inst, ty, "elem"))
+                }
+                ParamTy::RepeatMany(ref tys) => {
+                    let elems : Vec<_> = tys.iter().enumerate().map(|(i, &ty)| {
+                        fold_expr(inst, ty, &format!("elem.{}", i))
+                    }).collect();
+                    format!("{}.into_vec().into_iter().map(|elem| ({})).collect::<Vec<_>>()\
+                             .into_boxed_slice()", name, elems.join(", "))
+                }
+            };
+            try!(dest.write_line(&format!("{}: {},", name, expr)));
+        }
+        try!(dest.end_block("},"));
+    }
+    try!(dest.write_line("inst => inst"));
+    try!(dest.end_block("}"));
+    dest.end_block("}")
+}
+
+fn gen_visit_trait(tys: &[Ty], mode: VisitMode, dest: &mut CodeFile) -> Result<()> {
+    let (doc, name, prefix) = match mode {
+        VisitMode::Ref => ("Visits the operands of instructions", "Visitor", "visit"),
+        VisitMode::Mut => ("Visits the operands of instructions, allowing them to be changed",
+                           "VisitorMut", "visit"),
+        VisitMode::Fold => ("Rebuilds instructions from their folded operands", "Fold", "fold")
+    };
+    try!(dest.write_line(&format!("/// {}. Every hook does nothing by default, see the", doc)));
+    try!(dest.write_line("/// module documentation."));
+    try!(dest.start_block(&format!("pub trait {} {{", name)));
+
+    let (inst_sig, inst_body) = match mode {
+        VisitMode::Ref => ("inst: &Instruction)", "walk_instruction(self, inst)"),
+        VisitMode::Mut => ("inst: &mut Instruction)", "walk_instruction_mut(self, inst)"),
+        VisitMode::Fold => ("inst: Instruction) -> Instruction", "fold_operands(self, inst)")
+    };
+    try!(dest.write_line("/// Called for each instruction, visits its operands by default"));
+    try!(dest.start_block(&format!("fn {}_instruction(&mut self, {} {{", prefix, inst_sig)));
+    try!(dest.write_line(inst_body));
+    try!(dest.end_block("}"));
+
+    for &ty in tys {
+        let hook = hook_name(ty, false);
+        let doc = match ty {
+            Ty::ResultId => "the id defined by an instruction, including types".to_owned(),
+            Ty::ResultType => "the type of the result of an instruction".to_owned(),
+            Ty::TypeId => "each operand that is a type".to_owned(),
+            Ty::ValueId => "each operand that is a value".to_owned(),
+            Ty::Id => "each operand that may be a type or a value".to_owned(),
+            _ => format!("each `{}` operand", ty.rust_type_name(false))
+        };
+        try!(dest.write_line(&format!("/// Called for {}", doc)));
+
+        let rust_ty = ty.rust_type_name(false);
+        let by_ref = hook_by_ref(ty);
+        let arg_ty = match mode {
+            VisitMode::Ref if ty == Ty::String => "&str".to_owned(),
+            VisitMode::Ref if by_ref => format!("&{}", rust_ty),
+            VisitMode::Mut => format!("&mut {}", rust_ty),
+            _ => rust_ty.to_string()
+        };
+
+        let ret = if mode == VisitMode::Fold { format!(" -> {}", rust_ty) } else { String::new() };
+        if ty == Ty::ImageOperands {
+            // Image operands hold values, so visit those by default
+            try!(dest.start_block(&format!("fn {}_{}(&mut self, operands: {}){} {{", prefix, hook,
+                                           arg_ty, ret)));
+            match mode {
+                VisitMode::Ref => {
+                    try!(dest.start_block("for &value in operands.values() {"));
+                    try!(dest.write_line("self.visit_value_id(value);"));
+                }
+                VisitMode::Mut => {
+                    try!(dest.start_block("for value in operands.values_mut() {"));
+                    try!(dest.write_line("self.visit_value_id(value);"));
+                }
+                VisitMode::Fold => {
+                    try!(dest.write_line("let mut operands = operands;"));
+                    try!(dest.start_block("for value in operands.values_mut() {"));
+                    try!(dest.write_line("*value = self.fold_value_id(*value);"));
+                }
+            }
+            try!(dest.end_block("}"));
+            if mode == VisitMode::Fold {
+                try!(dest.write_line("operands"));
+            }
+            try!(dest.end_block("}"));
+        } else if mode == VisitMode::Fold {
+            try!(dest.write_line(&format!("fn fold_{}(&mut self, operand: {}){} {{ operand }}", hook,
+                                          arg_ty, ret)));
+        } else {
+            try!(dest.write_line(&format!("fn visit_{}(&mut self, _: {}) {{}}", hook, arg_ty)));
+        }
+    }
+
+    dest.end_block("}\n")
+}
+
+fn gen_walk(insts: &[Instruction], mode: VisitMode, dest: &mut CodeFile) -> Result<()> {
+    let (binding, iter) = if mode == VisitMode::Mut {
+        ("ref mut ", "iter_mut")
+    } else {
+        ("ref ", "iter")
+    };
+
+    if mode == VisitMode::Mut {
+        try!(dest.write_line("/// Calls the matching hook for each operand of the instruction"));
+        try!(dest.start_block(
+            "pub fn walk_instruction_mut<V: VisitorMut + ?Sized>(visitor: &mut V, inst: &mut Instruction) {"));
+    } else {
+        try!(dest.write_line("/// Calls the matching hook for each operand of the instruction"));
+        try!(dest.start_block(
+            "pub fn walk_instruction<V: Visitor + ?Sized>(visitor: &mut V, inst: &Instruction) {"));
+    }
+    try!(dest.write_line("use instruction::Instruction::*;"));
+    try!(dest.start_block("match *inst {"));
+
+    for inst in insts {
+        if inst.params.is_empty() { continue; }
+
+        try!(dest.start_block(&format!("{} {{", inst.name)));
+        for param in &inst.params {
+            try!(dest.write_line(&format!("{}{},", binding, normalize_name(&param.name))));
+        }
+        try!(dest.new_block("} => {"));
+
+        for param in &inst.params {
+            let name = normalize_name(&param.name);
+            let place = format!("*{}", name);
+            match param.ty {
+                ParamTy::Single(ty, true) if ty.is_id() => {
+                    try!(dest.write_line(&format!("if {}.is_valid() {{ {} }}", name,
+                                                  visit_stmt(inst, ty, mode, &place))));
+                }
+                ParamTy::Single(Ty::MemoryAccess, true) | ParamTy::Single(Ty::ImageOperands, true) |
+                ParamTy::Single(_, false) => {
+                    try!(dest.write_line(&visit_stmt(inst, param_ty(param), mode, &place)));
+                }
+                ParamTy::Single(ty, true) => {
+                    try!(dest.write_line(&format!("if let Some({}inner) = {} {{ {} }}", binding,
+                                                  place, visit_stmt(inst, ty, mode, "*inner"))));
+                }
+                ParamTy::Repeat(ty) => {
+                    try!(dest.start_block(&format!("for elem in {}.{}() {{", name, iter)));
+                    try!(dest.write_line(&visit_stmt(inst, ty, mode, "*elem")));
+                    try!(dest.end_block("}"));
+                }
+                ParamTy::RepeatMany(ref tys) => {
+                    try!(dest.start_block(&format!("for elem in {}.{}() {{", name, iter)));
+                    for (i, &ty) in tys.iter().enumerate() {
+                        try!(dest.write_line(&visit_stmt(inst, ty, mode, &format!("elem.{}", i))));
+                    }
+                    try!(dest.end_block("}"));
+                }
+            }
+        }
+        try!(dest.end_block("}"));
+    }
+
+    try!(dest.write_line("_ => ()"));
+    try!(dest.end_block("}"));
+    dest.end_block("}\n")
+}
+
+fn param_ty(param: &Param) -> Ty {
+    match param.ty {
+        ParamTy::Single(ty, _) | ParamTy::Repeat(ty) => ty,
+        ParamTy::RepeatMany(_) => panic!("parameter `{}` has more than one type", param.name)
+    }
+}
+
+/// Whether the type's hook is passed a reference by `Visitor`
+fn hook_by_ref(ty: Ty) -> bool {
+    match ty {
+        Ty::String | Ty::Decoration | Ty::ExecutionMode | Ty::ImageOperands => true,
+        _ => false
+    }
+}
+
+/// Gets the name of the hook for an operand, without the `visit_` or
+/// `fold_` prefix. Types are defined by their result type, but it's
+/// passed to the result id hook, so the hooks see it as defined.
+fn hook_name(ty: Ty, defines_type: bool) -> String {
+    if ty == Ty::ResultType && defines_type {
+        return "result_id".to_owned();
+    }
+    method_name(&format!("{:?}", ty))
+}
+
+fn defines_type(inst: &Instruction, ty: Ty) -> bool {
+    ty == Ty::ResultType && inst.group.as_ref().map(|g| g == "Type").unwrap_or(false)
+}
+
+/// Gets the statement that visits the operand at `place`
+fn visit_stmt(inst: &Instruction, ty: Ty, mode: VisitMode, place: &str) -> String {
+    let hook = hook_name(ty, defines_type(inst, ty));
+    if defines_type(inst, ty) {
+        return match mode {
+            VisitMode::Mut => format!("{{ let mut id = Id::from({}).to_result_id(); \
+                                       visitor.visit_result_id(&mut id); \
+                                       {} = Id::from(id).to_type_id(); }}", place, place),
+            _ => format!("visitor.visit_result_id(Id::from({}).to_result_id());", place)
+        };
+    }
+
+    // The bindings are already references, so they can be passed as
+    // they are
+    let arg = match mode {
+        VisitMode::Mut | VisitMode::Ref if place.starts_with('*') &&
+            (mode == VisitMode::Mut || hook_by_ref(ty)) => place[1..].to_owned(),
+        VisitMode::Mut => format!("&mut {}", place),
+        _ if hook_by_ref(ty) => format!("&{}", place),
+        _ => place.to_owned()
+    };
+    format!("visitor.visit_{}({});", hook, arg)
+}
+
+/// Gets the expression that folds the operand `value`
+fn fold_expr(inst: &Instruction, ty: Ty, value: &str) -> String {
+    if defines_type(inst, ty) {
+        return format!("Id::from(folder.fold_result_id(Id::from({}).to_result_id())).to_type_id()",
+                       value);
+    }
+    format!("folder.fold_{}({})", hook_name(ty, false), value)
+}
+
+/// Converts an instruction name to the name of its builder method, for
+/// example `IAdd` to `i_add`
+fn method_name(name: &str) -> String {
+    let chars : Vec<char> = name.chars().collect();
+    let mut method = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev_lower = chars[i - 1].is_lowercase();
+            let next_lower = chars.get(i + 1).map(|c| c.is_lowercase()).unwrap_or(false);
+            if prev_lower || (chars[i - 1].is_uppercase() && next_lower) {
+                method.push('_');
+            }
+        }
+        method.extend(c.to_lowercase());
+    }
+
+    match &method[..] {
+        "return" => "ret".to_owned(),
+        _ => method
+    }
+}
+
 fn normalize_name<'a>(s: &'a str) -> Cow<'a, str> {
     if s.contains('-') {
         s.replace("-", "_").into()
@@ -396,7 +1160,7 @@ fn normalize_name<'a>(s: &'a str) -> Cow<'a, str> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Ty {
     Id,
     ResultType,
@@ -492,6 +1256,17 @@ impl Ty {
         }
     }
 
+    /// Whether a parameter of this type refers to other ids
+    pub fn is_use(&self) -> bool {
+        match *self {
+            Ty::Id |
+            Ty::TypeId |
+            Ty::ValueId |
+            Ty::ImageOperands => true,
+            _ => false
+        }
+    }
+
     pub fn rust_type_name(&self, opt: bool) -> Cow<'static, str> {
         use self::Ty::*;
         if opt {
@@ -550,6 +1325,22 @@ impl Ty {
 }
 
 impl ParamTy {
+    /// Whether the parameter holds any ids that refer to other instructions
+    pub fn has_use(&self) -> bool {
+        match *self {
+            ParamTy::Single(ty, _) | ParamTy::Repeat(ty) => ty.is_use(),
+            ParamTy::RepeatMany(ref tys) => tys.iter().any(|ty| ty.is_use())
+        }
+    }
+
+    /// Whether the parameter holds any ids, including defined ids
+    pub fn has_id(&self) -> bool {
+        match *self {
+            ParamTy::Single(ty, _) | ParamTy::Repeat(ty) => ty.is_id() || ty.is_use(),
+            ParamTy::RepeatMany(ref tys) => tys.iter().any(|ty| ty.is_id() || ty.is_use())
+        }
+    }
+
     pub fn rust_type_name(&self) -> Cow<'static, str> {
         match *self {
             ParamTy::Single(ty, opt) => ty.rust_type_name(opt),
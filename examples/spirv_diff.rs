@@ -0,0 +1,24 @@
+extern crate spirv_utils;
+
+use std::env;
+use std::process;
+
+use spirv_utils::RawModule;
+
+fn main() {
+    let args : Vec<_> = env::args_os().skip(1).collect();
+    if args.len() != 2 {
+        println!("Usage: spirv_diff <old.spv> <new.spv>");
+        process::exit(2);
+    }
+
+    let old = RawModule::load_module(&args[0]).unwrap();
+    let new = RawModule::load_module(&args[1]).unwrap();
+
+    let diff = spirv_utils::diff::diff(&old, &new);
+    print!("{}", diff);
+
+    if !diff.is_empty() {
+        process::exit(1);
+    }
+}
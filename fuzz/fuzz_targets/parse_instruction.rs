@@ -0,0 +1,32 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decodes a single instruction. The first two bytes are the opcode and
+//! the rest are the operands, as little-endian words.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate spirv_utils;
+
+use spirv_utils::parse::{parse_raw_instruction, RawInstruction};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    let opcode = data[0] as u16 | (data[1] as u16) << 8;
+    let params = data[2..].chunks(4).map(|b| {
+        b.iter().rev().fold(0, |w, &b| w << 8 | b as u32)
+    }).collect();
+
+    let _ = parse_raw_instruction(RawInstruction {
+        opcode: opcode,
+        params: params
+    });
+});
@@ -0,0 +1,39 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reads a whole module, checking that parsing from a reader and from a
+//! byte slice agree, that lenient parsing only finds errors when strict
+//! parsing fails, and that transforms can run on any module that parses.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate spirv_utils;
+
+use spirv_utils::RawModule;
+use spirv_utils::transform;
+
+fuzz_target!(|data: &[u8]| {
+    let read = RawModule::read_module(data);
+    let from_bytes = RawModule::from_bytes(data);
+    let lenient = RawModule::read_module_lenient(data);
+
+    if let Ok((_, ref errors)) = lenient {
+        assert_eq!(errors.is_empty(), read.is_ok());
+    }
+
+    match (read, from_bytes) {
+        (Ok(read), Ok(from_bytes)) => {
+            assert_eq!(read.to_words(), from_bytes.to_words());
+            transform::compact_ids(&read);
+            transform::fold_constants(&read);
+        }
+        (Err(_), Err(_)) => (),
+        (read, _) => panic!("read_module and from_bytes disagree, read_module gave {}",
+                            if read.is_ok() { "Ok" } else { "Err" })
+    }
+});
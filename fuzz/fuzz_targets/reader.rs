@@ -0,0 +1,31 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Streams instructions with `Reader`, skipping the operands of every
+//! other instruction so both paths get exercised.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate spirv_utils;
+
+use spirv_utils::parse::Reader;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = match Reader::new(data) {
+        Ok(reader) => reader,
+        Err(_) => return
+    };
+
+    let mut keep = false;
+    for inst in reader.instructions(|_| { keep = !keep; keep }) {
+        match inst {
+            Ok(inst) => { let _ = inst.decode(); }
+            Err(_) => break
+        }
+    }
+});
@@ -0,0 +1,626 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Assembly of the textual form produced by `disasm` back into modules.
+//!
+//! Each line holds a single instruction, optionally starting with the
+//! id it defines, e.g. `%5 = OpIAdd %int %3 %4`. Ids that are numbers
+//! keep their number, named ids are given the lowest free numbers in the
+//! order they appear. Comments start with `;`. The `Version` and
+//! `Generator` comments written by the disassembler are used for the
+//! module header.
+
+use std::collections::{HashMap, HashSet};
+use std::{error, fmt};
+
+use desc::{self, Id, ValueId, TypeId, ResultId};
+use diagnostic::Diagnostic;
+use instruction::{Instruction, Decoration, ExecutionMode, ImageOperands};
+use parse::Header;
+use RawModule;
+
+include!(concat!(env!("OUT_DIR"), "/inst_asm.rs"));
+
+pub type Result<T> = ::std::result::Result<T, AsmError>;
+
+/// An error in the text being assembled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// The line the error is on, starting from 1
+    pub line: usize,
+    pub message: String
+}
+
+impl AsmError {
+    /**
+     * Gets a diagnostic for the error, pointing at its line
+     */
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new("assembling", self.message.clone());
+        diagnostic.line = Some(self.line);
+        diagnostic
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error while assembling: line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for AsmError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/**
+ * Assemble a module from its textual form
+ */
+pub fn assemble(text: &str) -> Result<RawModule> {
+    let mut header = Header {
+        version: (1, 0),
+        generator_id: 0,
+        id_bound: 0
+    };
+
+    let mut lines = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let tokens = try!(tokenize(line).map_err(|message| {
+            AsmError { line: line_no, message: message }
+        }));
+
+        if tokens.is_empty() {
+            // Only the comments before the first instruction are used for
+            // the header
+            if lines.is_empty() {
+                read_header_comment(line, &mut header);
+            }
+            continue;
+        }
+        lines.push((line_no, tokens));
+    }
+
+    // Numbered ids keep their number, so need to be known before any
+    // names are given ids
+    let mut ids = IdMap::default();
+    for &(_, ref tokens) in &lines {
+        for token in tokens {
+            if let Token::Id(ref name) = *token {
+                if let Ok(n) = name.parse::<u32>() {
+                    ids.reserved.insert(n);
+                }
+            }
+        }
+    }
+
+    let mut instructions = Vec::with_capacity(lines.len());
+    for (line_no, tokens) in lines {
+        let inst = try!(assemble_line(&tokens, &mut ids).map_err(|message| {
+            AsmError { line: line_no, message: message }
+        }));
+        instructions.push(inst);
+    }
+
+    header.id_bound = ids.bound;
+
+    Ok(RawModule::from_instructions(header, instructions))
+}
+
+fn read_header_comment(line: &str, header: &mut Header) {
+    let line = line.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+    let mut parts = line.splitn(2, ':');
+    let (key, value) = match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) => (key.trim(), value.trim()),
+        _ => return
+    };
+
+    match key {
+        "Version" => {
+            let mut nums = value.splitn(2, '.').map(|n| n.parse::<u8>());
+            if let (Some(Ok(major)), Some(Ok(minor))) = (nums.next(), nums.next()) {
+                header.version = (major, minor);
+            }
+        }
+        "Generator" => {
+            if let Ok(n) = parse_number(value) {
+                header.generator_id = n;
+            }
+        }
+        _ => ()
+    }
+}
+
+fn assemble_line(tokens: &[Token], ids: &mut IdMap) -> ::std::result::Result<Instruction, String> {
+    let (result, rest) = match (tokens.get(0), tokens.get(1)) {
+        (Some(&Token::Id(ref name)), Some(&Token::Word(ref eq))) if eq == "=" => {
+            (Some(try!(ids.get(name))), &tokens[2..])
+        }
+        _ => (None, tokens)
+    };
+
+    let op = match rest.first() {
+        Some(&Token::Word(ref op)) => op,
+        _ => return Err("expected an instruction".to_owned())
+    };
+
+    let mut a = InstAssembler {
+        tokens: &rest[1..],
+        ids: ids,
+        result: result,
+        result_used: false
+    };
+
+    let inst = try!(assemble_instruction(&mut a, op));
+
+    if a.has_tokens() {
+        return Err(format!("too many operands for `{}`", op));
+    }
+    if a.result.is_some() && !a.result_used {
+        return Err(format!("`{}` doesn't define an id", op));
+    }
+
+    Ok(inst)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// An id, without the leading `%`
+    Id(String),
+    Str(String),
+    /// Anything else: instruction names, numbers, enumerants and `=`
+    Word(String)
+}
+
+fn tokenize(line: &str) -> ::std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        match chars.next() {
+                            Some(c) => s.push(c),
+                            None => return Err("unterminated string".to_owned())
+                        }
+                    }
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_owned())
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' { break; }
+                word.push(c);
+                chars.next();
+            }
+
+            if word.starts_with('%') {
+                if word.len() == 1 {
+                    return Err("expected a name after `%`".to_owned());
+                }
+                tokens.push(Token::Id(word[1..].to_owned()));
+            } else {
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses an integer, which can be negative or in hex, or a 32-bit
+/// float
+fn parse_number(s: &str) -> ::std::result::Result<u32, String> {
+    let err = || format!("expected a number, found `{}`", s);
+
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16).map_err(|_| err())
+    } else if s.starts_with('-') && !s.contains('.') {
+        s.parse::<i32>().map(|n| n as u32).map_err(|_| err())
+    } else if s.contains('.') || s.contains('e') {
+        s.parse::<f32>().map(|f| f.to_bits()).map_err(|_| err())
+    } else {
+        s.parse::<u32>().map_err(|_| err())
+    }
+}
+
+/// Gives each id in the text a number
+#[derive(Default)]
+struct IdMap {
+    names: HashMap<String, Id>,
+    reserved: HashSet<u32>,
+    next: u32,
+    bound: u32
+}
+
+impl IdMap {
+    fn get(&mut self, name: &str) -> ::std::result::Result<Id, String> {
+        if let Some(&id) = self.names.get(name) {
+            return Ok(id);
+        }
+
+        let id = if let Ok(n) = name.parse::<u32>() {
+            if n == 0 {
+                return Err("`%0` is not a valid id".to_owned());
+            }
+            Id(n)
+        } else {
+            loop {
+                self.next += 1;
+                if !self.reserved.contains(&self.next) { break; }
+            }
+            Id(self.next)
+        };
+
+        self.bound = ::std::cmp::max(self.bound, id.0 + 1);
+        self.names.insert(name.to_owned(), id);
+        Ok(id)
+    }
+}
+
+/// Assembles the operands of a single instruction
+struct InstAssembler<'a> {
+    tokens: &'a [Token],
+    ids: &'a mut IdMap,
+    result: Option<Id>,
+    result_used: bool
+}
+
+impl<'a> InstAssembler<'a> {
+    fn has_tokens(&self) -> bool {
+        self.tokens.len() > 0
+    }
+
+    fn next_token(&mut self) -> ::std::result::Result<&'a Token, String> {
+        match self.tokens.split_first() {
+            Some((token, rest)) => {
+                self.tokens = rest;
+                Ok(token)
+            }
+            None => Err("missing operand".to_owned())
+        }
+    }
+
+    fn next_word(&mut self) -> ::std::result::Result<&'a str, String> {
+        match *try!(self.next_token()) {
+            Token::Word(ref w) => Ok(w),
+            ref t => Err(format!("unexpected operand `{}`", token_text(t)))
+        }
+    }
+
+    fn result(&mut self) -> ::std::result::Result<Id, String> {
+        self.result_used = true;
+        self.result.ok_or_else(|| "missing result id".to_owned())
+    }
+
+    fn parse<T: ParamAsm>(&mut self) -> ::std::result::Result<T, String> {
+        T::asm(self)
+    }
+}
+
+fn token_text(token: &Token) -> String {
+    match *token {
+        Token::Id(ref name) => format!("%{}", name),
+        Token::Str(ref s) => format!("\"{}\"", s),
+        Token::Word(ref w) => w.clone()
+    }
+}
+
+trait ParamAsm : Sized {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String>;
+}
+
+impl ParamAsm for Id {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        match *try!(a.next_token()) {
+            Token::Id(ref name) => a.ids.get(name),
+            ref t => Err(format!("expected an id, found `{}`", token_text(t)))
+        }
+    }
+}
+
+impl ParamAsm for ValueId {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        a.parse::<Id>().map(|id| ValueId(id.0))
+    }
+}
+
+impl ParamAsm for TypeId {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        a.parse::<Id>().map(|id| TypeId(id.0))
+    }
+}
+
+impl ParamAsm for ResultId {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        a.parse::<Id>().map(|id| ResultId(id.0))
+    }
+}
+
+impl ParamAsm for String {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        match *try!(a.next_token()) {
+            Token::Str(ref s) => Ok(s.clone()),
+            ref t => Err(format!("expected a string, found `{}`", token_text(t)))
+        }
+    }
+}
+
+impl ParamAsm for u32 {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        parse_number(try!(a.next_word()))
+    }
+}
+
+impl ParamAsm for bool {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        a.parse::<u32>().map(|n| n != 0)
+    }
+}
+
+impl<P: ParamAsm> ParamAsm for Option<P> {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        if a.has_tokens() {
+            P::asm(a).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<P: ParamAsm> ParamAsm for Box<[P]> {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        let mut buf = Vec::new();
+        while a.has_tokens() {
+            buf.push(try!(P::asm(a)));
+        }
+        Ok(buf.into_boxed_slice())
+    }
+}
+
+impl ParamAsm for ExecutionMode {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        use desc::ExecutionMode as EMTag;
+        use instruction::ExecutionMode::*;
+
+        let mode : desc::ExecutionMode = try!(a.parse());
+        let mode = match mode {
+            EMTag::Invocations => Invocations(try!(a.parse())),
+            EMTag::SpacingEqual => SpacingEqual,
+            EMTag::SpacingFractionalEven => SpacingFractionalEven,
+            EMTag::SpacingFractionalOdd => SpacingFractionalOdd,
+            EMTag::VertexOrderCw => VertexOrderCw,
+            EMTag::VertexOrderCcw => VertexOrderCcw,
+            EMTag::PixelCenterInteger => PixelCenterInteger,
+            EMTag::OriginUpperLeft => OriginUpperLeft,
+            EMTag::OriginLowerLeft => OriginLowerLeft,
+            EMTag::EarlyFragmentTests => EarlyFragmentTests,
+            EMTag::PointMode => PointMode,
+            EMTag::Xfb => Xfb,
+            EMTag::DepthReplacing => DepthReplacing,
+            EMTag::DepthGreater => DepthGreater,
+            EMTag::DepthLess => DepthLess,
+            EMTag::DepthUnchanged => DepthUnchanged,
+            EMTag::LocalSize => {
+                let x = try!(a.parse());
+                let y = try!(a.parse());
+                let z = try!(a.parse());
+                LocalSize(x, y, z)
+            }
+            EMTag::LocalSizeHint => {
+                let x = try!(a.parse());
+                let y = try!(a.parse());
+                let z = try!(a.parse());
+                LocalSizeHint(x, y, z)
+            }
+            EMTag::InputPoints => InputPoints,
+            EMTag::InputLines => InputLines,
+            EMTag::InputLinesAdjacency => InputLinesAdjacency,
+            EMTag::Triangles => Triangles,
+            EMTag::InputTrianglesAdjacency => InputTrianglesAdjacency,
+            EMTag::Quads => Quads,
+            EMTag::IsoLines => IsoLines,
+            EMTag::OutputVertices => OutputVertices(try!(a.parse())),
+            EMTag::OutputPoints => OutputPoints,
+            EMTag::OutputLineStrip => OutputLineStrip,
+            EMTag::OutputTriangleStrip => OutputTriangleStrip,
+            EMTag::VecTypeHint => VecTypeHint(try!(a.parse())),
+            EMTag::ContractionOff => ContractionOff
+        };
+
+        Ok(mode)
+    }
+}
+
+impl ParamAsm for Decoration {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        use desc::Decoration as D;
+        use instruction::Decoration::*;
+
+        let decoration : desc::Decoration = try!(a.parse());
+        let decoration = match decoration {
+            D::RelaxedPrecision => RelaxedPrecision,
+            D::SpecId => SpecId(try!(a.parse())),
+            D::Block => Block,
+            D::BufferBlock => BufferBlock,
+            D::RowMajor => RowMajor,
+            D::ColMajor => ColMajor,
+            D::ArrayStride => ArrayStride(try!(a.parse())),
+            D::MatrixStride => MatrixStride(try!(a.parse())),
+            D::GLSLShared => GLSLShared,
+            D::GLSLPacked => GLSLPacked,
+            D::CPacked => CPacked,
+            D::BuiltIn => BuiltIn(try!(a.parse())),
+            D::NoPerspective => NoPerspective,
+            D::Flat => Flat,
+            D::Patch => Patch,
+            D::Centroid => Centroid,
+            D::Sample => Sample,
+            D::Invariant => Invariant,
+            D::Restrict => Restrict,
+            D::Aliased => Aliased,
+            D::Volatile => Volatile,
+            D::Constant => Constant,
+            D::Coherent => Coherent,
+            D::NonWritable => NonWritable,
+            D::NonReadable => NonReadable,
+            D::Uniform => Uniform,
+            D::SaturatedConversion => SaturatedConversion,
+            D::Stream => Stream(try!(a.parse())),
+            D::Location => Location(try!(a.parse())),
+            D::Component => Component(try!(a.parse())),
+            D::Index => Index(try!(a.parse())),
+            D::Binding => Binding(try!(a.parse())),
+            D::DescriptorSet => DescriptorSet(try!(a.parse())),
+            D::Offset => Offset(try!(a.parse())),
+            D::XfbBuffer => XfbBuffer(try!(a.parse())),
+            D::XfbStride => XfbStride(try!(a.parse())),
+            D::FuncParamAttr => FuncParamAttr(try!(a.parse())),
+            D::FPRoundingMode => FPRoundingMode(try!(a.parse())),
+            D::FPFastMathMode => FPFastMathMode(try!(a.parse())),
+            D::LinkageAttributes => {
+                let name = try!(a.parse());
+                let ty = try!(a.parse());
+                LinkageAttributes(name, ty)
+            }
+            D::NoContraction => NoContraction,
+            D::InputAttachmentIndex => InputAttachmentIndex(try!(a.parse())),
+            D::Alignment => Alignment(try!(a.parse()))
+        };
+
+        Ok(decoration)
+    }
+}
+
+impl ParamAsm for ImageOperands {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        const OPERANDS : [desc::ImageOperands; 8] = [
+            desc::ImgOpBias, desc::ImgOpLod, desc::ImgOpGrad,
+            desc::ImgOpConstOffset, desc::ImgOpOffset,
+            desc::ImgOpConstOffsets, desc::ImgOpSample,
+            desc::ImgOpMinLod
+        ];
+        let mut operands = ImageOperands::new();
+
+        if a.has_tokens() {
+            let ops = try!(a.parse::<desc::ImageOperands>());
+
+            for &o in OPERANDS.iter() {
+                if ops.contains(o) {
+                    let operand = try!(a.parse::<ValueId>());
+                    operands.set(o, operand);
+                }
+            }
+        }
+
+        Ok(operands)
+    }
+}
+
+macro_rules! impl_param_asm(
+    (enum $($name:ident),+) => (
+        $(impl ParamAsm for ::desc::$name {
+            fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+                let word = try!(a.next_word());
+                desc::$name::from_name(word).ok_or_else(|| {
+                    format!("invalid {} `{}`", stringify!($name), word)
+                })
+            }
+        })+
+    );
+    (bitset $($name:ident),+) => (
+        $(impl ParamAsm for ::desc::$name {
+            fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+                let word = try!(a.next_word());
+                let mut set = desc::$name::empty();
+                if word != "None" {
+                    for flag in word.split('|') {
+                        match desc::$name::from_name(flag) {
+                            Some(flag) => set.insert(flag),
+                            None => {
+                                return Err(format!("invalid {} `{}`", stringify!($name), flag));
+                            }
+                        }
+                    }
+                }
+                Ok(set)
+            }
+        })+
+    );
+);
+
+impl_param_asm!(enum
+    SrcLang,
+    ExecutionModel,
+    AddressingModel,
+    MemoryModel,
+    ExecutionMode,
+    StorageClass,
+    Dim,
+    SamplerAddressingMode,
+    SamplerFilterMode,
+    ImageFormat,
+    ImageChannelOrder,
+    ImageChannelDataType,
+    FPRoundingMode,
+    LinkageType,
+    AccessQualifier,
+    FuncParamAttr,
+    Decoration,
+    BuiltIn,
+    Scope,
+    GroupOperation,
+    KernelEnqueueFlags,
+    Capability
+);
+
+impl_param_asm!(bitset
+    ImageOperands,
+    FPFastMathMode,
+    SelectionControl,
+    LoopControl,
+    FunctionControl,
+    MemoryOrdering,
+    // MemoryAccess, // Handled below
+    KernelProfilingInfo
+);
+
+impl ParamAsm for desc::MemoryAccess {
+    fn asm(a: &mut InstAssembler) -> ::std::result::Result<Self, String> {
+        // The operand is optional, a missing one is an empty set
+        let word = if a.has_tokens() {
+            try!(a.next_word())
+        } else {
+            return Ok(desc::MemoryAccess::empty());
+        };
+
+        let mut set = desc::MemoryAccess::empty();
+        if word != "None" {
+            for flag in word.split('|') {
+                match desc::MemoryAccess::from_name(flag) {
+                    Some(flag) => set.insert(flag),
+                    None => return Err(format!("invalid MemoryAccess `{}`", flag))
+                }
+            }
+        }
+        Ok(set)
+    }
+}
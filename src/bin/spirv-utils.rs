@@ -0,0 +1,352 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Command-line interface to the library.
+//!
+//! Modules are read from the file given, or stdin if there isn't one or
+//! it is `-`. Output goes to the file given with `-o`, or stdout.
+//!
+//! Exit codes are 0 on success, 1 if validation failed or `diff` found
+//! differences, 2 for bad arguments and 3 if a file couldn't be read,
+//! parsed or written.
+
+extern crate spirv_utils;
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use spirv_utils::desc::{Id, StorageClass};
+use spirv_utils::diagnostic::Diagnostic;
+use spirv_utils::disasm::Disassembler;
+use spirv_utils::instruction::{Decoration, Instruction};
+use spirv_utils::parse::InstructionError as ParseInstError;
+use spirv_utils::{asm, diff, stats, transform, validate, RawModule};
+
+const USAGE : &'static str = "\
+Usage: spirv-utils <command> [options] [input]
+
+Commands:
+    dis [--raw]          Disassemble a module. Ids are given names based on
+                         debug info and structure, unless --raw is given.
+    as                   Assemble a module from text
+    reflect              List the entry points and interface variables
+    validate             Check the structure of a module. Instructions that
+                         fail to parse are reported rather than stopping it.
+    strip                Remove debug information
+    stats [--json]       Show what takes up the space in a module
+    diff <old> <new>     Show the differences between two modules
+    opt [passes]         Optimize a module. The passes are run in the
+                         order given: --inline, --fold, --dce, --strip and
+                         --compact. Without any, all but --strip are run.
+                         --compact leaves modules with instructions it
+                         doesn't know, or extended instructions from sets
+                         other than GLSL.std.450 and NonSemantic ones,
+                         unchanged, as it can't tell which of their
+                         operands are ids.
+
+Options:
+    -o <file>            Write the output to the file instead of stdout
+";
+
+fn main() {
+    let args : Vec<String> = env::args().skip(1).collect();
+
+    let code = match run(&args) {
+        Ok(code) => code,
+        Err(Error::Usage(msg)) => {
+            let _ = writeln!(io::stderr(), "spirv-utils: {}\n\n{}", msg, USAGE);
+            2
+        }
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "spirv-utils: {}", e);
+            3
+        }
+    };
+
+    process::exit(code);
+}
+
+/// Errors other than bad arguments are all shown as diagnostics
+enum Error {
+    Usage(String),
+    Diagnostic(Diagnostic)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Usage(ref msg) => f.write_str(msg),
+            Error::Diagnostic(ref d) => fmt::Display::fmt(d, f)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Diagnostic(Diagnostic::new("reading or writing", e.to_string()))
+    }
+}
+
+impl From<spirv_utils::parse::ParseError> for Error {
+    fn from(e: spirv_utils::parse::ParseError) -> Error {
+        Error::Diagnostic(e.diagnostic())
+    }
+}
+
+impl From<asm::AsmError> for Error {
+    fn from(e: asm::AsmError) -> Error {
+        Error::Diagnostic(e.diagnostic())
+    }
+}
+
+/// The arguments after the command
+struct Args {
+    inputs: Vec<String>,
+    output: Option<String>,
+    flags: Vec<String>
+}
+
+impl Args {
+    fn parse(args: &[String], allowed_flags: &[&str]) -> Result<Args, Error> {
+        let mut parsed = Args {
+            inputs: Vec::new(),
+            output: None,
+            flags: Vec::new()
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "-o" {
+                match args.next() {
+                    Some(out) => parsed.output = Some(out.clone()),
+                    None => return Err(Error::Usage("missing file after `-o`".to_owned()))
+                }
+            } else if arg.starts_with("--") {
+                if !allowed_flags.contains(&&arg[..]) {
+                    return Err(Error::Usage(format!("unknown option `{}`", arg)));
+                }
+                parsed.flags.push(arg.clone());
+            } else {
+                parsed.inputs.push(arg.clone());
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    /// Gets the single input, which may be missing for stdin
+    fn input(&self) -> Result<Option<&str>, Error> {
+        match self.inputs.len() {
+            0 => Ok(None),
+            1 if self.inputs[0] == "-" => Ok(None),
+            1 => Ok(Some(&self.inputs[0])),
+            _ => Err(Error::Usage("too many input files".to_owned()))
+        }
+    }
+
+    fn read_module(&self) -> Result<RawModule, Error> {
+        match try!(self.input()) {
+            Some(path) => Ok(try!(RawModule::load_module(path))),
+            None => Ok(try!(RawModule::read_module(io::stdin())))
+        }
+    }
+
+    /// Reads the module, also giving the instructions that failed to parse
+    fn read_module_lenient(&self) -> Result<(RawModule, Vec<ParseInstError>), Error> {
+        match try!(self.input()) {
+            Some(path) => Ok(try!(RawModule::read_module_lenient(try!(File::open(path))))),
+            None => Ok(try!(RawModule::read_module_lenient(io::stdin())))
+        }
+    }
+
+    fn read_text(&self) -> Result<String, Error> {
+        let mut text = String::new();
+        match try!(self.input()) {
+            Some(path) => try!(try!(File::open(path)).read_to_string(&mut text)),
+            None => try!(io::stdin().read_to_string(&mut text))
+        };
+        Ok(text)
+    }
+
+    fn write_output(&self, bytes: &[u8]) -> Result<(), Error> {
+        match self.output {
+            Some(ref path) if path != "-" => try!(try!(File::create(path)).write_all(bytes)),
+            _ => try!(io::stdout().write_all(bytes))
+        }
+        Ok(())
+    }
+
+    fn write_module(&self, module: &RawModule) -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        try!(module.write_module(&mut bytes));
+        self.write_output(&bytes)
+    }
+}
+
+fn run(args: &[String]) -> Result<i32, Error> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (&command[..], args),
+        None => return Err(Error::Usage("missing command".to_owned()))
+    };
+
+    match command {
+        "dis" => {
+            let args = try!(Args::parse(args, &["--raw"]));
+            let module = try!(args.read_module());
+            let d = if args.has_flag("--raw") {
+                Disassembler::new()
+            } else {
+                Disassembler::with_friendly_names(&module)
+            };
+            try!(args.write_output(d.module(&module).as_bytes()));
+        }
+        "as" => {
+            let args = try!(Args::parse(args, &[]));
+            let module = try!(asm::assemble(&try!(args.read_text())));
+            try!(args.write_module(&module));
+        }
+        "reflect" => {
+            let args = try!(Args::parse(args, &[]));
+            let module = try!(args.read_module());
+            try!(args.write_output(reflect(&module).as_bytes()));
+        }
+        "validate" => {
+            let args = try!(Args::parse(args, &[]));
+            let (module, parse_errors) = try!(args.read_module_lenient());
+            for e in &parse_errors {
+                let _ = writeln!(io::stderr(), "{}\n", e.diagnostic().with_module(&module));
+            }
+            let errors = validate::validate(&module);
+            for e in &errors {
+                let _ = writeln!(io::stderr(), "{}\n", e.diagnostic().with_module(&module));
+            }
+            if !parse_errors.is_empty() || !errors.is_empty() {
+                return Ok(1);
+            }
+        }
+        "strip" => {
+            let args = try!(Args::parse(args, &[]));
+            let module = try!(args.read_module());
+            let (stripped, stats) = transform::strip_debug_info(&module);
+            let _ = writeln!(io::stderr(), "Removed {} instructions ({} bytes)",
+                             stats.instructions_removed, stats.words_removed * 4);
+            try!(args.write_module(&stripped));
+        }
+        "stats" => {
+            let args = try!(Args::parse(args, &["--json"]));
+            let module = try!(args.read_module());
+            let stats = stats::module_stats(&module);
+            let text = if args.has_flag("--json") {
+                stats.to_json()
+            } else {
+                stats.to_string()
+            };
+            try!(args.write_output(text.as_bytes()));
+        }
+        "diff" => {
+            let args = try!(Args::parse(args, &[]));
+            if args.inputs.len() != 2 {
+                return Err(Error::Usage("`diff` needs two input files".to_owned()));
+            }
+            let old = try!(RawModule::load_module(&args.inputs[0]));
+            let new = try!(RawModule::load_module(&args.inputs[1]));
+            let diff = diff::diff(&old, &new);
+            try!(args.write_output(diff.to_string().as_bytes()));
+            if !diff.is_empty() {
+                return Ok(1);
+            }
+        }
+        "opt" => {
+            const PASSES : [&'static str; 5] = ["--inline", "--fold", "--dce", "--strip", "--compact"];
+            let args = try!(Args::parse(args, &PASSES));
+            let mut module = try!(args.read_module());
+
+            let passes : Vec<&str> = if args.flags.is_empty() {
+                vec!["--inline", "--fold", "--dce", "--compact"]
+            } else {
+                args.flags.iter().map(|f| &f[..]).collect()
+            };
+
+            for pass in passes {
+                module = match pass {
+                    "--inline" => transform::inline_functions(&module),
+                    "--fold" => transform::fold_constants(&module),
+                    "--dce" => transform::eliminate_dead_code(&module),
+                    "--strip" => transform::strip_debug_info(&module).0,
+                    "--compact" => transform::compact_ids(&module),
+                    _ => unreachable!()
+                };
+            }
+            try!(args.write_module(&module));
+        }
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+        }
+        _ => return Err(Error::Usage(format!("unknown command `{}`", command)))
+    }
+
+    Ok(0)
+}
+
+fn reflect(module: &RawModule) -> String {
+    let d = Disassembler::with_friendly_names(module);
+    let mut out = String::new();
+
+    for inst in module.instructions() {
+        if let Instruction::EntryPoint { execution_model, func, ref name, ref interface } = *inst {
+            out.push_str(&format!("EntryPoint {:?} \"{}\" {}\n",
+                                  execution_model, name, d.id_name(func.into())));
+            for &var in interface.iter() {
+                out.push_str(&format!("    {}\n", d.id_name(var.into())));
+            }
+        }
+    }
+
+    for inst in module.instructions() {
+        if let Instruction::Variable { result_type, result_id, storage_class, .. } = *inst {
+            if storage_class == StorageClass::Function { continue; }
+
+            let pointee = match module.def(result_type) {
+                Some(&Instruction::TypePointer { pointee, .. }) => d.id_name(pointee.into()),
+                _ => d.id_name(result_type.into())
+            };
+
+            out.push_str(&format!("Variable {} {:?} {}", d.id_name(result_id.into()),
+                                  storage_class, pointee));
+            for decoration in decorations(module, result_id.into()) {
+                match *decoration {
+                    Decoration::DescriptorSet(n) => out.push_str(&format!(" set={}", n)),
+                    Decoration::Binding(n) => out.push_str(&format!(" binding={}", n)),
+                    Decoration::Location(n) => out.push_str(&format!(" location={}", n)),
+                    Decoration::Component(n) => out.push_str(&format!(" component={}", n)),
+                    Decoration::BuiltIn(b) => out.push_str(&format!(" builtin={}", b.name())),
+                    _ => ()
+                }
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+fn decorations<'a>(module: &'a RawModule, id: Id) -> Vec<&'a Decoration> {
+    module.uses(id).filter_map(|inst| {
+        match *inst {
+            Instruction::Decorate { target, ref decoration } if target == id => Some(decoration),
+            _ => None
+        }
+    }).collect()
+}
@@ -0,0 +1,374 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Building modules from Rust code.
+//!
+//! `Builder` has a method for every instruction in `core.desc`, named
+//! after the instruction in snake case, such as `i_add` for `OpIAdd`.
+//! Instructions can be added in any order, they are put into the right
+//! section of the module. The ids of results are allocated by the
+//! builder and returned from the methods. Types and constants are only
+//! added once, so asking for the same type again gives the same id. The
+//! exceptions are structs, arrays and pointers, which may need to be
+//! distinct so that each can have its own decorations, and
+//! specialization constants.
+//!
+//! Function-local variables are moved to the start of the function's
+//! first block, as the specification requires.
+
+use std::collections::HashMap;
+
+use desc::{self, Id, TypeId, ValueId};
+use instruction::{Instruction, Decoration, ExecutionMode, ImageOperands};
+use parse::Header;
+use write::encode_instruction;
+use RawModule;
+
+include!(concat!(env!("OUT_DIR"), "/inst_builder.rs"));
+
+/// Builds a module, see the module documentation
+pub struct Builder {
+    version: (u8, u8),
+    generator_id: u32,
+    next_id: u32,
+    capabilities: Vec<Instruction>,
+    extensions: Vec<Instruction>,
+    ext_inst_imports: Vec<Instruction>,
+    memory_model: Vec<Instruction>,
+    entry_points: Vec<Instruction>,
+    execution_modes: Vec<Instruction>,
+    debug_sources: Vec<Instruction>,
+    debug_names: Vec<Instruction>,
+    debug_processed: Vec<Instruction>,
+    annotations: Vec<Instruction>,
+    globals: Vec<Instruction>,
+    functions: Vec<Instruction>,
+    in_function: bool,
+    /// Where the next function-local variable goes, once the function's
+    /// first block has started
+    local_vars: Option<usize>,
+    /// The ids of the types and constants added so far, keyed by their
+    /// encoding with a placeholder result id
+    cache: HashMap<Vec<u32>, Id>
+}
+
+impl Builder {
+    /**
+     * Creates a builder for a SPIR-V 1.0 module
+     */
+    pub fn new() -> Builder {
+        Builder {
+            version: (1, 0),
+            generator_id: 0,
+            next_id: 1,
+            capabilities: Vec::new(),
+            extensions: Vec::new(),
+            ext_inst_imports: Vec::new(),
+            memory_model: Vec::new(),
+            entry_points: Vec::new(),
+            execution_modes: Vec::new(),
+            debug_sources: Vec::new(),
+            debug_names: Vec::new(),
+            debug_processed: Vec::new(),
+            annotations: Vec::new(),
+            globals: Vec::new(),
+            functions: Vec::new(),
+            in_function: false,
+            local_vars: None,
+            cache: HashMap::new()
+        }
+    }
+
+    /**
+     * Sets the version of SPIR-V in the header
+     */
+    pub fn set_version(&mut self, major: u8, minor: u8) {
+        self.version = (major, minor);
+    }
+
+    /**
+     * Sets the generator id in the header
+     */
+    pub fn set_generator(&mut self, generator_id: u32) {
+        self.generator_id = generator_id;
+    }
+
+    /**
+     * Allocates a new id
+     */
+    pub fn alloc_id(&mut self) -> Id {
+        let id = Id(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /**
+     * Gets the id bound of the module so far
+     */
+    pub fn id_bound(&self) -> u32 {
+        self.next_id
+    }
+
+    /**
+     * Adds an instruction to the section of the module it belongs in.
+     * Any ids it defines must have come from `alloc_id`. The instruction
+     * isn't checked against the types and constants already added.
+     */
+    pub fn push(&mut self, inst: Instruction) {
+        use instruction::Instruction::*;
+
+        let section = match inst {
+            Capability { .. } => &mut self.capabilities,
+            Extension { .. } => &mut self.extensions,
+            ExtInstImport { .. } => &mut self.ext_inst_imports,
+            MemoryModel { .. } => &mut self.memory_model,
+            EntryPoint { .. } => &mut self.entry_points,
+            ExecutionMode { .. } => &mut self.execution_modes,
+            SourceContinued { .. } | Source { .. } | SourceExtension { .. } |
+            String { .. } => &mut self.debug_sources,
+            Name { .. } | MemberName { .. } => &mut self.debug_names,
+            ModuleProcessed { .. } => &mut self.debug_processed,
+            Decorate { .. } | MemberDecorate { .. } | DecorationGroup { .. } |
+            GroupDecorate { .. } | GroupMemberDecorate { .. } => &mut self.annotations,
+            Function { .. } => {
+                self.in_function = true;
+                self.local_vars = None;
+                &mut self.functions
+            }
+            FunctionEnd => {
+                self.in_function = false;
+                self.local_vars = None;
+                &mut self.functions
+            }
+            Label { .. } if self.in_function && self.local_vars.is_none() => {
+                self.functions.push(inst);
+                self.local_vars = Some(self.functions.len());
+                return;
+            }
+            Variable { storage_class: desc::StorageClass::Function, .. } if self.in_function => {
+                if let Some(pos) = self.local_vars {
+                    self.functions.insert(pos, inst);
+                    self.local_vars = Some(pos + 1);
+                    return;
+                }
+                &mut self.functions
+            }
+            Variable { .. } => &mut self.globals,
+            _ if inst.defines_type().is_some() || inst.group() == Some("Constant") => {
+                &mut self.globals
+            }
+            _ if self.in_function => &mut self.functions,
+            _ => &mut self.globals
+        };
+
+        section.push(inst);
+    }
+
+    /**
+     * Starts a function, returning its id. Instructions are added to the
+     * function until `end_function` is called.
+     */
+    pub fn begin_function(&mut self, return_type: TypeId, control: desc::FunctionControl,
+                          function_type: TypeId) -> ValueId {
+        self.function(return_type, control, function_type)
+    }
+
+    /**
+     * Ends the current function
+     */
+    pub fn end_function(&mut self) {
+        self.function_end()
+    }
+
+    /**
+     * Starts a new block in the current function, returning its label
+     */
+    pub fn begin_block(&mut self) -> ValueId {
+        self.label()
+    }
+
+    /**
+     * Starts a new block with a label from `alloc_id`, for blocks that
+     * are branched to before they are added
+     */
+    pub fn place_block(&mut self, label: ValueId) {
+        self.push(Instruction::Label { result_id: Id::from(label).to_result_id() });
+    }
+
+    /**
+     * Gets a 32-bit float constant
+     */
+    pub fn constant_f32(&mut self, value: f32) -> ValueId {
+        let ty = self.type_float(32);
+        self.constant(ty, &[value.to_bits()])
+    }
+
+    /**
+     * Gets a 64-bit float constant
+     */
+    pub fn constant_f64(&mut self, value: f64) -> ValueId {
+        let ty = self.type_float(64);
+        let bits = value.to_bits();
+        self.constant(ty, &[bits as u32, (bits >> 32) as u32])
+    }
+
+    /**
+     * Gets a 32-bit signed integer constant
+     */
+    pub fn constant_i32(&mut self, value: i32) -> ValueId {
+        let ty = self.type_int(32, true);
+        self.constant(ty, &[value as u32])
+    }
+
+    /**
+     * Gets a 32-bit unsigned integer constant
+     */
+    pub fn constant_u32(&mut self, value: u32) -> ValueId {
+        let ty = self.type_int(32, false);
+        self.constant(ty, &[value])
+    }
+
+    /**
+     * Gets a boolean constant
+     */
+    pub fn constant_bool(&mut self, value: bool) -> ValueId {
+        let ty = self.type_bool();
+        if value {
+            self.constant_true(ty)
+        } else {
+            self.constant_false(ty)
+        }
+    }
+
+    /**
+     * Finishes the module
+     */
+    pub fn into_module(self) -> RawModule {
+        let header = Header {
+            version: self.version,
+            generator_id: self.generator_id,
+            id_bound: self.next_id
+        };
+
+        let mut instructions = self.capabilities;
+        instructions.extend(self.extensions);
+        instructions.extend(self.ext_inst_imports);
+        instructions.extend(self.memory_model);
+        instructions.extend(self.entry_points);
+        instructions.extend(self.execution_modes);
+        instructions.extend(self.debug_sources);
+        instructions.extend(self.debug_names);
+        instructions.extend(self.debug_processed);
+        instructions.extend(self.annotations);
+        instructions.extend(self.globals);
+        instructions.extend(self.functions);
+
+        RawModule::from_instructions(header, instructions)
+    }
+
+    /**
+     * Finishes the module, encoding it as words
+     */
+    pub fn into_words(self) -> Vec<u32> {
+        self.into_module().to_words()
+    }
+}
+
+/// Gets the key for looking up a type or constant in the cache
+fn cache_key(inst: &Instruction) -> Vec<u32> {
+    let mut words = Vec::new();
+    encode_instruction(inst, &mut words);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Builder;
+    use desc::{self, Id};
+    use instruction::{Decoration, Instruction};
+    use validate::validate;
+    use RawModule;
+
+    #[test]
+    fn build_round_trip() {
+        let mut b = Builder::new();
+        b.capability(desc::Capability::Shader);
+        b.memory_model(desc::AddressingModel::Logical, desc::MemoryModel::GLSL450);
+
+        let void = b.type_void();
+        let fn_ty = b.type_function(void, &[]);
+        let float = b.type_float(32);
+        let ptr = b.type_pointer(desc::StorageClass::Function, float);
+
+        let main = b.begin_function(void, desc::FunctionControl::empty(), fn_ty);
+        b.begin_block();
+        let one = b.constant_f32(1.0);
+        let var = b.variable(ptr, desc::StorageClass::Function, None);
+        b.store(var, one, desc::MemoryAccess::empty());
+        b.ret();
+        b.end_function();
+
+        // Debug instructions are added out of order
+        b.name(main.into(), "main");
+        let file = b.string("main.glsl");
+        b.source(desc::SrcLang::GLSL, 450, Some(file), None);
+        b.entry_point(desc::ExecutionModel::Vertex, main, "main", &[]);
+
+        let words = b.into_words();
+        let module = RawModule::from_words(&words).unwrap();
+        assert!(validate(&module).is_empty());
+        assert_eq!(module.to_words(), words);
+
+        let pos = |op: desc::Op| {
+            module.instructions().iter().position(|i| i.opcode() == op as u16).unwrap()
+        };
+        assert!(pos(desc::Op::String) < pos(desc::Op::Name));
+        assert!(pos(desc::Op::Source) < pos(desc::Op::Name));
+
+        // The variable is moved to the start of the block
+        assert_eq!(pos(desc::Op::Variable), pos(desc::Op::Label) + 1);
+    }
+
+    #[test]
+    fn types_and_constants_are_deduplicated() {
+        let mut b = Builder::new();
+
+        let float = b.type_float(32);
+        assert_eq!(b.type_float(32), float);
+        assert!(b.type_float(64) != float);
+
+        let vec4 = b.type_vector(float, 4);
+        assert_eq!(b.type_vector(float, 4), vec4);
+
+        let one = b.constant_f32(1.0);
+        assert_eq!(b.constant_f32(1.0), one);
+        assert!(b.constant_f32(2.0) != one);
+        assert_eq!(b.constant_u32(4), b.constant_u32(4));
+
+        // Arrays and pointers are distinct, so they can have their own
+        // decorations
+        let len = b.constant_u32(4);
+        let a = b.type_array(float, len);
+        let c = b.type_array(float, len);
+        assert!(a != c);
+        b.decorate(a.into(), Decoration::ArrayStride(4));
+        b.decorate(c.into(), Decoration::ArrayStride(16));
+        assert!(b.type_pointer(desc::StorageClass::Private, float) !=
+                b.type_pointer(desc::StorageClass::Private, float));
+
+        b.memory_model(desc::AddressingModel::Logical, desc::MemoryModel::GLSL450);
+        let module = b.into_module();
+        assert!(validate(&module).is_empty());
+
+        let floats = module.instructions().iter().filter(|i| {
+            if let Instruction::TypeFloat { .. } = **i { true } else { false }
+        }).count();
+        assert_eq!(floats, 2);
+        assert!(module.def(Id::from(a)).is_some());
+    }
+}
@@ -0,0 +1,684 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decoding of the `NonSemantic.Shader.DebugInfo.100` extended
+//! instruction set.
+//!
+//! All operands of non-semantic instructions are ids, so numeric values
+//! such as line numbers and flags are references to `OpConstant`
+//! instructions. `DebugInfoMap` resolves these when answering queries.
+
+use std::collections::HashMap;
+
+use desc::{Id, ValueId};
+use instruction::Instruction;
+use parse::{ParseError, Result};
+use RawModule;
+
+/// Name used to import the instruction set with `OpExtInstImport`
+pub const SET_NAME : &'static str = "NonSemantic.Shader.DebugInfo.100";
+
+struct OperandParser<'a> {
+    operands: &'a [Id]
+}
+
+impl<'a> OperandParser<'a> {
+    fn one(&mut self) -> Result<ValueId> {
+        if self.operands.len() > 0 {
+            let id = self.operands[0];
+            self.operands = &self.operands[1..];
+            Ok(id.to_value_id())
+        } else {
+            Err(ParseError::InstructionTooShort)
+        }
+    }
+
+    fn opt(&mut self) -> Result<Option<ValueId>> {
+        if self.operands.len() > 0 {
+            self.one().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn rest(&mut self) -> Result<Box<[ValueId]>> {
+        let ids : Vec<_> = self.operands.iter().map(|id| id.to_value_id()).collect();
+        self.operands = &[];
+        Ok(ids.into_boxed_slice())
+    }
+
+    fn pairs(&mut self) -> Result<Box<[(ValueId, ValueId)]>> {
+        let mut pairs = Vec::with_capacity(self.operands.len() / 2);
+        while self.operands.len() > 0 {
+            let a = try!(self.one());
+            let b = try!(self.one());
+            pairs.push((a, b));
+        }
+        Ok(pairs.into_boxed_slice())
+    }
+}
+
+macro_rules! def_debug_info {
+    ($($code:expr => $name:ident $({ $($field:ident : $ty:ty = $parse:ident),+ })*),+) => (
+        #[derive(Clone, Debug)]
+        pub enum DebugInfo {
+            $($name $({ $($field: $ty),+ })*,)+
+            Unknown(u32, Box<[ValueId]>)
+        }
+
+        impl DebugInfo {
+            /**
+             * Decode an instruction from this set given the instruction
+             * number and operands of an `OpExtInst`
+             */
+            pub fn parse(instruction: u32, operands: &[Id]) -> Result<DebugInfo> {
+                let mut _p = OperandParser { operands: operands };
+                let inst = match instruction {
+                    $($code => DebugInfo::$name $({ $($field: try!(_p.$parse())),+ })*,)+
+                    _ => {
+                        let ops = try!(_p.rest());
+                        DebugInfo::Unknown(instruction, ops)
+                    }
+                };
+
+                Ok(inst)
+            }
+
+            /**
+             * Gets the instruction number of this instruction
+             */
+            pub fn instruction(&self) -> u32 {
+                match *self {
+                    $(DebugInfo::$name { .. } => $code,)+
+                    DebugInfo::Unknown(n, _) => n
+                }
+            }
+        }
+    )
+}
+
+def_debug_info! {
+    0 => DebugInfoNone,
+    1 => DebugCompilationUnit {
+        version: ValueId = one, dwarf_version: ValueId = one,
+        source: ValueId = one, language: ValueId = one
+    },
+    2 => DebugTypeBasic {
+        name: ValueId = one, size: ValueId = one,
+        encoding: ValueId = one, flags: ValueId = one
+    },
+    3 => DebugTypePointer {
+        base_type: ValueId = one, storage_class: ValueId = one, flags: ValueId = one
+    },
+    4 => DebugTypeQualifier {
+        base_type: ValueId = one, qualifier: ValueId = one
+    },
+    5 => DebugTypeArray {
+        base_type: ValueId = one, counts: Box<[ValueId]> = rest
+    },
+    6 => DebugTypeVector {
+        base_type: ValueId = one, count: ValueId = one
+    },
+    7 => DebugTypedef {
+        name: ValueId = one, base_type: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one
+    },
+    8 => DebugTypeFunction {
+        flags: ValueId = one, return_type: ValueId = one,
+        params: Box<[ValueId]> = rest
+    },
+    9 => DebugTypeEnum {
+        name: ValueId = one, underlying_type: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one,
+        size: ValueId = one, flags: ValueId = one,
+        enumerators: Box<[(ValueId, ValueId)]> = pairs
+    },
+    10 => DebugTypeComposite {
+        name: ValueId = one, tag: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one,
+        linkage_name: ValueId = one, size: ValueId = one, flags: ValueId = one,
+        members: Box<[ValueId]> = rest
+    },
+    11 => DebugTypeMember {
+        name: ValueId = one, ty: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, offset: ValueId = one,
+        size: ValueId = one, flags: ValueId = one, value: Option<ValueId> = opt
+    },
+    12 => DebugTypeInheritance {
+        parent: ValueId = one, offset: ValueId = one,
+        size: ValueId = one, flags: ValueId = one
+    },
+    13 => DebugTypePtrToMember {
+        member_type: ValueId = one, parent: ValueId = one
+    },
+    14 => DebugTypeTemplate {
+        target: ValueId = one, params: Box<[ValueId]> = rest
+    },
+    15 => DebugTypeTemplateParameter {
+        name: ValueId = one, actual_type: ValueId = one, value: ValueId = one,
+        source: ValueId = one, line: ValueId = one, column: ValueId = one
+    },
+    16 => DebugTypeTemplateTemplateParameter {
+        name: ValueId = one, template_name: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one
+    },
+    17 => DebugTypeTemplateParameterPack {
+        name: ValueId = one, source: ValueId = one, line: ValueId = one,
+        column: ValueId = one, params: Box<[ValueId]> = rest
+    },
+    18 => DebugGlobalVariable {
+        name: ValueId = one, ty: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one,
+        linkage_name: ValueId = one, variable: ValueId = one, flags: ValueId = one,
+        static_member: Option<ValueId> = opt
+    },
+    19 => DebugFunctionDeclaration {
+        name: ValueId = one, ty: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one,
+        linkage_name: ValueId = one, flags: ValueId = one
+    },
+    20 => DebugFunction {
+        name: ValueId = one, ty: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one,
+        linkage_name: ValueId = one, flags: ValueId = one,
+        scope_line: ValueId = one, declaration: Option<ValueId> = opt
+    },
+    21 => DebugLexicalBlock {
+        source: ValueId = one, line: ValueId = one, column: ValueId = one,
+        parent: ValueId = one, name: Option<ValueId> = opt
+    },
+    22 => DebugLexicalBlockDiscriminator {
+        source: ValueId = one, discriminator: ValueId = one, parent: ValueId = one
+    },
+    23 => DebugScope {
+        scope: ValueId = one, inlined_at: Option<ValueId> = opt
+    },
+    24 => DebugNoScope,
+    25 => DebugInlinedAt {
+        line: ValueId = one, scope: ValueId = one, inlined: Option<ValueId> = opt
+    },
+    26 => DebugLocalVariable {
+        name: ValueId = one, ty: ValueId = one, source: ValueId = one,
+        line: ValueId = one, column: ValueId = one, parent: ValueId = one,
+        flags: ValueId = one, arg_number: Option<ValueId> = opt
+    },
+    27 => DebugInlinedVariable {
+        variable: ValueId = one, inlined: ValueId = one
+    },
+    28 => DebugDeclare {
+        local_variable: ValueId = one, variable: ValueId = one,
+        expression: ValueId = one, indexes: Box<[ValueId]> = rest
+    },
+    29 => DebugValue {
+        local_variable: ValueId = one, value: ValueId = one,
+        expression: ValueId = one, indexes: Box<[ValueId]> = rest
+    },
+    30 => DebugOperation {
+        operation: ValueId = one, operands: Box<[ValueId]> = rest
+    },
+    31 => DebugExpression {
+        operations: Box<[ValueId]> = rest
+    },
+    32 => DebugMacroDef {
+        source: ValueId = one, line: ValueId = one,
+        name: ValueId = one, value: Option<ValueId> = opt
+    },
+    33 => DebugMacroUndef {
+        source: ValueId = one, line: ValueId = one, def: ValueId = one
+    },
+    34 => DebugImportedEntity {
+        name: ValueId = one, tag: ValueId = one, source: ValueId = one,
+        entity: ValueId = one, line: ValueId = one, column: ValueId = one,
+        parent: ValueId = one
+    },
+    35 => DebugSource {
+        file: ValueId = one, text: Option<ValueId> = opt
+    },
+    101 => DebugFunctionDefinition {
+        function: ValueId = one, definition: ValueId = one
+    },
+    102 => DebugSourceContinued {
+        text: ValueId = one
+    },
+    103 => DebugLine {
+        source: ValueId = one, line_start: ValueId = one, line_end: ValueId = one,
+        column_start: ValueId = one, column_end: ValueId = one
+    },
+    104 => DebugNoLine,
+    105 => DebugBuildIdentifier {
+        identifier: ValueId = one, flags: ValueId = one
+    },
+    106 => DebugStoragePath {
+        path: ValueId = one
+    },
+    107 => DebugEntryPoint {
+        entry_point: ValueId = one, compilation_unit: ValueId = one,
+        signature: ValueId = one, args: ValueId = one
+    },
+    108 => DebugTypeMatrix {
+        vector_type: ValueId = one, count: ValueId = one, column_major: ValueId = one
+    }
+}
+
+impl DebugInfo {
+    /**
+     * Decode the given instruction if it is an `OpExtInst` from the
+     * instruction set imported as `set`
+     */
+    pub fn decode(inst: &Instruction, set: ValueId) -> Option<Result<DebugInfo>> {
+        if let Instruction::ExtInst { set: s, instruction, ref operands, .. } = *inst {
+            if s == set {
+                return Some(DebugInfo::parse(instruction, operands));
+            }
+        }
+        None
+    }
+
+    /**
+     * Gets the id of the scope this entry is nested in, if any
+     */
+    pub fn parent(&self) -> Option<ValueId> {
+        use self::DebugInfo::*;
+        match *self {
+            DebugTypedef { parent, .. } |
+            DebugTypeEnum { parent, .. } |
+            DebugTypeComposite { parent, .. } |
+            DebugGlobalVariable { parent, .. } |
+            DebugFunctionDeclaration { parent, .. } |
+            DebugFunction { parent, .. } |
+            DebugLexicalBlock { parent, .. } |
+            DebugLexicalBlockDiscriminator { parent, .. } |
+            DebugLocalVariable { parent, .. } |
+            DebugImportedEntity { parent, .. } => Some(parent),
+            _ => None
+        }
+    }
+
+    /**
+     * Gets the id of the `OpString` holding the name of this entry, if
+     * any
+     */
+    pub fn name(&self) -> Option<ValueId> {
+        use self::DebugInfo::*;
+        match *self {
+            DebugTypeBasic { name, .. } |
+            DebugTypedef { name, .. } |
+            DebugTypeEnum { name, .. } |
+            DebugTypeComposite { name, .. } |
+            DebugTypeMember { name, .. } |
+            DebugTypeTemplateParameter { name, .. } |
+            DebugTypeTemplateTemplateParameter { name, .. } |
+            DebugTypeTemplateParameterPack { name, .. } |
+            DebugGlobalVariable { name, .. } |
+            DebugFunctionDeclaration { name, .. } |
+            DebugFunction { name, .. } |
+            DebugLocalVariable { name, .. } |
+            DebugMacroDef { name, .. } |
+            DebugImportedEntity { name, .. } => Some(name),
+            DebugLexicalBlock { name, .. } => name,
+            _ => None
+        }
+    }
+
+    /**
+     * Returns true if this entry introduces a scope
+     */
+    pub fn is_scope(&self) -> bool {
+        use self::DebugInfo::*;
+        match *self {
+            DebugCompilationUnit { .. } |
+            DebugTypeComposite { .. } |
+            DebugFunction { .. } |
+            DebugLexicalBlock { .. } |
+            DebugLexicalBlockDiscriminator { .. } => true,
+            _ => false
+        }
+    }
+}
+
+/**
+ * Decoded debug information for a module, indexed for lookups by id.
+ */
+pub struct DebugInfoMap<'a> {
+    module: &'a RawModule,
+    entries: HashMap<ValueId, DebugInfo>,
+    variables: HashMap<ValueId, ValueId>
+}
+
+impl<'a> DebugInfoMap<'a> {
+    /**
+     * Decode all the debug information in the module. If the module
+     * does not import the instruction set, the map is empty.
+     */
+    pub fn new(module: &'a RawModule) -> Result<DebugInfoMap<'a>> {
+        let mut entries = HashMap::new();
+        let mut variables = HashMap::new();
+
+        if let Some(set) = find_import(module) {
+            for inst in module.instructions() {
+                let info = match DebugInfo::decode(inst, set) {
+                    Some(info) => try!(info),
+                    None => continue
+                };
+
+                match info {
+                    DebugInfo::DebugDeclare { local_variable, variable, .. } => {
+                        variables.insert(variable, local_variable);
+                    }
+                    DebugInfo::DebugValue { local_variable, value, .. } => {
+                        variables.insert(value, local_variable);
+                    }
+                    DebugInfo::DebugGlobalVariable { variable, .. } => {
+                        if let Some(result_id) = inst.defines_value() {
+                            variables.insert(variable, result_id);
+                        }
+                    }
+                    _ => ()
+                }
+
+                if let Some(result_id) = inst.defines_value() {
+                    entries.insert(result_id, info);
+                }
+            }
+        }
+
+        Ok(DebugInfoMap {
+            module: module,
+            entries: entries,
+            variables: variables
+        })
+    }
+
+    /**
+     * Gets the decoded instruction with the given result id
+     */
+    pub fn get(&self, id: ValueId) -> Option<&DebugInfo> {
+        self.entries.get(&id)
+    }
+
+    /**
+     * Gets the id of the `DebugLocalVariable` or `DebugGlobalVariable`
+     * describing the given SSA value or variable
+     */
+    pub fn variable(&self, value: ValueId) -> Option<ValueId> {
+        self.variables.get(&value).map(|&v| {
+            // Look through inlined variables to the original declaration
+            match self.get(v) {
+                Some(&DebugInfo::DebugInlinedVariable { variable, .. }) => variable,
+                _ => v
+            }
+        })
+    }
+
+    /**
+     * Gets the source-level name of the given SSA value or variable
+     */
+    pub fn variable_name(&self, value: ValueId) -> Option<&'a str> {
+        self.variable(value).and_then(|var| self.name(var))
+    }
+
+    /**
+     * Gets the id of the scope the given SSA value or variable was
+     * declared in
+     */
+    pub fn variable_scope(&self, value: ValueId) -> Option<ValueId> {
+        self.variable(value).and_then(|var| self.parent(var))
+    }
+
+    /**
+     * Gets the name of the given entry, if it has one
+     */
+    pub fn name(&self, id: ValueId) -> Option<&'a str> {
+        self.get(id).and_then(|info| info.name()).and_then(|name| self.string(name))
+    }
+
+    /**
+     * Gets the id of the scope the given entry is nested in
+     */
+    pub fn parent(&self, id: ValueId) -> Option<ValueId> {
+        self.get(id).and_then(|info| info.parent())
+    }
+
+    /**
+     * Returns an iterator over the given scope and all the scopes it is
+     * nested in, innermost first
+     */
+    pub fn scopes<'b>(&'b self, scope: ValueId) -> Scopes<'b, 'a> {
+        Scopes {
+            map: self,
+            next: Some(scope),
+            remaining: self.entries.len()
+        }
+    }
+
+    /**
+     * Gets the contents of the `OpString` with the given id
+     */
+    pub fn string(&self, id: ValueId) -> Option<&'a str> {
+        match self.module.def(id) {
+            Some(&Instruction::String { ref string, .. }) => Some(&string[..]),
+            _ => None
+        }
+    }
+
+    /**
+     * Gets the value of the 32-bit integer constant with the given id,
+     * as used for line numbers, flags and other numeric operands
+     */
+    pub fn constant(&self, id: ValueId) -> Option<u32> {
+        match self.module.def(id) {
+            Some(&Instruction::Constant { ref val, .. }) if val.len() == 1 => Some(val[0]),
+            _ => None
+        }
+    }
+}
+
+pub struct Scopes<'a, 'b: 'a> {
+    map: &'a DebugInfoMap<'b>,
+    next: Option<ValueId>,
+    remaining: usize
+}
+
+impl<'a, 'b> Iterator for Scopes<'a, 'b> {
+    type Item = ValueId;
+
+    fn next(&mut self) -> Option<ValueId> {
+        let scope = match self.next {
+            Some(scope) => scope,
+            None => return None
+        };
+
+        // Guard against malformed modules with cycles in the scope
+        // chain, no valid chain can be longer than the number of entries
+        if self.remaining == 0 {
+            self.next = None;
+        } else {
+            self.remaining -= 1;
+            self.next = self.map.parent(scope);
+        }
+
+        Some(scope)
+    }
+}
+
+/**
+ * Gets the id the debug information instruction set is imported as in
+ * the module, if any
+ */
+pub fn find_import(module: &RawModule) -> Option<ValueId> {
+    module.instructions().iter().filter_map(|inst| {
+        if let Instruction::ExtInstImport { result_id, ref name } = *inst {
+            if &name[..] == SET_NAME {
+                return Some(result_id.to_value_id());
+            }
+        }
+        None
+    }).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc::{Id, ValueId};
+    use instruction::Instruction;
+    use parse::ParseError;
+    use RawModule;
+
+    use super::{DebugInfo, DebugInfoMap};
+
+    const MODULE : &'static str = "
+        OpCapability Shader
+        OpExtension \"SPV_KHR_non_semantic_info\"
+        %debug = OpExtInstImport \"NonSemantic.Shader.DebugInfo.100\"
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Fragment %main \"main\"
+        %file = OpString \"shader.frag\"
+        %main_name = OpString \"main\"
+        %float_name = OpString \"float\"
+        %x_name = OpString \"x\"
+        %g_name = OpString \"g\"
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %uint = OpTypeInt 32 0
+        %ptr_fn = OpTypePointer Function %float
+        %ptr_private = OpTypePointer Private %float
+        %u0 = OpConstant %uint 0
+        %u1 = OpConstant %uint 1
+        %u2 = OpConstant %uint 2
+        %u3 = OpConstant %uint 3
+        %u4 = OpConstant %uint 4
+        %u32 = OpConstant %uint 32
+        %g = OpVariable %ptr_private Private
+        %none = OpExtInst %void %debug 0
+        %expr = OpExtInst %void %debug 31
+        %src = OpExtInst %void %debug 35 %file
+        %cu = OpExtInst %void %debug 1 %u1 %u4 %src %u2
+        %dfloat = OpExtInst %void %debug 2 %float_name %u32 %u3 %u0
+        %dfn = OpExtInst %void %debug 8 %u0 %none
+        %dmain = OpExtInst %void %debug 20 %main_name %dfn %src %u1 %u1 %cu %main_name %u0 %u1
+        %block = OpExtInst %void %debug 21 %src %u2 %u1 %dmain
+        %dx = OpExtInst %void %debug 26 %x_name %dfloat %src %u3 %u1 %block %u0
+        %dg = OpExtInst %void %debug 18 %g_name %dfloat %src %u1 %u1 %cu %g_name %g %u0
+        %at = OpExtInst %void %debug 25 %u4 %dmain
+        %dxi = OpExtInst %void %debug 27 %dx %at
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        %x = OpVariable %ptr_fn Function
+        %decl = OpExtInst %void %debug 28 %dx %x %expr
+        %y = OpLoad %float %x
+        %value = OpExtInst %void %debug 29 %dxi %y %expr
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    /// Gets the result id of the first debug info instruction with the
+    /// given instruction number
+    fn entry(module: &RawModule, number: u32) -> ValueId {
+        module.instructions().iter().filter_map(|inst| match *inst {
+            Instruction::ExtInst { result_id, instruction, .. } if instruction == number => {
+                Some(result_id.to_value_id())
+            }
+            _ => None
+        }).next().unwrap()
+    }
+
+    /// Gets the result id of the `n`th instruction with the given opcode
+    fn nth(module: &RawModule, op: ::desc::Op, n: usize) -> ValueId {
+        module.instructions().iter().filter(|inst| inst.opcode() == op as u16)
+            .nth(n).and_then(|inst| inst.defines_value()).unwrap()
+    }
+
+    #[test]
+    fn decode() {
+        let module = assemble(MODULE).unwrap();
+        let map = DebugInfoMap::new(&module).unwrap();
+
+        let src = entry(&module, 35);
+        match *map.get(entry(&module, 1)).unwrap() {
+            DebugInfo::DebugCompilationUnit { source, .. } => assert_eq!(source, src),
+            ref info => panic!("unexpected {:?}", info)
+        }
+        match *map.get(entry(&module, 20)).unwrap() {
+            DebugInfo::DebugFunction { line, declaration, .. } => {
+                assert_eq!(map.constant(line), Some(1));
+                assert_eq!(declaration, None);
+            }
+            ref info => panic!("unexpected {:?}", info)
+        }
+        match *map.get(src).unwrap() {
+            DebugInfo::DebugSource { file, text } => {
+                assert_eq!(map.string(file), Some("shader.frag"));
+                assert_eq!(text, None);
+            }
+            ref info => panic!("unexpected {:?}", info)
+        }
+
+        assert_eq!(map.name(entry(&module, 2)), Some("float"));
+        assert_eq!(map.name(entry(&module, 21)), None);
+        assert!(map.get(entry(&module, 21)).unwrap().is_scope());
+        assert!(!map.get(entry(&module, 26)).unwrap().is_scope());
+
+        // Instructions this set doesn't know are kept, ones missing
+        // operands fail
+        match DebugInfo::parse(1000, &[Id(1), Id(2)]).unwrap() {
+            DebugInfo::Unknown(1000, ref ops) => assert_eq!(ops[..], [ValueId(1), ValueId(2)]),
+            info => panic!("unexpected {:?}", info)
+        }
+        match DebugInfo::parse(35, &[]) {
+            Err(ParseError::InstructionTooShort) => (),
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+
+    #[test]
+    fn variables() {
+        let module = assemble(MODULE).unwrap();
+        let map = DebugInfoMap::new(&module).unwrap();
+
+        let g = nth(&module, ::desc::Op::Variable, 0);
+        let x = nth(&module, ::desc::Op::Variable, 1);
+        let y = nth(&module, ::desc::Op::Load, 0);
+        let cu = entry(&module, 1);
+        let block = entry(&module, 21);
+        let dx = entry(&module, 26);
+
+        assert_eq!(map.variable(x), Some(dx));
+        assert_eq!(map.variable_name(x), Some("x"));
+        assert_eq!(map.variable_scope(x), Some(block));
+
+        // The value is described by an inlined copy of the variable
+        assert_eq!(map.variable(y), Some(dx));
+        assert_eq!(map.variable_name(y), Some("x"));
+
+        assert_eq!(map.variable(g), Some(entry(&module, 18)));
+        assert_eq!(map.variable_name(g), Some("g"));
+        assert_eq!(map.variable_scope(g), Some(cu));
+
+        assert_eq!(map.variable(nth(&module, ::desc::Op::Constant, 0)), None);
+    }
+
+    #[test]
+    fn scopes() {
+        let module = assemble(MODULE).unwrap();
+        let map = DebugInfoMap::new(&module).unwrap();
+
+        let block = entry(&module, 21);
+        let scopes : Vec<_> = map.scopes(block).collect();
+        assert_eq!(scopes, [block, entry(&module, 20), entry(&module, 1)]);
+    }
+
+    #[test]
+    fn without_import() {
+        let module = assemble(&MODULE.replace("NonSemantic.Shader.DebugInfo.100",
+                                              "NonSemantic.Other")).unwrap();
+        let map = DebugInfoMap::new(&module).unwrap();
+        assert!(map.get(entry(&module, 1)).is_none());
+        assert_eq!(map.variable(nth(&module, ::desc::Op::Variable, 1)), None);
+    }
+}
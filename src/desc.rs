@@ -12,15 +12,19 @@
 use std::fmt;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Id(pub u32);
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct TypeId(pub u32);
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct ValueId(pub u32);
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct ResultId(pub u32);
 
 impl From<TypeId> for Id {
@@ -110,7 +114,7 @@ macro_rules! def_enum {
     );
     (enum $en:ident : $repr:ident { $($name:ident = $code:expr),+ }) => (
         #[repr($repr)]
-        #[derive(Copy, Clone, Debug, PartialEq, Hash)]
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
         pub enum $en {
             $($name = $code,)+
         }
@@ -122,12 +126,45 @@ macro_rules! def_enum {
                     _ => None
                 }
             }
+
+            /// Gets the name of the value, as used in the specification
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $($en::$name => stringify!($name).trim_start_matches('_'),)+
+                }
+            }
+
+            /// Gets a value from its name in the specification
+            pub fn from_name(name: &str) -> Option<$en> {
+                $(if stringify!($name).trim_start_matches('_') == name {
+                    return Some($en::$name);
+                })+
+                None
+            }
+        }
+
+        // Values are serialized by their name in the specification
+        #[cfg(feature = "serde")]
+        impl ::serde::Serialize for $en {
+            fn serialize<S: ::serde::Serializer>(&self, s: S) -> ::std::result::Result<S::Ok, S::Error> {
+                s.serialize_str(self.name())
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> ::serde::Deserialize<'de> for $en {
+            fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> ::std::result::Result<$en, D::Error> {
+                let name : String = try!(::serde::Deserialize::deserialize(d));
+                $en::from_name(&name).ok_or_else(|| {
+                    ::serde::de::Error::custom(format!("unknown {} `{}`", stringify!($en), name))
+                })
+            }
         }
     )
 }
 
 macro_rules! def_bitset {
-    ($setname:ident { $($name:ident = $code:expr),+ }) => (
+    ($setname:ident prefix $prefix:expr, { $($name:ident = $code:expr),+ }) => (
         #[derive(Copy, Clone, PartialEq, Hash)]
         pub struct $setname(u32);
 
@@ -167,6 +204,50 @@ macro_rules! def_bitset {
             pub fn count(&self) -> u32 {
                 self.0.count_ones()
             }
+
+            /// Gets the names of the flags in the set, as used in the
+            /// specification
+            pub fn names(&self) -> Vec<&'static str> {
+                let mut names = Vec::new();
+                $(if self.contains($name) {
+                    names.push(&stringify!($name)[$prefix.len()..]);
+                })+
+                names
+            }
+
+            /// Gets a flag from its name in the specification
+            pub fn from_name(name: &str) -> Option<$setname> {
+                $(if &stringify!($name)[$prefix.len()..] == name {
+                    return Some($name);
+                })+
+                None
+            }
+        }
+
+        // Sets are serialized as a list of the names of their flags
+        #[cfg(feature = "serde")]
+        impl ::serde::Serialize for $setname {
+            fn serialize<S: ::serde::Serializer>(&self, s: S) -> ::std::result::Result<S::Ok, S::Error> {
+                ::serde::Serialize::serialize(&self.names(), s)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> ::serde::Deserialize<'de> for $setname {
+            fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> ::std::result::Result<$setname, D::Error> {
+                let names : Vec<String> = try!(::serde::Deserialize::deserialize(d));
+                let mut set = $setname::empty();
+                for name in names {
+                    match $setname::from_name(&name) {
+                        Some(flag) => set.insert(flag),
+                        None => {
+                            return Err(::serde::de::Error::custom(
+                                format!("unknown {} `{}`", stringify!($setname), name)));
+                        }
+                    }
+                }
+                Ok(set)
+            }
         }
 
         impl From<u32> for $setname {
@@ -385,7 +466,7 @@ def_enum!(enum ImageChannelDataType {
     UnormInt101010_2 = 16
 });
 
-def_bitset!(ImageOperands {
+def_bitset!(ImageOperands prefix "ImgOp", {
     ImgOpBias         = 0x01,
     ImgOpLod          = 0x02,
     ImgOpGrad         = 0x04,
@@ -396,7 +477,7 @@ def_bitset!(ImageOperands {
     ImgOpMinLod       = 0x80
 });
 
-def_bitset!(FPFastMathMode {
+def_bitset!(FPFastMathMode prefix "FastMath", {
     FastMathNotNaN     = 0x01,
     FastMathNotInf     = 0x02,
     FastMathNSZ        = 0x04,
@@ -523,24 +604,24 @@ def_enum!(enum BuiltIn {
     InstanceIndex = 43
 });
 
-def_bitset!(SelectionControl {
+def_bitset!(SelectionControl prefix "SelCtl", {
     SelCtlFlatten     = 0x01,
     SelCtlDontFlatten = 0x02
 });
 
-def_bitset!(LoopControl {
+def_bitset!(LoopControl prefix "LoopCtl", {
     LoopCtlUnroll     = 0x01,
     LoopCtlDontUnroll = 0x02
 });
 
-def_bitset!(FunctionControl {
+def_bitset!(FunctionControl prefix "FnCtl", {
     FnCtlInline     = 0x01,
     FnCtlDontInline = 0x02,
     FnCtlPure       = 0x04,
     FnCtlConst      = 0x08
 });
 
-def_bitset!(MemoryOrdering {
+def_bitset!(MemoryOrdering prefix "MemOrd", {
     MemOrdAcquire                = 0x002,
     MemOrdRelease                = 0x004,
     MemOrdAcquireRelease         = 0x008,
@@ -553,7 +634,7 @@ def_bitset!(MemoryOrdering {
     MemOrdImageMemory            = 0x800
 });
 
-def_bitset!(MemoryAccess {
+def_bitset!(MemoryAccess prefix "MemAcc", {
     MemAccVolatile    = 0x01,
     MemAccAligned     = 0x02,
     MemAccNontemporal = 0x04
@@ -579,7 +660,7 @@ def_enum!(enum KernelEnqueueFlags {
     WaitWorkGroup = 2
 });
 
-def_bitset!(KernelProfilingInfo {
+def_bitset!(KernelProfilingInfo prefix "ProfInfo", {
     ProfInfoCmdExecTime = 0x01
 });
 
@@ -936,5 +1017,47 @@ def_enum!(enum Op : u16 {
     NoLine = 317,
     AtomicFlagTestAndSet = 318,
     AtomicFlagClear = 319,
-    ImageSparseRead = 320
+    ImageSparseRead = 320,
+    ModuleProcessed = 330
 });
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use serde_test::{assert_de_tokens_error, assert_ser_tokens, assert_tokens, Token};
+
+    use instruction::Decoration;
+
+    use super::*;
+
+    #[test]
+    fn serde_enums() {
+        assert_tokens(&StorageClass::Function, &[Token::Str("Function")]);
+        assert_tokens(&BuiltIn::Position, &[Token::Str("Position")]);
+        assert_de_tokens_error::<StorageClass>(&[Token::Str("Nowhere")],
+                                               "unknown StorageClass `Nowhere`");
+
+        // Enums inside other operands are also given by name
+        assert_ser_tokens(&Decoration::BuiltIn(BuiltIn::Position), &[
+            Token::NewtypeVariant { name: "Decoration", variant: "BuiltIn" },
+            Token::Str("Position")
+        ]);
+    }
+
+    #[test]
+    fn serde_bitsets() {
+        let mut set = MemAccVolatile;
+        set.insert(MemAccNontemporal);
+        assert_tokens(&set, &[
+            Token::Seq { len: Some(2) },
+            Token::Str("Volatile"),
+            Token::Str("Nontemporal"),
+            Token::SeqEnd
+        ]);
+        assert_tokens(&MemoryAccess::empty(), &[Token::Seq { len: Some(0) }, Token::SeqEnd]);
+        assert_de_tokens_error::<MemoryAccess>(&[
+            Token::Seq { len: Some(1) },
+            Token::Str("Sometimes"),
+            Token::SeqEnd
+        ], "unknown MemoryAccess `Sometimes`");
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+//! A common form for the errors found by the parser, the assembler, the
+//! validator and the linker.
+//!
+//! Each error type has a `diagnostic` method giving a `Diagnostic` with
+//! whatever context the error has. If the module is available, more of
+//! the context can be filled in with `Diagnostic::with_module`, which
+//! also renders the disassembly around the instruction.
+
+use std::fmt;
+
+use desc;
+use disasm::Disassembler;
+use instruction::operand_names;
+use write::encode_instruction;
+use RawModule;
+
+/// How many instructions are shown either side of the one with the error
+const SNIPPET_CONTEXT : usize = 2;
+
+/// An error, with as much context about where it is as is known
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// What was being done when the error was found, such as "parsing"
+    pub stage: &'static str,
+    /// The error itself, without any of the context
+    pub message: String,
+    /// The index of the instruction in the module
+    pub index: Option<usize>,
+    /// The offset in words of the instruction from the start of the module
+    pub offset: Option<usize>,
+    /// The line in the text being assembled, starting from 1
+    pub line: Option<usize>,
+    pub opcode: Option<u16>,
+    /// The index of the operand in `core.desc`
+    pub operand: Option<usize>,
+    /// The disassembly of the instructions around the one with the
+    /// error, which is marked with `>`
+    pub snippet: Option<String>
+}
+
+impl Diagnostic {
+    /**
+     * Creates a diagnostic without any context
+     */
+    pub fn new(stage: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            stage: stage,
+            message: message,
+            index: None,
+            offset: None,
+            line: None,
+            opcode: None,
+            operand: None,
+            snippet: None
+        }
+    }
+
+    /**
+     * Gets the name of the opcode, such as `OpDecorate`, if it is known
+     */
+    pub fn opcode_name(&self) -> Option<String> {
+        self.opcode.and_then(desc::Op::from).map(|op| format!("Op{}", op.name()))
+    }
+
+    /**
+     * Gets the name of the operand in `core.desc`, if it is known
+     */
+    pub fn operand_name(&self) -> Option<&'static str> {
+        match (self.opcode, self.operand) {
+            (Some(opcode), Some(operand)) => operand_names(opcode).get(operand).cloned(),
+            _ => None
+        }
+    }
+
+    /**
+     * Fills in the context that is missing from the module the error is
+     * in. The index and offset of the instruction are each found from
+     * the other, and the opcode and snippet from the instruction.
+     */
+    pub fn with_module(mut self, module: &RawModule) -> Diagnostic {
+        let insts = module.instructions();
+
+        let mut offset = 5;
+        let mut words = Vec::new();
+        for (idx, inst) in insts.iter().enumerate() {
+            if self.index == Some(idx) || self.offset == Some(offset) {
+                self.index = Some(idx);
+                self.offset = Some(offset);
+                break;
+            }
+            words.clear();
+            encode_instruction(inst, &mut words);
+            offset += words.len();
+        }
+
+        let index = match self.index {
+            Some(index) if index < insts.len() => index,
+            _ => return self
+        };
+        if self.opcode.is_none() {
+            self.opcode = Some(insts[index].opcode());
+        }
+
+        let d = Disassembler::with_friendly_names(module);
+        let start = index.saturating_sub(SNIPPET_CONTEXT);
+        let end = ::std::cmp::min(index + SNIPPET_CONTEXT + 1, insts.len());
+        let width = (end - 1).to_string().len();
+
+        let mut snippet = String::new();
+        for idx in start..end {
+            let marker = if idx == index { '>' } else { ' ' };
+            snippet.push_str(&format!("{} {:>width$} | {}\n", marker, idx,
+                                      d.instruction(&insts[idx]), width=width));
+        }
+        self.snippet = Some(snippet);
+
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Error while {}: {}", self.stage, self.message));
+
+        let mut location = Vec::new();
+        if let Some(line) = self.line {
+            location.push(format!("line {}", line));
+        }
+        if let Some(index) = self.index {
+            location.push(format!("instruction {}", index));
+        }
+        if let Some(offset) = self.offset {
+            location.push(format!("word {}", offset));
+        }
+        match (self.operand_name(), self.operand, self.opcode_name()) {
+            (Some(name), _, Some(op)) => location.push(format!("operand `{}` of {}", name, op)),
+            (None, Some(operand), Some(op)) => location.push(format!("operand {} of {}", operand, op)),
+            (_, _, Some(op)) => location.push(op),
+            (_, _, None) => if let Some(opcode) = self.opcode {
+                location.push(format!("opcode {}", opcode));
+            }
+        }
+        if !location.is_empty() {
+            try!(write!(f, "\n  --> {}", location.join(", ")));
+        }
+
+        if let Some(ref snippet) = self.snippet {
+            try!(write!(f, "\n{}", snippet.trim_end()));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,564 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Semantic differences between modules.
+//!
+//! Items in the two modules (functions, global variables, types,
+//! constants and the instructions at the start of the module) are
+//! matched up by their names. Ids are named using
+//! `Disassembler::with_friendly_names`, so types and constants are
+//! matched by their structure, named items by their `Name` and unnamed
+//! functions by their entry point or position. Entry points are matched
+//! by their literal name, and ids local to a function are numbered within
+//! the function, e.g. `%_1`. Items that
+//! can't be matched by name are matched by their content, which finds
+//! renamed items. Debug information is ignored, as is the numbering of
+//! ids.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use desc::Id;
+use disasm::Disassembler;
+use instruction::Instruction;
+use transform::strip_debug_info;
+use RawModule;
+
+/// The kind of an item in a module
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ItemKind {
+    /// Capabilities, extensions, imports, the memory model, entry points
+    /// and execution modes
+    Preamble,
+    Type,
+    Constant,
+    Global,
+    Function
+}
+
+impl ItemKind {
+    fn name(&self) -> &'static str {
+        match *self {
+            ItemKind::Preamble => "preamble",
+            ItemKind::Type => "type",
+            ItemKind::Constant => "constant",
+            ItemKind::Global => "global",
+            ItemKind::Function => "function"
+        }
+    }
+}
+
+/// How an item differs between the modules. Items are given as lines of
+/// disassembly, with any decorations on the item after its definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Added(Vec<String>),
+    Removed(Vec<String>),
+    Changed { old: Vec<String>, new: Vec<String> },
+    /// The item has a different name but is otherwise the same
+    Renamed { old_name: String, lines: Vec<String> }
+}
+
+/// A single item that differs between the modules
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemDiff {
+    pub kind: ItemKind,
+    /// The name of the item, e.g. `%main` or `%v4float`. For preamble
+    /// items this is the disassembled instruction.
+    pub name: String,
+    pub change: Change
+}
+
+/// The differences between two modules
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleDiff {
+    /// The items that differ, ordered by kind and then by where they are
+    /// in the modules
+    pub items: Vec<ItemDiff>
+}
+
+impl ModuleDiff {
+    /**
+     * Whether the modules are the same
+     */
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl fmt::Display for ModuleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            try!(write!(f, "{}", item));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ItemDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = self.kind.name();
+        match self.change {
+            Change::Added(ref lines) => {
+                try!(writeln!(f, "@@ added {} {} @@", kind, self.name));
+                for line in lines {
+                    try!(writeln!(f, "+{}", line));
+                }
+            }
+            Change::Removed(ref lines) => {
+                try!(writeln!(f, "@@ removed {} {} @@", kind, self.name));
+                for line in lines {
+                    try!(writeln!(f, "-{}", line));
+                }
+            }
+            Change::Changed { ref old, ref new } => {
+                try!(writeln!(f, "@@ changed {} {} @@", kind, self.name));
+                for (tag, line) in diff_lines(old, new) {
+                    try!(writeln!(f, "{}{}", tag, line));
+                }
+            }
+            Change::Renamed { ref old_name, .. } => {
+                try!(writeln!(f, "@@ renamed {} {} -> {} @@", kind, old_name, self.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Find the differences between two modules
+ */
+pub fn diff(old: &RawModule, new: &RawModule) -> ModuleDiff {
+    let old_items = items(old);
+    let new_items = items(new);
+
+    let mut diffs = Vec::new();
+
+    let new_by_name : HashMap<_, _> = new_items.iter().enumerate()
+        .map(|(i, item)| ((item.kind, &item.name[..]), i)).collect();
+    let old_names : HashSet<_> = old_items.iter()
+        .map(|item| (item.kind, &item.name[..])).collect();
+
+    let mut removed = Vec::new();
+    for (i, item) in old_items.iter().enumerate() {
+        match new_by_name.get(&(item.kind, &item.name[..])) {
+            Some(&j) => {
+                let new_item = &new_items[j];
+                if item.lines != new_item.lines {
+                    diffs.push((item.kind, i, ItemDiff {
+                        kind: item.kind,
+                        name: item.name.clone(),
+                        change: Change::Changed {
+                            old: item.lines.clone(),
+                            new: new_item.lines.clone()
+                        }
+                    }));
+                }
+            }
+            None => removed.push(i)
+        }
+    }
+
+    let added : Vec<_> = (0..new_items.len()).filter(|&j| {
+        let item = &new_items[j];
+        !old_names.contains(&(item.kind, &item.name[..]))
+    }).collect();
+
+    // Match up the remaining items by their content
+    let mut by_content : HashMap<_, Vec<usize>> = HashMap::new();
+    for &i in removed.iter().rev() {
+        let item = &old_items[i];
+        by_content.entry((item.kind, item.content())).or_insert_with(Vec::new).push(i);
+    }
+
+    let mut renamed = HashSet::new();
+    for &j in &added {
+        let item = &new_items[j];
+        let old_idx = by_content.get_mut(&(item.kind, item.content())).and_then(|v| v.pop());
+        match old_idx {
+            Some(i) => {
+                renamed.insert(i);
+                diffs.push((item.kind, i, ItemDiff {
+                    kind: item.kind,
+                    name: item.name.clone(),
+                    change: Change::Renamed {
+                        old_name: old_items[i].name.clone(),
+                        lines: item.lines.clone()
+                    }
+                }));
+            }
+            None => {
+                diffs.push((item.kind, old_items.len() + j, ItemDiff {
+                    kind: item.kind,
+                    name: item.name.clone(),
+                    change: Change::Added(item.lines.clone())
+                }));
+            }
+        }
+    }
+
+    for i in removed {
+        if renamed.contains(&i) { continue; }
+        let item = &old_items[i];
+        diffs.push((item.kind, i, ItemDiff {
+            kind: item.kind,
+            name: item.name.clone(),
+            change: Change::Removed(item.lines.clone())
+        }));
+    }
+
+    diffs.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    ModuleDiff {
+        items: diffs.into_iter().map(|(_, _, item)| item).collect()
+    }
+}
+
+/// An item in a module, as lines of disassembly
+struct Item {
+    kind: ItemKind,
+    name: String,
+    lines: Vec<String>
+}
+
+impl Item {
+    /// The lines of the item, with its own name replaced so items can be
+    /// compared regardless of their name
+    fn content(&self) -> Vec<String> {
+        self.lines.iter().map(|line| {
+            let tokens : Vec<_> = line.split(' ').map(|tok| {
+                if tok == self.name { "%_" } else { tok }
+            }).collect();
+            tokens.join(" ")
+        }).collect()
+    }
+}
+
+fn items(module: &RawModule) -> Vec<Item> {
+    let disasm = Disassembler::with_friendly_names(module);
+    let (module, _) = strip_debug_info(module);
+
+    // Decorations are attached to the item that defines the decorated id
+    let mut decorations : HashMap<Id, Vec<&Instruction>> = HashMap::new();
+    for inst in module.instructions() {
+        match *inst {
+            Instruction::Decorate { .. } |
+            Instruction::MemberDecorate { .. } |
+            Instruction::GroupDecorate { .. } |
+            Instruction::GroupMemberDecorate { .. } => {
+                if let Some(target) = inst.uses_iter().next() {
+                    decorations.entry(target).or_insert_with(Vec::new).push(inst);
+                }
+            }
+            _ => ()
+        }
+    }
+
+    let add_decorations = |d: &Disassembler, ids: &[Id], lines: &mut Vec<String>| {
+        let mut decoration_lines = Vec::new();
+        for id in ids {
+            if let Some(insts) = decorations.get(id) {
+                decoration_lines.extend(insts.iter().map(|inst| d.instruction(inst)));
+            }
+        }
+        decoration_lines.sort();
+        lines.extend(decoration_lines);
+    };
+
+    let mut items = Vec::new();
+    let mut insts = module.instructions().iter();
+    while let Some(inst) = insts.next() {
+        let kind = match *inst {
+            Instruction::Capability { .. } |
+            Instruction::Extension { .. } |
+            Instruction::ExtInstImport { .. } |
+            Instruction::MemoryModel { .. } |
+            Instruction::EntryPoint { .. } |
+            Instruction::ExecutionMode { .. } => ItemKind::Preamble,
+            Instruction::Decorate { .. } |
+            Instruction::MemberDecorate { .. } |
+            Instruction::GroupDecorate { .. } |
+            Instruction::GroupMemberDecorate { .. } => continue,
+            Instruction::Variable { .. } |
+            Instruction::DecorationGroup { .. } |
+            Instruction::Undef { .. } => ItemKind::Global,
+            Instruction::Function { .. } => ItemKind::Function,
+            _ if inst.defines_type().is_some() => ItemKind::Type,
+            _ if inst.defines().is_some() => ItemKind::Constant,
+            _ => ItemKind::Global
+        };
+
+        match kind {
+            ItemKind::Preamble => {
+                // Entry points and their modes are matched by name, so
+                // changes to their operands show as changes
+                let line = disasm.instruction(inst);
+                let name = match *inst {
+                    Instruction::EntryPoint { execution_model, ref name, .. } => {
+                        format!("OpEntryPoint {} {:?}", execution_model.name(), name)
+                    }
+                    Instruction::ExecutionMode { entry_point, ref execution_mode } => {
+                        format!("OpExecutionMode {} {}", disasm.id_name(entry_point.into()),
+                                execution_mode.to_desc().name())
+                    }
+                    _ => line.clone()
+                };
+                items.push(Item {
+                    kind: kind,
+                    name: name,
+                    lines: vec![line]
+                });
+            }
+            ItemKind::Function => {
+                let mut body = vec![inst];
+                while let Some(inst) = insts.next() {
+                    body.push(inst);
+                    if let Instruction::FunctionEnd = *inst { break; }
+                }
+
+                // Ids local to the function are numbered in the order they
+                // are defined, e.g. `%_1`, so they don't depend on the rest
+                // of the module and can't be mistaken for global ids
+                let mut local = disasm.clone();
+                let mut ids = Vec::new();
+                for inst in &body {
+                    if let Some(id) = inst.defines() {
+                        ids.push(id);
+                        if ids.len() > 1 && disasm.id_name(id) == format!("%{}", id.0) {
+                            local.set_name(id, format!("_{}", ids.len() - 1));
+                        }
+                    }
+                }
+
+                let mut lines : Vec<_> = body.iter().map(|inst| local.instruction(inst)).collect();
+                add_decorations(&local, &ids, &mut lines);
+                items.push(Item {
+                    kind: kind,
+                    name: disasm.id_name(ids[0]),
+                    lines: lines
+                });
+            }
+            _ => {
+                let id = match inst.defines() {
+                    Some(id) => id,
+                    None => {
+                        // Shouldn't happen in a valid module, but an
+                        // instruction without an id is still compared
+                        let line = disasm.instruction(inst);
+                        items.push(Item {
+                            kind: kind,
+                            name: line.clone(),
+                            lines: vec![line]
+                        });
+                        continue;
+                    }
+                };
+
+                let mut lines = vec![disasm.instruction(inst)];
+                add_decorations(&disasm, &[id], &mut lines);
+                items.push(Item {
+                    kind: kind,
+                    name: disasm.id_name(id),
+                    lines: lines
+                });
+            }
+        }
+    }
+
+    items
+}
+
+/// Above this many added and removed lines, a changed item is shown as
+/// entirely removed and added again, which bounds the time and memory the
+/// line diff takes
+const MAX_LINE_EDITS : usize = 2000;
+
+/// Diffs the lines of a changed item, using Myers' algorithm to find the
+/// fewest lines to add and remove. Each line is returned with its `-`,
+/// `+` or ` ` tag.
+fn diff_lines<'a>(old: &'a [String], new: &'a [String]) -> Vec<(char, &'a str)> {
+    // The lines at the start and the end that are the same don't need to
+    // go through the diff
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut out : Vec<_> = old[..prefix].iter().map(|l| (' ', &l[..])).collect();
+    match shortest_edit(a, b) {
+        Some(edits) => out.extend(edits),
+        None => {
+            out.extend(a.iter().map(|l| ('-', &l[..])));
+            out.extend(b.iter().map(|l| ('+', &l[..])));
+        }
+    }
+    out.extend(old[old.len() - suffix..].iter().map(|l| (' ', &l[..])));
+
+    out
+}
+
+/// Finds the shortest edit script between the lines, or `None` if it
+/// needs more than `MAX_LINE_EDITS` edits
+fn shortest_edit<'a>(a: &'a [String], b: &'a [String]) -> Option<Vec<(char, &'a str)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = ::std::cmp::min(a.len() + b.len(), MAX_LINE_EDITS) as isize;
+
+    // v[k] is the furthest x reached on diagonal k = x - y. The values
+    // for each number of edits d are kept for the way back, only for the
+    // diagonals that can be reached, which are every other one in -d..d.
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace : Vec<Vec<isize>> = Vec::new();
+
+    let mut end = None;
+    'search: for d in 0..max + 1 {
+        for k in (-d..d + 1).filter(|k| (k + d) % 2 == 0) {
+            let down = k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]);
+            let mut x = if down {
+                v[(offset + k + 1) as usize]
+            } else {
+                v[(offset + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+
+            if x >= n && y >= m {
+                end = Some(d);
+                break 'search;
+            }
+        }
+        trace.push((-d..d + 1).filter(|k| (k + d) % 2 == 0)
+                   .map(|k| v[(offset + k) as usize]).collect());
+    }
+    let end = match end {
+        Some(end) => end,
+        None => return None
+    };
+
+    // Walk back from the end, following the edit taken at each step
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..end + 1).rev() {
+        let prev = &trace[d as usize - 1];
+        let get = |k: isize| prev[((k + d - 1) / 2) as usize];
+
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push((' ', &a[x as usize - 1][..]));
+            x -= 1;
+            y -= 1;
+        }
+        if x == prev_x {
+            edits.push(('+', &b[y as usize - 1][..]));
+            y -= 1;
+        } else {
+            edits.push(('-', &a[x as usize - 1][..]));
+            x -= 1;
+        }
+    }
+    while x > 0 && y > 0 {
+        edits.push((' ', &a[x as usize - 1][..]));
+        x -= 1;
+        y -= 1;
+    }
+
+    edits.reverse();
+    Some(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, diff_lines, MAX_LINE_EDITS};
+    use desc::Id;
+    use parse::Header;
+    use transform::strip_debug_info;
+    use RawModule;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.chars().map(|c| c.to_string()).collect()
+    }
+
+    /// Checks that the diff turns `old` into `new` and returns the number
+    /// of lines added and removed
+    fn check(old: &[String], new: &[String]) -> usize {
+        let diff = diff_lines(old, new);
+        let from : Vec<_> = diff.iter().filter(|d| d.0 != '+').map(|d| d.1).collect();
+        let to : Vec<_> = diff.iter().filter(|d| d.0 != '-').map(|d| d.1).collect();
+        assert_eq!(from, old.iter().map(|l| &l[..]).collect::<Vec<_>>());
+        assert_eq!(to, new.iter().map(|l| &l[..]).collect::<Vec<_>>());
+        diff.iter().filter(|d| d.0 != ' ').count()
+    }
+
+    #[test]
+    fn fewest_edits() {
+        assert_eq!(check(&lines("abcabba"), &lines("cbabac")), 5);
+        assert_eq!(check(&lines("abc"), &lines("abc")), 0);
+        assert_eq!(check(&lines(""), &lines("abc")), 3);
+        assert_eq!(check(&lines("abxc"), &lines("abyc")), 2);
+
+        // Against the length of the longest common subsequence, for a
+        // spread of small inputs
+        let mut seed = 1u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            seed >> 16
+        };
+        for _ in 0..200 {
+            let old : String = (0..next() % 12).map(|_| (b'a' + (next() % 3) as u8) as char).collect();
+            let new : String = (0..next() % 12).map(|_| (b'a' + (next() % 3) as u8) as char).collect();
+            let (a, b) = (lines(&old), lines(&new));
+
+            let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in (0..a.len()).rev() {
+                for j in (0..b.len()).rev() {
+                    lcs[i][j] = if a[i] == b[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        ::std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+                    };
+                }
+            }
+            assert_eq!(check(&a, &b), a.len() + b.len() - 2 * lcs[0][0]);
+        }
+    }
+
+    #[test]
+    fn large_diffs_are_replaced() {
+        let old : Vec<_> = (0..MAX_LINE_EDITS * 2).map(|i| format!("old {}", i)).collect();
+        let mut new : Vec<_> = (0..MAX_LINE_EDITS * 2).map(|i| format!("new {}", i)).collect();
+        new.insert(0, old[0].clone());
+        assert_eq!(check(&old, &new), old.len() - 1 + new.len() - 1);
+    }
+
+    #[test]
+    fn renumbering_is_ignored() {
+        let module = RawModule::load_module("examples/vert.spv").unwrap();
+        let (module, _) = strip_debug_info(&module);
+
+        // Reverse the order of the ids
+        let bound = module.header().id_bound;
+        let renumbered : Vec<_> = module.instructions().iter().map(|inst| {
+            let mut inst = inst.clone();
+            inst.map_ids(|id| Id(bound - id.0));
+            inst
+        }).collect();
+        let header = Header { id_bound: bound, .. module.header().clone() };
+        let renumbered = RawModule::from_instructions(header, renumbered);
+
+        let d = diff(&module, &renumbered);
+        assert!(d.is_empty(), "{}", d);
+    }
+}
@@ -0,0 +1,513 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Disassembly of modules into the textual form used by the SPIR-V
+//! tools, e.g. `%5 = OpIAdd %int %3 %4`.
+
+use std::collections::{HashMap, HashSet};
+
+use desc::{self, Id, ValueId, TypeId, ResultId};
+use instruction::{Instruction, Decoration, ExecutionMode, ImageOperands};
+use write::header_words;
+use RawModule;
+
+include!(concat!(env!("OUT_DIR"), "/inst_disasm.rs"));
+
+/**
+ * Disassemble the module, using the numeric ids
+ */
+pub fn disassemble(module: &RawModule) -> String {
+    Disassembler::new().module(module)
+}
+
+/// Turns instructions into text, with control over how ids are named
+#[derive(Clone, Debug, Default)]
+pub struct Disassembler {
+    names: HashMap<Id, String>
+}
+
+impl Disassembler {
+    /**
+     * Creates a disassembler that writes ids as their number, e.g. `%5`
+     */
+    pub fn new() -> Disassembler {
+        Disassembler::default()
+    }
+
+    /**
+     * Creates a disassembler that names ids using the `Name` instructions
+     * in the module where possible. Names are changed to only use
+     * characters valid in an id, and given a numeric suffix if they
+     * clash.
+     */
+    pub fn with_names(module: &RawModule) -> Disassembler {
+        let mut d = Disassembler::new();
+        let mut used = HashSet::new();
+
+        for inst in module.instructions() {
+            if let Instruction::Name { id, ref name } = *inst {
+                if d.names.contains_key(&id) { continue; }
+
+                let name = id_safe(name);
+                // Purely numeric names would look like other ids, and `_`
+                // followed by digits is kept for numbering local ids
+                if name.len() == 0 || name.chars().all(|c| c.is_digit(10)) ||
+                    is_local_name(&name) { continue; }
+
+                d.add_unique(id, name, &mut used);
+            }
+        }
+
+        d
+    }
+
+    /**
+     * Creates a disassembler that names ids like `with_names`, but also
+     * gives names based on their structure to types, constants and
+     * global variables that don't have one, e.g. `%v4float` or `%int_1`.
+     * Functions without a name are named after their entry point, or
+     * numbered in the order they are defined, e.g. `%_func_0`. These
+     * names don't depend on how the module is numbered.
+     */
+    pub fn with_friendly_names(module: &RawModule) -> Disassembler {
+        let mut d = Disassembler::with_names(module);
+        let mut used : HashSet<String> = d.names.values().cloned().collect();
+
+        for inst in module.instructions() {
+            if let Instruction::EntryPoint { func, ref name, .. } = *inst {
+                let name = id_safe(name);
+                if d.names.contains_key(&func.into()) || name.len() == 0 ||
+                    is_local_name(&name) { continue; }
+                d.add_unique(func.into(), name, &mut used);
+            }
+        }
+
+        let mut functions = 0;
+        for inst in module.instructions() {
+            let id = match inst.defines() {
+                Some(id) => id,
+                None => continue
+            };
+            if d.names.contains_key(&id) {
+                if let Instruction::Function { .. } = *inst { functions += 1; }
+                continue;
+            }
+
+            let name = match *inst {
+                Instruction::Function { .. } => {
+                    functions += 1;
+                    format!("_func_{}", functions - 1)
+                }
+                _ => match friendly_name(&d, module, inst) {
+                    Some(name) => name,
+                    None => continue
+                }
+            };
+
+            d.add_unique(id, name, &mut used);
+        }
+
+        d
+    }
+
+    /// Names the id, adding a numeric suffix if the name is already used
+    fn add_unique(&mut self, id: Id, name: String, used: &mut HashSet<String>) {
+        let mut unique = name.clone();
+        let mut n = 1;
+        while used.contains(&unique) {
+            unique = format!("{}_{}", name, n);
+            n += 1;
+        }
+        used.insert(unique.clone());
+        self.names.insert(id, unique);
+    }
+
+    /**
+     * Sets the name used for the given id
+     */
+    pub fn set_name(&mut self, id: Id, name: String) {
+        self.names.insert(id, name);
+    }
+
+    /**
+     * Gets the text for the given id, including the leading `%`
+     */
+    pub fn id_name(&self, id: Id) -> String {
+        match self.names.get(&id) {
+            Some(name) => format!("%{}", name),
+            None => format!("%{}", id.0)
+        }
+    }
+
+    /**
+     * Disassembles a single instruction
+     */
+    pub fn instruction(&self, inst: &Instruction) -> String {
+        let mut out = String::new();
+        write_instruction(self, inst, &mut out);
+        out
+    }
+
+    /**
+     * Disassembles the whole module, with a comment giving the header
+     * information. Instructions are indented so the `=` of each result
+     * lines up.
+     */
+    pub fn module(&self, module: &RawModule) -> String {
+        let header = header_words(module.header());
+        let mut out = format!("; SPIR-V\n; Version: {}.{}\n; Generator: {:#010x}\n; Bound: {}\n; Schema: {}\n",
+                              module.header().version.0, module.header().version.1,
+                              header[2], header[3], header[4]);
+
+        let lines : Vec<_> = module.instructions().iter().map(|inst| {
+            let line = self.instruction(inst);
+            match line.find(" = ") {
+                Some(idx) if inst.defines().is_some() => (Some(line[..idx].to_owned()),
+                                                          line[idx + 3..].to_owned()),
+                _ => (None, line)
+            }
+        }).collect();
+
+        let width = lines.iter().filter_map(|&(ref result, _)| {
+            result.as_ref().map(|r| r.len())
+        }).filter(|&len| len <= 20).max().unwrap_or(0);
+
+        for (result, rest) in lines {
+            match result {
+                Some(result) => {
+                    out.push_str(&format!("{:>width$} = {}\n", result, rest, width=width));
+                }
+                None => {
+                    out.push_str(&format!("{:width$}   {}\n", "", rest, width=width));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Gets a name for a type, constant or global variable, based on its
+/// structure and the names of the ids it refers to
+fn friendly_name(d: &Disassembler, module: &RawModule, inst: &Instruction) -> Option<String> {
+    let n = |id: Id| d.names.get(&id).cloned().unwrap_or_else(|| id.0.to_string());
+    let ty_name = |ty: TypeId| n(ty.into());
+
+    let name = match *inst {
+        Instruction::TypeVoid { .. } => "void".to_owned(),
+        Instruction::TypeBool { .. } => "bool".to_owned(),
+        Instruction::TypeInt { width, signed, .. } => {
+            let base = if signed { "int" } else { "uint" };
+            if width == 32 { base.to_owned() } else { format!("{}{}", base, width) }
+        }
+        Instruction::TypeFloat { width, .. } => {
+            match width {
+                16 => "half".to_owned(),
+                32 => "float".to_owned(),
+                64 => "double".to_owned(),
+                _ => format!("fp{}", width)
+            }
+        }
+        Instruction::TypeVector { type_id, len, .. } => format!("v{}{}", len, ty_name(type_id)),
+        Instruction::TypeMatrix { type_id, cols, .. } => format!("mat{}{}", cols, ty_name(type_id)),
+        Instruction::TypeArray { element, len, .. } => {
+            format!("_arr_{}_{}", ty_name(element), n(len.into()))
+        }
+        Instruction::TypeRuntimeArray { element, .. } => format!("_runtimearr_{}", ty_name(element)),
+        Instruction::TypePointer { storage_class, pointee, .. } => {
+            format!("_ptr_{}_{}", storage_class.name(), ty_name(pointee))
+        }
+        Instruction::TypeStruct { ref fields, .. } => {
+            let mut name = "_struct".to_owned();
+            for &f in fields.iter() {
+                name.push('_');
+                name.push_str(&ty_name(f));
+            }
+            name
+        }
+        Instruction::TypeFunction { return_ty, ref params, .. } => {
+            let mut name = format!("_fn_{}", ty_name(return_ty));
+            for &p in params.iter() {
+                name.push('_');
+                name.push_str(&ty_name(p));
+            }
+            name
+        }
+        Instruction::TypeImage { type_id, dim, .. } => {
+            format!("image_{}_{}", dim.name(), ty_name(type_id))
+        }
+        Instruction::TypeSampledImage { image, .. } => format!("sampled_{}", ty_name(image)),
+        Instruction::TypeSampler { .. } => "sampler".to_owned(),
+        Instruction::ConstantTrue { .. } => "true".to_owned(),
+        Instruction::ConstantFalse { .. } => "false".to_owned(),
+        Instruction::Constant { result_type, ref val, .. } => {
+            let value = match module.def(result_type) {
+                Some(&Instruction::TypeInt { width: 32, signed: true, .. }) if val.len() == 1 => {
+                    (val[0] as i32).to_string()
+                }
+                Some(&Instruction::TypeInt { width: 32, .. }) if val.len() == 1 => {
+                    val[0].to_string()
+                }
+                Some(&Instruction::TypeFloat { width: 32, .. }) if val.len() == 1 => {
+                    float_name(f32::from_bits(val[0]) as f64)
+                }
+                Some(&Instruction::TypeFloat { width: 64, .. }) if val.len() == 2 => {
+                    float_name(f64::from_bits((val[1] as u64) << 32 | val[0] as u64))
+                }
+                _ => {
+                    let words : Vec<_> = val.iter().map(|w| w.to_string()).collect();
+                    words.join("_")
+                }
+            };
+            format!("{}_{}", ty_name(result_type), value.replace('-', "n"))
+        }
+        Instruction::ConstantComposite { result_type, .. } => {
+            format!("{}_composite", ty_name(result_type))
+        }
+        Instruction::ConstantNull { result_type, .. } => format!("{}_null", ty_name(result_type)),
+        Instruction::SpecConstantTrue { result_type, .. } |
+        Instruction::SpecConstantFalse { result_type, .. } |
+        Instruction::SpecConstant { result_type, .. } |
+        Instruction::SpecConstantComposite { result_type, .. } |
+        Instruction::SpecConstantOp { result_type, .. } => format!("spec_{}", ty_name(result_type)),
+        Instruction::ExtInstImport { ref name, .. } => {
+            name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect()
+        }
+        Instruction::DecorationGroup { .. } => "group".to_owned(),
+        Instruction::Variable { result_type, storage_class, .. } => {
+            // Variables in functions are left alone
+            if storage_class == desc::StorageClass::Function { return None; }
+            format!("_var{}", ty_name(result_type).trim_start_matches("_ptr"))
+        }
+        _ => return None
+    };
+
+    Some(name)
+}
+
+/// Replaces the characters that aren't valid in an id
+fn id_safe(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+/// Whether the name is `_` followed by digits, which the diff uses to
+/// number the ids local to a function
+fn is_local_name(name: &str) -> bool {
+    name.len() > 1 && name.starts_with('_') && name[1..].chars().all(|c| c.is_digit(10))
+}
+
+fn float_name(f: f64) -> String {
+    if f.is_nan() {
+        "nan".to_owned()
+    } else if f.is_infinite() {
+        "inf".to_owned()
+    } else {
+        f.to_string().replace('.', "p")
+    }
+}
+
+trait ParamDisasm {
+    fn disasm(&self, d: &Disassembler, out: &mut String);
+}
+
+impl ParamDisasm for Id {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        out.push(' ');
+        out.push_str(&d.id_name(*self));
+    }
+}
+
+impl ParamDisasm for ValueId {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        Id::from(*self).disasm(d, out);
+    }
+}
+
+impl ParamDisasm for TypeId {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        Id::from(*self).disasm(d, out);
+    }
+}
+
+impl ParamDisasm for ResultId {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        Id::from(*self).disasm(d, out);
+    }
+}
+
+impl ParamDisasm for String {
+    fn disasm(&self, _: &Disassembler, out: &mut String) {
+        out.push_str(" \"");
+        for c in self.chars() {
+            if c == '"' || c == '\\' {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out.push('"');
+    }
+}
+
+impl ParamDisasm for u32 {
+    fn disasm(&self, _: &Disassembler, out: &mut String) {
+        out.push_str(&format!(" {}", self));
+    }
+}
+
+impl ParamDisasm for bool {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        (*self as u32).disasm(d, out);
+    }
+}
+
+impl<P: ParamDisasm> ParamDisasm for Option<P> {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        if let Some(ref p) = *self {
+            p.disasm(d, out);
+        }
+    }
+}
+
+impl<P: ParamDisasm> ParamDisasm for Box<[P]> {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        for p in self.iter() {
+            p.disasm(d, out);
+        }
+    }
+}
+
+impl ParamDisasm for ExecutionMode {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        use instruction::ExecutionMode::*;
+
+        self.to_desc().disasm(d, out);
+        match *self {
+            Invocations(n) |
+            OutputVertices(n) |
+            VecTypeHint(n) => n.disasm(d, out),
+            LocalSize(x, y, z) |
+            LocalSizeHint(x, y, z) => {
+                x.disasm(d, out);
+                y.disasm(d, out);
+                z.disasm(d, out);
+            }
+            _ => ()
+        }
+    }
+}
+
+impl ParamDisasm for Decoration {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        use instruction::Decoration::*;
+
+        self.to_desc().disasm(d, out);
+        match *self {
+            SpecId(n) |
+            ArrayStride(n) |
+            MatrixStride(n) |
+            Stream(n) |
+            Location(n) |
+            Component(n) |
+            Index(n) |
+            Binding(n) |
+            DescriptorSet(n) |
+            Offset(n) |
+            XfbBuffer(n) |
+            XfbStride(n) |
+            InputAttachmentIndex(n) |
+            Alignment(n) => n.disasm(d, out),
+            BuiltIn(b) => b.disasm(d, out),
+            FuncParamAttr(attr) => attr.disasm(d, out),
+            FPRoundingMode(mode) => mode.disasm(d, out),
+            FPFastMathMode(mode) => mode.disasm(d, out),
+            LinkageAttributes(ref name, ty) => {
+                name.disasm(d, out);
+                ty.disasm(d, out);
+            }
+            _ => ()
+        }
+    }
+}
+
+impl ParamDisasm for ImageOperands {
+    fn disasm(&self, d: &Disassembler, out: &mut String) {
+        self.mask().disasm(d, out);
+        for v in self.values() {
+            v.disasm(d, out);
+        }
+    }
+}
+
+macro_rules! impl_param_disasm(
+    (enum $($name:ident),+) => (
+        $(impl ParamDisasm for ::desc::$name {
+            fn disasm(&self, _: &Disassembler, out: &mut String) {
+                out.push(' ');
+                out.push_str(self.name());
+            }
+        })+
+    );
+    (bitset $($name:ident),+) => (
+        $(impl ParamDisasm for ::desc::$name {
+            fn disasm(&self, _: &Disassembler, out: &mut String) {
+                out.push(' ');
+                if self.is_empty() {
+                    out.push_str("None");
+                } else {
+                    out.push_str(&self.names().join("|"));
+                }
+            }
+        })+
+    );
+);
+
+impl_param_disasm!(enum
+    SrcLang,
+    ExecutionModel,
+    AddressingModel,
+    MemoryModel,
+    ExecutionMode,
+    StorageClass,
+    Dim,
+    SamplerAddressingMode,
+    SamplerFilterMode,
+    ImageFormat,
+    ImageChannelOrder,
+    ImageChannelDataType,
+    FPRoundingMode,
+    LinkageType,
+    AccessQualifier,
+    FuncParamAttr,
+    Decoration,
+    BuiltIn,
+    Scope,
+    GroupOperation,
+    KernelEnqueueFlags,
+    Capability
+);
+
+impl_param_disasm!(bitset
+    ImageOperands,
+    FPFastMathMode,
+    SelectionControl,
+    LoopControl,
+    FunctionControl,
+    MemoryOrdering,
+    // MemoryAccess, // Handled below
+    KernelProfilingInfo
+);
+
+impl ParamDisasm for desc::MemoryAccess {
+    fn disasm(&self, _: &Disassembler, out: &mut String) {
+        // Like the encoder, a missing operand is the same as an empty set
+        if !self.is_empty() {
+            out.push(' ');
+            out.push_str(&self.names().join("|"));
+        }
+    }
+}
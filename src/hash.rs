@@ -0,0 +1,279 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Content hashing of modules that ignores details that don't affect
+//! what the module does, such as debug information and id numbering.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use desc::Id;
+use instruction::Instruction;
+use transform::strip_debug_info;
+use write::{encode_instruction, header_words};
+use RawModule;
+
+/// A 128-bit digest of a module
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest(pub [u8; 16]);
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.0 {
+            try!(write!(f, "{:02x}", b));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HashOptions {
+    /// Whether `Name` and `MemberName` instructions affect the hash
+    pub include_names: bool
+}
+
+/**
+ * Computes a digest of the canonical form of the module, see
+ * `canonical_words`. The digest is the same across platforms and
+ * versions of this library.
+ */
+pub fn canonical_hash(module: &RawModule, options: &HashOptions) -> Digest {
+    let words = canonical_words(module, options);
+
+    let mut bytes = Vec::with_capacity(words.len() * 4);
+    for w in words {
+        bytes.extend_from_slice(&[w as u8, (w >> 8) as u8, (w >> 16) as u8, (w >> 24) as u8]);
+    }
+
+    Digest(murmur3_x64_128(&bytes, 0))
+}
+
+/**
+ * Gets the canonical form of the module as a stream of words. Two
+ * modules that differ only in debug information, id numbering or the
+ * order of their decorations have the same canonical form.
+ *
+ * Debug information is removed, then ids are numbered in the order they
+ * first appear in the remaining instructions, leaving out decorations
+ * and names. Decorations, and names if they're included, are sorted and
+ * placed at the end. The generator and id bound from the header are
+ * ignored.
+ *
+ * Ids that only appear in decorations, such as decoration groups, are
+ * numbered in the order of the sorted decorations, with those ids left
+ * out while sorting. Group applications come first, so a group is
+ * identified by what it is applied to.
+ */
+pub fn canonical_words(module: &RawModule, options: &HashOptions) -> Vec<u32> {
+    let (stripped, _) = strip_debug_info(module);
+
+    let mut ids = IdNumbering::default();
+    let mut words = vec![header_words(module.header())[1]];
+
+    let mut annotations = Vec::new();
+    for inst in stripped.instructions() {
+        if is_annotation(inst) {
+            annotations.push(inst);
+            continue;
+        }
+
+        let mut inst = inst.clone();
+        inst.map_ids(|id| ids.get(id));
+        encode_instruction(&inst, &mut words);
+    }
+
+    if options.include_names {
+        for inst in module.instructions() {
+            match *inst {
+                Instruction::Name { .. } |
+                Instruction::MemberName { .. } => annotations.push(inst),
+                _ => ()
+            }
+        }
+    }
+
+    // Ids only referred to by decorations are numbered in an order that
+    // doesn't depend on the order of the decorations, by sorting them
+    // with those ids masked out first
+    let mut keyed : Vec<_> = annotations.into_iter().map(|inst| {
+        let mut masked = inst.clone();
+        masked.map_ids(|id| ids.lookup(id).unwrap_or(Id(!0)));
+        let mut key = vec![annotation_rank(inst)];
+        encode_instruction(&masked, &mut key);
+        (key, inst)
+    }).collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut sorted : Vec<_> = keyed.into_iter().map(|(_, inst)| {
+        let mut inst = inst.clone();
+        inst.map_ids(|id| ids.get(id));
+        let mut inst_words = Vec::new();
+        encode_instruction(&inst, &mut inst_words);
+        inst_words
+    }).collect();
+
+    sorted.sort();
+    for inst_words in sorted {
+        words.extend(inst_words);
+    }
+
+    words
+}
+
+/// Numbers ids in the order they are first seen
+#[derive(Default)]
+struct IdNumbering {
+    ids: HashMap<Id, Id>
+}
+
+impl IdNumbering {
+    fn get(&mut self, id: Id) -> Id {
+        if !id.is_valid() { return id; }
+
+        let next = Id(self.ids.len() as u32 + 1);
+        *self.ids.entry(id).or_insert(next)
+    }
+
+    /// Gets the number of an id without numbering it if it's new
+    fn lookup(&self, id: Id) -> Option<Id> {
+        if !id.is_valid() { return Some(id); }
+        self.ids.get(&id).cloned()
+    }
+}
+
+/// Where an annotation or name goes when numbering the ids only they
+/// refer to
+fn annotation_rank(inst: &Instruction) -> u32 {
+    match *inst {
+        Instruction::GroupDecorate { .. } |
+        Instruction::GroupMemberDecorate { .. } => 0,
+        Instruction::Decorate { .. } |
+        Instruction::MemberDecorate { .. } => 1,
+        Instruction::DecorationGroup { .. } => 2,
+        _ => 3
+    }
+}
+
+fn is_annotation(inst: &Instruction) -> bool {
+    match *inst {
+        Instruction::Decorate { .. } |
+        Instruction::MemberDecorate { .. } |
+        Instruction::DecorationGroup { .. } |
+        Instruction::GroupDecorate { .. } |
+        Instruction::GroupMemberDecorate { .. } => true,
+        _ => false
+    }
+}
+
+/// The 128-bit x64 variant of MurmurHash3
+fn murmur3_x64_128(data: &[u8], seed: u64) -> [u8; 16] {
+    const C1 : u64 = 0x87c37b91114253d5;
+    const C2 : u64 = 0x4cf5ad432745937f;
+
+    fn fmix(mut k: u64) -> u64 {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51afd7ed558ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ceb9fe1a85ec53);
+        k ^= k >> 33;
+        k
+    }
+
+    fn read_u64(bytes: &[u8]) -> u64 {
+        bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64)
+    }
+
+    let mut h1 = seed;
+    let mut h2 = seed;
+
+    let blocks = data.len() / 16;
+    for i in 0..blocks {
+        let block = &data[i * 16..i * 16 + 16];
+        let mut k1 = read_u64(&block[..8]);
+        let mut k2 = read_u64(&block[8..]);
+
+        k1 = k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(27).wrapping_add(h2).wrapping_mul(5).wrapping_add(0x52dce729);
+
+        k2 = k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2.rotate_left(31).wrapping_add(h1).wrapping_mul(5).wrapping_add(0x38495ab5);
+    }
+
+    let tail = &data[blocks * 16..];
+    if tail.len() > 8 {
+        let k2 = read_u64(&tail[8..]);
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if tail.len() > 0 {
+        let k1 = read_u64(&tail[..::std::cmp::min(tail.len(), 8)]);
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    let mut out = [0; 16];
+    for i in 0..8 {
+        out[i] = (h1 >> (i * 8)) as u8;
+        out[i + 8] = (h2 >> (i * 8)) as u8;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use super::{canonical_hash, HashOptions};
+
+    #[test]
+    fn decoration_order_with_groups() {
+        let module = |decorations: &str| {
+            assemble(&format!("
+                OpCapability Shader
+                OpMemoryModel Logical GLSL450
+                {}
+                %a_group = OpDecorationGroup
+                %b_group = OpDecorationGroup
+                OpGroupDecorate %a_group %a
+                OpGroupDecorate %b_group %b
+                %float = OpTypeFloat 32
+                %ptr = OpTypePointer Private %float
+                %a = OpVariable %ptr Private
+                %b = OpVariable %ptr Private
+            ", decorations)).unwrap()
+        };
+
+        let options = HashOptions::default();
+        let first = module("OpDecorate %a_group Flat
+                            OpDecorate %b_group NoPerspective");
+        let second = module("OpDecorate %b_group NoPerspective
+                             OpDecorate %a_group Flat");
+        let swapped = module("OpDecorate %a_group NoPerspective
+                              OpDecorate %b_group Flat");
+
+        assert_eq!(canonical_hash(&first, &options), canonical_hash(&second, &options));
+        assert!(canonical_hash(&first, &options) != canonical_hash(&swapped, &options));
+    }
+}
@@ -15,9 +15,87 @@ impl Instruction {
     pub fn defines_value(&self) -> Option<ValueId> {
         self.defines_value_inner().map(|r| r.to_value_id())
     }
+
+    /**
+     * Gets the id defined by this instruction, whether it is a value or
+     * a type
+     */
+    pub fn defines(&self) -> Option<Id> {
+        if let Some(id) = self.defines_value() {
+            Some(id.into())
+        } else {
+            self.defines_type().map(Id::from)
+        }
+    }
+
+    /**
+     * Gets the ids used by this instruction, not including its result
+     * type. Missing optional ids are given as the invalid id.
+     */
+    pub fn uses(&self) -> Vec<Id> {
+        self.uses_iter().collect()
+    }
+
+    /**
+     * Iterates over the ids used by this instruction without allocating,
+     * giving the same ids as `uses`
+     */
+    pub fn uses_iter<'a>(&'a self) -> UsesIter<'a> {
+        UsesIter {
+            inst: self,
+            next: 0
+        }
+    }
+
+    /**
+     * Rewrites every id in the instruction, including the ids it defines,
+     * with the given function. Missing optional ids are left as they
+     * are. This is `for_each_id_mut` for when the role doesn't matter.
+     */
+    pub fn map_ids<F: FnMut(Id) -> Id>(&mut self, mut f: F) {
+        self.for_each_id_mut(|id, _| *id = f(Id(*id)).0);
+    }
+}
+
+/// What an id is to the instruction it's in, see
+/// `Instruction::for_each_id_mut`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdRole {
+    /// The id the instruction defines, including the id of a type
+    Result,
+    /// The type of the value the instruction defines
+    ResultType,
+    /// An operand that is a type
+    Type,
+    /// An operand that is a value, including labels and functions
+    Value,
+    /// An operand that may be a type or a value, like the target of a
+    /// decoration
+    Any
+}
+
+/// Iterator over the ids used by an instruction, see
+/// `Instruction::uses_iter`
+#[derive(Clone)]
+pub struct UsesIter<'a> {
+    inst: &'a Instruction,
+    next: usize
+}
+
+impl<'a> Iterator for UsesIter<'a> {
+    type Item = Id;
+
+    fn next(&mut self) -> Option<Id> {
+        let id = self.inst.nth_use(self.next);
+        if id.is_some() {
+            self.next += 1;
+        }
+        id
+    }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum ExecutionMode {
     Invocations(u32),
     SpacingEqual,
@@ -91,6 +169,7 @@ impl ExecutionMode {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum Decoration {
     RelaxedPrecision,
     SpecId(u32),
@@ -188,6 +267,7 @@ impl Decoration {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct ImageOperands {
     set: desc::ImageOperands,
     values: Vec<ValueId>
@@ -209,6 +289,37 @@ impl ImageOperands {
         }
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /**
+     * Gets the set of operands that have values
+     */
+    #[inline]
+    pub fn mask(&self) -> desc::ImageOperands {
+        self.set
+    }
+
+    /**
+     * Gets the values of the operands, in the order they appear in the
+     * instruction
+     */
+    #[inline]
+    pub fn values(&self) -> &[ValueId] {
+        &self.values[..]
+    }
+
+    /**
+     * Gets the values of the operands mutably, in the order they appear
+     * in the instruction
+     */
+    #[inline]
+    pub fn values_mut(&mut self) -> &mut [ValueId] {
+        &mut self.values[..]
+    }
+
     pub fn get(&mut self, op: desc::ImageOperands) -> Option<ValueId> {
         assert!(op.count() == 1, "`op` must be single entry, got {:?}", op);
 
@@ -6,24 +6,44 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::io::Read;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_test;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
 use std::path::Path;
 
+pub mod asm;
+pub mod builder;
+pub mod debug_info;
 pub mod desc;
+pub mod diagnostic;
+pub mod diff;
+pub mod disasm;
+pub mod hash;
 pub mod instruction;
+pub mod link;
 pub mod parse;
+pub mod stats;
+pub mod transform;
+pub mod validate;
+pub mod visit;
+pub mod write;
 
 use desc::Id;
-use instruction::Instruction;
-use parse::Result;
+use instruction::{IdRole, Instruction};
+use parse::{Header, Result};
 
 /**
  * Minimal representation of a SPIR-V module.
  */
 pub struct RawModule {
+    header: Header,
     instructions: Vec<Instruction>,
-    def_map: Box<[usize]>,
-    use_map: Box<[Vec<usize>]>
+    def_map: HashMap<Id, usize>,
+    use_map: HashMap<Id, Vec<usize>>
 }
 
 impl RawModule {
@@ -38,46 +58,198 @@ impl RawModule {
     }
 
     /**
-     * Read a module
+     * Read a module, with the default `ReaderLimits`. Modules with an id
+     * bound over the universal limit in the specification are rejected.
      */
     pub fn read_module<R: Read>(reader: R) -> Result<RawModule> {
+        RawModule::read_module_with_limits(reader, parse::ReaderLimits::default())
+    }
+
+    /**
+     * Read a module, failing if it goes over the given limits. Use this
+     * for modules from untrusted sources.
+     */
+    pub fn read_module_with_limits<R: Read>(reader: R, limits: parse::ReaderLimits)
+                                            -> Result<RawModule> {
+        let mut reader = try!(parse::Reader::with_limits(reader, limits));
+
+        let header = try!(reader.read_header());
+
+        let mut instructions = Vec::new();
+
+        let mut offset = reader.offset();
+        while let Some(raw_inst) = try!(reader.read_instruction()) {
+            let inst = try!(parse::parse_raw_instruction(raw_inst).and_then(|inst| {
+                try!(parse::check_ids(&inst, header.id_bound));
+                Ok(inst)
+            }).map_err(|e| e.at(offset)));
+            instructions.push(inst);
+            offset = reader.offset();
+        }
+
+        Ok(RawModule::from_instructions(header, instructions))
+    }
+
+    /**
+     * Read a module, carrying on past instructions that fail to parse.
+     * Those instructions are kept as `Instruction::Unknown` with their
+     * raw operands, and their errors are returned with the module. If an
+     * instruction's word count is bad, the rest of the module can't be
+     * read, so only the instructions before it are kept. Fails only if
+     * the header can't be read.
+     */
+    pub fn read_module_lenient<R: Read>(reader: R)
+                                        -> Result<(RawModule, Vec<parse::InstructionError>)> {
         let mut reader = try!(parse::Reader::new(reader));
 
         let header = try!(reader.read_header());
 
-        let ids = header.id_bound as usize;
-        let mut def_map = (vec![!0; ids]).into_boxed_slice();
-        let mut use_map = (vec![Vec::new(); ids]).into_boxed_slice();
+        let mut instructions = Vec::new();
+        let mut errors = Vec::new();
 
-        let mut instructions = Vec::with_capacity(ids);
+        loop {
+            let offset = reader.offset();
+            let raw_inst = match reader.read_instruction() {
+                Ok(Some(raw_inst)) => raw_inst,
+                Ok(None) => break,
+                Err(e) => {
+                    errors.push(parse::InstructionError {
+                        index: instructions.len(),
+                        error: e
+                    });
+                    break;
+                }
+            };
 
-        while let Some(raw_inst) = try!(reader.read_instruction()) {
-            let inst = try!(parse::parse_raw_instruction(raw_inst));
-            let inst_idx = instructions.len();
-
-            if let Some(id) = inst.defines_value() {
-                let idx = id.0 as usize;
-                def_map[idx] = inst_idx;
-            } else if let Some(id) = inst.defines_type() {
-                let idx = id.0 as usize;
-                def_map[idx] = inst_idx;
+            let inst = parse::parse_instruction(raw_inst.opcode, &raw_inst.params).and_then(|inst| {
+                try!(parse::check_ids(&inst, header.id_bound));
+                Ok(inst)
+            });
+            match inst {
+                Ok(inst) => instructions.push(inst),
+                Err(e) => {
+                    errors.push(parse::InstructionError {
+                        index: instructions.len(),
+                        error: e.at(offset)
+                    });
+                    instructions.push(Instruction::Unknown(raw_inst.opcode,
+                                                           raw_inst.params.into_boxed_slice()));
+                }
             }
+        }
+
+        Ok((RawModule::from_instructions(header, instructions), errors))
+    }
+
+    /**
+     * Parse a module from its words, which must be in the native byte
+     * order
+     */
+    pub fn from_words(words: &[u32]) -> Result<RawModule> {
+        let (header, insts) = try!(parse::parse_words(words));
 
-            let uses = inst.uses();
-            for id in uses {
-                let idx = id.0 as usize;
-                if idx == 0 { continue; }
-                use_map[idx].push(inst_idx);
+        let mut instructions = Vec::new();
+        for inst in insts {
+            let inst = try!(inst);
+            let decoded = try!(inst.decode());
+            try!(parse::check_ids(&decoded, header.id_bound).map_err(|e| e.at(inst.offset)));
+            instructions.push(decoded);
+        }
+
+        Ok(RawModule::from_instructions(header, instructions))
+    }
+
+    /**
+     * Parse a module from its bytes, in either byte order
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Result<RawModule> {
+        let words = try!(parse::words_from_bytes(bytes));
+        RawModule::from_words(&words)
+    }
+
+    /**
+     * Create a module from the given header and instructions. Ids that
+     * aren't below the id bound in the header are left out of the
+     * definition and use maps.
+     *
+     * The maps only have entries for the ids that are actually used, so
+     * a large id bound doesn't cost anything by itself.
+     */
+    pub fn from_instructions(header: Header, instructions: Vec<Instruction>) -> RawModule {
+        let in_bounds = |id: Id| id.is_valid() && id.0 < header.id_bound;
+        let mut def_map = HashMap::new();
+        let mut use_map = HashMap::new();
+
+        for (inst_idx, inst) in instructions.iter().enumerate() {
+            if let Some(id) = inst.defines() {
+                if in_bounds(id) {
+                    def_map.insert(id, inst_idx);
+                }
             }
 
-            instructions.push(inst);
+            for id in inst.uses_iter() {
+                if in_bounds(id) {
+                    use_map.entry(id).or_insert_with(Vec::new).push(inst_idx);
+                }
+            }
         }
 
-        Ok(RawModule {
+        RawModule {
+            header: header,
             instructions: instructions,
             def_map: def_map,
             use_map: use_map
-        })
+        }
+    }
+
+    /**
+     * Write the module to a file
+     */
+    pub fn save_module<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = try!(std::fs::File::create(path));
+
+        self.write_module(io::BufWriter::new(file))
+    }
+
+    /**
+     * Write the module in binary form
+     */
+    pub fn write_module<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = write::Writer::new(writer);
+
+        try!(writer.write_header(&self.header));
+        for inst in &self.instructions {
+            try!(writer.write_instruction(inst));
+        }
+
+        writer.flush()
+    }
+
+    /**
+     * Encode the module as a sequence of words
+     */
+    pub fn to_words(&self) -> Vec<u32> {
+        let mut words = Vec::with_capacity(self.instructions.len() * 4);
+        words.extend_from_slice(&write::header_words(&self.header));
+        for inst in &self.instructions {
+            write::encode_instruction(inst, &mut words);
+        }
+
+        words
+    }
+
+    /**
+     * Gets the header of the module
+     */
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /**
+     * Consumes the module, returning the header and instructions
+     */
+    pub fn into_parts(self) -> (Header, Vec<Instruction>) {
+        (self.header, self.instructions)
     }
 
     /**
@@ -92,28 +264,17 @@ impl RawModule {
      * any
      */
     pub fn def_index<I: Into<Id>>(&self, id: I) -> Option<usize> {
-        let idx = id.into().0 as usize;
-        if idx == 0 { return None; }
-
-        self.def_map.get(idx).and_then(|&idx| {
-            if idx == !0 {
-                None
-            } else {
-                Some(idx)
-            }
-        })
+        self.def_map.get(&id.into()).cloned()
     }
 
     /**
      * Gets the indices of the instructions that use the given id
      */
     pub fn use_indices<'a, I: Into<Id>>(&'a self, id: I) -> Option<&'a [usize]> {
-        let idx = id.into().0 as usize;
-        if idx == 0 { return None; }
+        let id = id.into();
+        if !id.is_valid() || id.0 >= self.header.id_bound { return None; }
 
-        self.use_map.get(idx).map(|indices| {
-            &indices[..]
-        })
+        Some(self.use_map.get(&id).map(|indices| &indices[..]).unwrap_or(&[]))
     }
 
     /**
@@ -136,6 +297,222 @@ impl RawModule {
             indices: indices
         }
     }
+
+    /**
+     * Allocates a new id by raising the id bound
+     */
+    pub fn alloc_id(&mut self) -> Id {
+        let id = Id(self.header.id_bound);
+        self.reserve_id(id);
+        id
+    }
+
+    /**
+     * Adds an instruction to the end of the module. Unlike
+     * `from_instructions`, ids that aren't below the id bound raise it.
+     */
+    pub fn push_instruction(&mut self, inst: Instruction) {
+        let idx = self.instructions.len();
+        self.insert_instruction(idx, inst);
+    }
+
+    /**
+     * Inserts an instruction at the given index, moving the instructions
+     * after it along. Ids that aren't below the id bound raise it.
+     *
+     * Every index after the instruction in the definition and use maps
+     * has to be updated, so unless the instruction goes at the end this
+     * takes time proportional to the number of ids and uses in the
+     * module. Use `splice_instructions` to make many changes at once.
+     *
+     * Panics if the index is greater than the number of instructions.
+     */
+    pub fn insert_instruction(&mut self, idx: usize, inst: Instruction) {
+        self.splice_instructions(idx..idx, vec![inst]);
+    }
+
+    /**
+     * Removes the instruction at the given index, moving the instructions
+     * after it back. Instructions that use an id it defines are left as
+     * they are.
+     *
+     * Like `insert_instruction`, this has to update the indices of all
+     * the instructions after it.
+     *
+     * Panics if the index is out of bounds.
+     */
+    pub fn remove_instruction(&mut self, idx: usize) -> Instruction {
+        assert!(idx < self.instructions.len(), "instruction index out of bounds");
+        self.splice_instructions(idx..idx + 1, Vec::new()).pop().unwrap()
+    }
+
+    /**
+     * Replaces the instructions in the range with the given ones,
+     * returning the old ones. Ids that aren't below the id bound raise
+     * it. The indices of the instructions after the range are only
+     * updated once, however many instructions are added or removed.
+     *
+     * Panics if the range is out of bounds.
+     */
+    pub fn splice_instructions(&mut self, range: std::ops::Range<usize>,
+                               insts: Vec<Instruction>) -> Vec<Instruction> {
+        assert!(range.start <= range.end && range.end <= self.instructions.len(),
+                "instruction range out of bounds");
+
+        for idx in range.clone() {
+            self.unindex_instruction(idx);
+        }
+
+        let added = insts.len();
+        let removed = range.end - range.start;
+        if added != removed {
+            self.shift_indices(range.end, added as isize - removed as isize);
+        }
+
+        let start = range.start;
+        let old = self.instructions.splice(range, insts).collect();
+        for idx in start..start + added {
+            self.index_instruction(idx);
+        }
+        old
+    }
+
+    /**
+     * Replaces the instruction at the given index, returning the old one.
+     * Ids that aren't below the id bound raise it.
+     *
+     * Panics if the index is out of bounds.
+     */
+    pub fn replace_instruction(&mut self, idx: usize, inst: Instruction) -> Instruction {
+        self.unindex_instruction(idx);
+        let old = std::mem::replace(&mut self.instructions[idx], inst);
+        self.index_instruction(idx);
+        old
+    }
+
+    /**
+     * Replaces every use of `old` with `new`. The definition of `old` is
+     * kept, and `Unknown` instructions are not rewritten.
+     *
+     * Result types aren't in the use map, so replacing a type has to look
+     * through all the instructions for values of that type.
+     */
+    pub fn replace_uses<A: Into<Id>, B: Into<Id>>(&mut self, old: A, new: B) {
+        let (old, new) = (old.into(), new.into());
+        if old == new || !old.is_valid() {
+            return;
+        }
+        if old.0 >= self.header.id_bound {
+            return;
+        }
+        self.reserve_id(new);
+
+        let def = self.def_index(old);
+        let rewrite = |inst: &mut Instruction| {
+            inst.for_each_id_mut(|id, role| {
+                if *id == old.0 && role != IdRole::Result {
+                    *id = new.0;
+                }
+            });
+        };
+
+        let indices = self.use_map.remove(&old).unwrap_or_else(Vec::new);
+        let mut last = None;
+        for &inst_idx in &indices {
+            // Instructions that use the id more than once appear more
+            // than once
+            if last == Some(inst_idx) { continue; }
+            last = Some(inst_idx);
+
+            rewrite(&mut self.instructions[inst_idx]);
+        }
+
+        if def.map(|def| self.instructions[def].defines_type().is_some()).unwrap_or(true) {
+            let old_ty = old.to_type_id();
+            for inst in &mut self.instructions {
+                if inst.type_id_of() == Some(old_ty) {
+                    rewrite(inst);
+                }
+            }
+        }
+
+        if new.is_valid() && indices.len() > 0 {
+            let uses = self.use_map.entry(new).or_insert_with(Vec::new);
+            uses.extend(indices);
+            uses.sort();
+        }
+    }
+
+    /// Raises the id bound so the id is below it
+    fn reserve_id(&mut self, id: Id) {
+        if id.0 >= self.header.id_bound {
+            self.header.id_bound = id.0 + 1;
+        }
+    }
+
+    /// Adds the instruction at the index to the definition and use maps
+    fn index_instruction(&mut self, inst_idx: usize) {
+        if let Some(id) = self.instructions[inst_idx].defines() {
+            if id.is_valid() {
+                self.reserve_id(id);
+                self.def_map.insert(id, inst_idx);
+            }
+        }
+
+        let max_use = self.instructions[inst_idx].uses_iter().max_by_key(|id| id.0);
+        if let Some(id) = max_use {
+            self.reserve_id(id);
+        }
+
+        for id in self.instructions[inst_idx].uses_iter() {
+            if !id.is_valid() { continue; }
+            let uses = self.use_map.entry(id).or_insert_with(Vec::new);
+            let pos = match uses.binary_search(&inst_idx) {
+                Ok(pos) | Err(pos) => pos
+            };
+            uses.insert(pos, inst_idx);
+        }
+    }
+
+    /// Removes the instruction at the index from the definition and use
+    /// maps
+    fn unindex_instruction(&mut self, inst_idx: usize) {
+        let inst = &self.instructions[inst_idx];
+        if let Some(id) = inst.defines() {
+            if self.def_map.get(&id) == Some(&inst_idx) {
+                self.def_map.remove(&id);
+            }
+        }
+
+        for id in inst.uses_iter() {
+            if let Some(uses) = self.use_map.get_mut(&id) {
+                uses.retain(|&idx| idx != inst_idx);
+            }
+        }
+    }
+
+    /// Moves the indices at or after `from` in the definition and use
+    /// maps by `delta`
+    fn shift_indices(&mut self, from: usize, delta: isize) {
+        if from >= self.instructions.len() {
+            return;
+        }
+
+        let shift = |idx: &mut usize| {
+            if *idx >= from {
+                *idx = (*idx as isize + delta) as usize;
+            }
+        };
+
+        for def in self.def_map.values_mut() {
+            shift(def);
+        }
+        for uses in self.use_map.values_mut() {
+            for idx in uses.iter_mut() {
+                shift(idx);
+            }
+        }
+    }
 }
 
 pub struct Uses<'a> {
@@ -162,3 +539,239 @@ impl<'a> Iterator for Uses<'a> {
         (len, Some(len))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use asm::assemble;
+    use desc::{Id, ValueId, ResultId};
+    use instruction::Instruction;
+    use RawModule;
+
+    const MODULE : &'static str = "
+        OpCapability Shader
+        OpMemoryModel Logical GLSL450
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %int = OpTypeInt 32 1
+        %int2 = OpTypeInt 32 1
+        %bool = OpTypeBool
+        %zero = OpConstant %int 0
+        %one = OpConstant %int 1
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        OpBranch %header
+        %header = OpLabel
+        %i = OpPhi %int %zero %entry %next %header
+        %next = OpIAdd %int %i %one
+        %done = OpSGreaterThan %bool %next %one
+        OpLoopMerge %merge %header None
+        OpBranchConditional %done %merge %header
+        %merge = OpLabel
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    /// Checks the definition and use maps against the ones built from
+    /// scratch for the same instructions
+    fn check_maps(module: &RawModule) {
+        let rebuilt = RawModule::from_instructions(module.header().clone(),
+                                                   module.instructions().to_vec());
+        assert_eq!(module.def_map, rebuilt.def_map);
+
+        let non_empty = |map: &HashMap<Id, Vec<usize>>| -> HashMap<Id, Vec<usize>> {
+            map.iter().filter(|e| e.1.len() > 0).map(|(&k, v)| (k, v.clone())).collect()
+        };
+        assert_eq!(non_empty(&module.use_map), non_empty(&rebuilt.use_map));
+    }
+
+    fn index_of(module: &RawModule, op: ::desc::Op) -> usize {
+        module.instructions().iter().position(|inst| inst.opcode() == op as u16).unwrap()
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut module = assemble(MODULE).unwrap();
+        check_maps(&module);
+
+        let add = index_of(&module, ::desc::Op::IAdd);
+        let (int, one) = match module.instructions()[add] {
+            Instruction::IAdd { result_type, rhs, .. } => (result_type, rhs),
+            _ => unreachable!()
+        };
+        let id = module.alloc_id();
+        module.insert_instruction(add + 1, Instruction::IAdd {
+            result_type: int,
+            result_id: id.to_result_id(),
+            lhs: one,
+            rhs: one
+        });
+        check_maps(&module);
+        assert_eq!(module.def_index(id), Some(add + 1));
+
+        module.remove_instruction(add + 1);
+        check_maps(&module);
+        assert_eq!(module.def_index(id), None);
+
+        module.push_instruction(Instruction::Capability {
+            capability: ::desc::Capability::Matrix
+        });
+        check_maps(&module);
+    }
+
+    #[test]
+    fn replace_and_splice() {
+        let mut module = assemble(MODULE).unwrap();
+
+        let add = index_of(&module, ::desc::Op::IAdd);
+        let old = module.replace_instruction(add, Instruction::Nop);
+        check_maps(&module);
+        module.replace_instruction(add, old);
+        check_maps(&module);
+
+        // Swap the add and the comparison, and add a new instruction
+        let moved = module.splice_instructions(add..add + 2, Vec::new());
+        check_maps(&module);
+        let id = module.alloc_id();
+        let mut insts = vec![moved[1].clone(), moved[0].clone()];
+        insts.push(Instruction::Undef {
+            result_type: moved[0].type_id_of().unwrap(),
+            result_id: ResultId(id.0)
+        });
+        module.splice_instructions(add - 1..add, insts);
+        check_maps(&module);
+
+        // Large enough to go past the id bound
+        module.splice_instructions(0..0, vec![Instruction::Name {
+            id: Id(100),
+            name: "far".to_owned()
+        }]);
+        check_maps(&module);
+        assert_eq!(module.header().id_bound, 101);
+    }
+
+    #[test]
+    fn replace_uses() {
+        let mut module = assemble(MODULE).unwrap();
+
+        // The phi uses its own loop's add, which uses the phi
+        let phi = module.instructions()[index_of(&module, ::desc::Op::Phi)].defines().unwrap();
+        let new = module.alloc_id();
+        module.replace_uses(phi, new);
+        check_maps(&module);
+        assert_eq!(module.uses(phi).count(), 0);
+        assert!(module.def(phi).is_some());
+        match module.instructions()[index_of(&module, ::desc::Op::IAdd)] {
+            Instruction::IAdd { lhs, .. } => assert_eq!(lhs, ValueId(new.0)),
+            _ => unreachable!()
+        }
+
+        // Types are also used as result types, which aren't in the use
+        // map
+        let int = module.instructions()[index_of(&module, ::desc::Op::TypeInt)].defines().unwrap();
+        let int2 = module.instructions()[index_of(&module, ::desc::Op::TypeInt) + 1]
+            .defines().unwrap();
+        module.replace_uses(int, int2);
+        check_maps(&module);
+        for inst in module.instructions() {
+            assert!(inst.type_id_of() != Some(int.to_type_id()));
+        }
+    }
+
+    #[test]
+    fn ids_out_of_range() {
+        let words = RawModule::load_module("examples/vert.spv").unwrap().to_words();
+        let bound = words[3];
+        let module = RawModule::from_words(&words).unwrap();
+
+        // Every id the instruction has, in any role, is checked
+        for inst in module.instructions() {
+            let mut count = 0;
+            inst.clone().for_each_id_mut(|_, _| count += 1);
+            for n in 0..count {
+                let mut bad = inst.clone();
+                let mut i = 0;
+                bad.for_each_id_mut(|id, _| {
+                    if i == n { *id = bound; }
+                    i += 1;
+                });
+                match ::parse::check_ids(&bad, bound) {
+                    Err(::parse::ParseError::IdOutOfRange(id)) => assert_eq!(id, Id(bound)),
+                    r => panic!("{:?} with id {} out of range gave {:?}", bad, n, r)
+                }
+            }
+        }
+
+        // A variable with a result type past the bound, as in the fuzz
+        // corpus
+        let out_of_range = |r: ::parse::Result<RawModule>| match r {
+            Err(e) => match *e.root() {
+                ::parse::ParseError::IdOutOfRange(id) => assert_eq!(id, Id(134217736)),
+                ref e => panic!("unexpected error {}", e)
+            },
+            Ok(_) => panic!("module with a result type out of range was accepted")
+        };
+        let path = "fuzz/corpus/read_module/result_type_out_of_range.spv";
+        out_of_range(RawModule::load_module(path));
+
+        let mut bad = words.clone();
+        let mut i = 5;
+        while bad[i] & 0xFFFF != ::desc::Op::Variable as u32 {
+            i += (bad[i] >> 16) as usize;
+        }
+        bad[i + 1] = 134217736;
+        out_of_range(RawModule::from_words(&bad));
+    }
+
+    #[test]
+    fn read_lenient() {
+        let module = assemble(MODULE).unwrap();
+        let add = index_of(&module, ::desc::Op::IAdd);
+        let (next, one) = match module.instructions()[add] {
+            Instruction::IAdd { result_id, rhs, .. } => (Id::from(result_id), Id::from(rhs)),
+            _ => unreachable!()
+        };
+
+        // Give the add a result id past the bound
+        let mut words = module.to_words();
+        let mut offset = 5;
+        for _ in 0..add {
+            offset += (words[offset] >> 16) as usize;
+        }
+        assert_eq!(words[offset] & 0xFFFF, ::desc::Op::IAdd as u32);
+        words[offset + 2] = module.header().id_bound;
+        let bytes : Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes().to_vec()).collect();
+
+        let (read, errors) = RawModule::read_module_lenient(&bytes[..]).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, add);
+        assert_eq!(errors[0].error.offset(), Some(offset));
+        match *errors[0].error.root() {
+            ::parse::ParseError::IdOutOfRange(id) => assert_eq!(id.0, module.header().id_bound),
+            ref e => panic!("unexpected error {}", e)
+        }
+
+        // The bad instruction is kept as it was, and the rest is decoded
+        assert_eq!(read.instructions().len(), module.instructions().len());
+        match read.instructions()[add] {
+            Instruction::Unknown(op, ref operands) => {
+                assert_eq!(op, ::desc::Op::IAdd as u16);
+                assert_eq!(operands[..], words[offset + 1..offset + 5]);
+            }
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+        for (idx, inst) in read.instructions().iter().enumerate() {
+            if idx != add {
+                assert_eq!(format!("{:?}", inst),
+                           format!("{:?}", module.instructions()[idx]));
+            }
+        }
+
+        check_maps(&read);
+        assert_eq!(read.def_index(next), None);
+        assert_eq!(read.def_index(one), module.def_index(one));
+        let uses : Vec<_> = read.uses(one).map(|inst| inst.opcode()).collect();
+        assert_eq!(uses, [::desc::Op::SGreaterThan as u16]);
+    }
+}
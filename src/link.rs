@@ -0,0 +1,667 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Linking of modules using the `LinkageAttributes` decoration.
+
+use std::{self, fmt, error};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+
+use desc::{self, Id};
+use diagnostic::Diagnostic;
+use instruction::{Decoration, Instruction};
+use transform::compact_ids;
+use write::encode_instruction;
+use RawModule;
+
+pub type Result<T> = std::result::Result<T, LinkError>;
+
+#[derive(Debug)]
+pub enum LinkError {
+    NoModules,
+    UnresolvedSymbol(String),
+    DuplicateSymbol(String),
+    SymbolTypeMismatch(String),
+    DuplicateEntryPoint(String),
+    MemoryModelMismatch
+}
+
+impl LinkError {
+    /**
+     * Gets a diagnostic for the error. Link errors are about whole
+     * modules, so there's no instruction for it to point at.
+     */
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new("linking", self.message())
+    }
+
+    fn message(&self) -> String {
+        use self::LinkError::*;
+
+        match *self {
+            NoModules => "no modules to link".to_owned(),
+            UnresolvedSymbol(ref name) => {
+                format!("no export for imported symbol `{}`", name)
+            }
+            DuplicateSymbol(ref name) => {
+                format!("symbol `{}` is exported more than once", name)
+            }
+            SymbolTypeMismatch(ref name) => {
+                format!("import of symbol `{}` does not match the type of the export", name)
+            }
+            DuplicateEntryPoint(ref name) => {
+                format!("entry point `{}` is defined more than once", name)
+            }
+            MemoryModelMismatch => {
+                "modules have different addressing or memory models".to_owned()
+            }
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("Error while linking: "));
+        f.write_str(&self.message())
+    }
+}
+
+impl error::Error for LinkError {
+    fn description(&self) -> &str {
+        use self::LinkError::*;
+        match *self {
+            NoModules => "no modules to link",
+            UnresolvedSymbol(_) => "unresolved symbol",
+            DuplicateSymbol(_) => "duplicate symbol",
+            SymbolTypeMismatch(_) => "symbol type mismatch",
+            DuplicateEntryPoint(_) => "duplicate entry point",
+            MemoryModelMismatch => "memory model mismatch"
+        }
+    }
+}
+
+/**
+ * Links the given modules into a single module.
+ *
+ * Every symbol imported with `LinkageAttributes` is resolved against the
+ * symbol exported under the same name, which must have the same type.
+ * The imported declarations are removed and all uses refer to the
+ * export instead. Identical types and constants are merged, and the ids
+ * of the output are compacted.
+ *
+ * If `keep_exports` is set, the export decorations are kept so the
+ * output can be linked again, otherwise they are removed along with the
+ * `Linkage` capability.
+ */
+pub fn link(modules: &[RawModule], keep_exports: bool) -> Result<RawModule> {
+    if modules.len() == 0 {
+        return Err(LinkError::NoModules);
+    }
+
+    let mut sections = Sections::default();
+    let mut ids = HashMap::new();
+
+    let mut base = 0;
+    for module in modules {
+        try!(sections.add(module, base, &mut ids));
+        base += module.header().id_bound;
+    }
+
+    let removed = try!(sections.resolve(&mut ids));
+    let instructions = sections.finish(&ids, &removed, keep_exports);
+
+    let mut header = modules[0].header().clone();
+    for module in &modules[1..] {
+        header.version = std::cmp::max(header.version, module.header().version);
+    }
+    header.id_bound = base;
+
+    Ok(compact_ids(&RawModule::from_instructions(header, instructions)))
+}
+
+/// The instructions of all the modules, split into the sections of the
+/// output module
+#[derive(Default)]
+struct Sections {
+    capabilities: Vec<desc::Capability>,
+    extensions: Vec<String>,
+    ext_imports: HashMap<String, Id>,
+    memory_model: Option<Instruction>,
+    entry_point_names: HashSet<(desc::ExecutionModel, String)>,
+    entry_points: Vec<Instruction>,
+    execution_modes: Vec<Instruction>,
+    debug_sources: Vec<Instruction>,
+    debug_names: Vec<Instruction>,
+    debug_processed: Vec<Instruction>,
+    annotations: Vec<Instruction>,
+    globals: Vec<Instruction>,
+    functions: Vec<Instruction>
+}
+
+impl Sections {
+    fn add(&mut self, module: &RawModule, base: u32, ids: &mut HashMap<Id, Id>) -> Result<()> {
+        let mut in_function = false;
+
+        for inst in module.instructions() {
+            // Shift the ids so they don't overlap with the ids of any
+            // other module
+            let mut inst = inst.clone();
+            inst.map_ids(|id| if id.is_valid() { Id(id.0 + base) } else { id });
+
+            if in_function {
+                if let Instruction::FunctionEnd = inst {
+                    in_function = false;
+                }
+                self.functions.push(inst);
+                continue;
+            }
+
+            match inst {
+                Instruction::Function { .. } => {
+                    in_function = true;
+                    self.functions.push(inst);
+                }
+                Instruction::Capability { capability } => {
+                    if !self.capabilities.contains(&capability) {
+                        self.capabilities.push(capability);
+                    }
+                }
+                Instruction::Extension { name } => {
+                    if !self.extensions.contains(&name) {
+                        self.extensions.push(name);
+                    }
+                }
+                Instruction::ExtInstImport { result_id, ref name } => {
+                    match self.ext_imports.entry(name.clone()) {
+                        Entry::Occupied(e) => {
+                            ids.insert(result_id.into(), *e.get());
+                        }
+                        Entry::Vacant(e) => {
+                            e.insert(result_id.into());
+                        }
+                    }
+                }
+                Instruction::MemoryModel { addressing_model, memory_model } => {
+                    if let Some(Instruction::MemoryModel {
+                        addressing_model: a, memory_model: m
+                    }) = self.memory_model {
+                        if a != addressing_model || m != memory_model {
+                            return Err(LinkError::MemoryModelMismatch);
+                        }
+                    }
+                    self.memory_model = Some(inst.clone());
+                }
+                Instruction::EntryPoint { execution_model, ref name, .. } => {
+                    if !self.entry_point_names.insert((execution_model, name.clone())) {
+                        return Err(LinkError::DuplicateEntryPoint(name.clone()));
+                    }
+                    self.entry_points.push(inst.clone());
+                }
+                Instruction::ExecutionMode { .. } => self.execution_modes.push(inst),
+                Instruction::SourceContinued { .. } |
+                Instruction::Source { .. } |
+                Instruction::SourceExtension { .. } |
+                Instruction::String { .. } => self.debug_sources.push(inst),
+                Instruction::Name { .. } |
+                Instruction::MemberName { .. } => self.debug_names.push(inst),
+                Instruction::ModuleProcessed { .. } => self.debug_processed.push(inst),
+                Instruction::Decorate { .. } |
+                Instruction::MemberDecorate { .. } |
+                Instruction::DecorationGroup { .. } |
+                Instruction::GroupDecorate { .. } |
+                Instruction::GroupMemberDecorate { .. } => self.annotations.push(inst),
+                inst => self.globals.push(inst)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges identical types and constants, and resolves imports to
+    /// exports. Returns the set of ids whose definitions are removed.
+    fn resolve(&mut self, ids: &mut HashMap<Id, Id>) -> Result<HashSet<Id>> {
+        let mut removed = HashSet::new();
+
+        // Decorations are part of the identity of a type. Ids decorated
+        // through groups or forward declared are left alone.
+        let mut decorations : HashMap<Id, Vec<Vec<u32>>> = HashMap::new();
+        let mut fixed = HashSet::new();
+        let mut exports = HashMap::new();
+        let mut imports = Vec::new();
+
+        for inst in &self.annotations {
+            let target = match *inst {
+                Instruction::Decorate { target, ref decoration } => {
+                    if let Decoration::LinkageAttributes(ref name, ty) = *decoration {
+                        if ty == desc::LinkageType::Export {
+                            if exports.insert(name.clone(), target).is_some() {
+                                return Err(LinkError::DuplicateSymbol(name.clone()));
+                            }
+                        } else {
+                            imports.push((name.clone(), target));
+                        }
+                    }
+                    target
+                }
+                Instruction::MemberDecorate { target, .. } => target.into(),
+                Instruction::GroupDecorate { ref targets, .. } => {
+                    fixed.extend(targets.iter().cloned());
+                    continue;
+                }
+                Instruction::GroupMemberDecorate { ref targets, .. } => {
+                    fixed.extend(targets.iter().map(|t| Id::from(t.0)));
+                    continue;
+                }
+                _ => continue
+            };
+
+            let mut key = inst.clone();
+            key.map_ids(|id| if id == target { Id(0) } else { id });
+            let mut words = Vec::new();
+            encode_instruction(&key, &mut words);
+            decorations.entry(target).or_insert_with(Vec::new).push(words);
+        }
+
+        let mut types = HashMap::new();
+        let mut seen = HashMap::new();
+        for inst in &self.globals {
+            if let Instruction::TypeForwardPointer { type_id, .. } = *inst {
+                fixed.insert(type_id.into());
+            }
+            if let Instruction::Variable { result_type, result_id, .. } = *inst {
+                types.insert(Id::from(result_id), result_type);
+            }
+        }
+
+        for inst in &self.globals {
+            let def = match inst.defines() {
+                Some(def) if is_mergeable(inst) && !fixed.contains(&def) => def,
+                _ => continue
+            };
+
+            let mut key = inst.clone();
+            key.map_ids(|id| {
+                if id == def { Id(0) } else { ids.get(&id).cloned().unwrap_or(id) }
+            });
+            let mut words = Vec::new();
+            encode_instruction(&key, &mut words);
+            if let Some(decorations) = decorations.get_mut(&def) {
+                decorations.sort();
+                for d in decorations.iter() {
+                    words.extend_from_slice(d);
+                }
+            }
+
+            match seen.entry(words) {
+                Entry::Occupied(e) => {
+                    ids.insert(def, *e.get());
+                    removed.insert(def);
+                }
+                Entry::Vacant(e) => {
+                    e.insert(def);
+                }
+            }
+        }
+
+        // Functions that are imported are declarations without a body,
+        // which are removed along with their parameters
+        let mut declaration = Vec::new();
+        let import_ids : HashSet<_> = imports.iter().map(|&(_, id)| id).collect();
+        for inst in &self.functions {
+            match *inst {
+                Instruction::Function { result_id, fn_ty, .. } => {
+                    let id = Id::from(result_id);
+                    types.insert(id, fn_ty);
+                    if import_ids.contains(&id) {
+                        declaration.push(id);
+                    }
+                }
+                Instruction::FunctionParameter { result_id, .. } => {
+                    if declaration.len() > 0 {
+                        declaration.push(result_id.into());
+                    }
+                }
+                Instruction::FunctionEnd => {
+                    removed.extend(declaration.drain(..));
+                }
+                _ => {
+                    // Not a declaration after all
+                    declaration.clear();
+                }
+            }
+        }
+
+        let type_of = |id: Id, ids: &HashMap<Id, Id>| {
+            types.get(&id).map(|&ty| {
+                ids.get(&Id::from(ty)).cloned().unwrap_or(Id::from(ty))
+            })
+        };
+
+        for (name, import) in imports {
+            let export = match exports.get(&name) {
+                Some(&export) => export,
+                None => return Err(LinkError::UnresolvedSymbol(name))
+            };
+
+            if type_of(import, ids) != type_of(export, ids) {
+                return Err(LinkError::SymbolTypeMismatch(name));
+            }
+
+            ids.insert(import, export);
+            removed.insert(import);
+        }
+
+        Ok(removed)
+    }
+
+    /// Builds the instructions of the linked module
+    fn finish(self, ids: &HashMap<Id, Id>, removed: &HashSet<Id>,
+              keep_exports: bool) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+
+        let mut annotations = Vec::with_capacity(self.annotations.len());
+        let mut linkage = false;
+        for inst in self.annotations {
+            match inst {
+                Instruction::Decorate { target, ref decoration } => {
+                    if removed.contains(&target) { continue; }
+                    if let Decoration::LinkageAttributes(..) = *decoration {
+                        if !keep_exports { continue; }
+                        linkage = true;
+                    }
+                }
+                Instruction::MemberDecorate { target, .. } => {
+                    if removed.contains(&target.into()) { continue; }
+                }
+                Instruction::GroupDecorate { grp, ref targets } => {
+                    let targets : Vec<_> = targets.iter().cloned()
+                        .filter(|t| !removed.contains(t)).collect();
+                    if targets.len() > 0 {
+                        annotations.push(Instruction::GroupDecorate {
+                            grp: grp,
+                            targets: targets.into_boxed_slice()
+                        });
+                    }
+                    continue;
+                }
+                Instruction::GroupMemberDecorate { grp, ref targets } => {
+                    let targets : Vec<_> = targets.iter().cloned()
+                        .filter(|t| !removed.contains(&t.0.into())).collect();
+                    if targets.len() > 0 {
+                        annotations.push(Instruction::GroupMemberDecorate {
+                            grp: grp,
+                            targets: targets.into_boxed_slice()
+                        });
+                    }
+                    continue;
+                }
+                _ => ()
+            }
+            annotations.push(inst);
+        }
+
+        for capability in self.capabilities {
+            if capability == desc::Capability::Linkage && !linkage { continue; }
+            instructions.push(Instruction::Capability { capability: capability });
+        }
+        for name in self.extensions {
+            instructions.push(Instruction::Extension { name: name });
+        }
+
+        let mut ext_imports : Vec<_> = self.ext_imports.into_iter().collect();
+        ext_imports.sort_by_key(|&(_, id)| id.0);
+        for (name, id) in ext_imports {
+            instructions.push(Instruction::ExtInstImport {
+                result_id: id.to_result_id(),
+                name: name
+            });
+        }
+
+        instructions.extend(self.memory_model);
+        instructions.extend(self.entry_points);
+        instructions.extend(self.execution_modes);
+        instructions.extend(self.debug_sources);
+
+        for inst in self.debug_names {
+            match inst {
+                Instruction::Name { id, .. } => {
+                    if removed.contains(&id) { continue; }
+                }
+                Instruction::MemberName { type_id, .. } => {
+                    if removed.contains(&type_id.into()) { continue; }
+                }
+                _ => ()
+            }
+            instructions.push(inst);
+        }
+
+        instructions.extend(self.debug_processed);
+        instructions.extend(annotations);
+
+        for inst in self.globals {
+            if let Some(def) = inst.defines() {
+                if removed.contains(&def) { continue; }
+            }
+            instructions.push(inst);
+        }
+
+        let mut skip = false;
+        for inst in self.functions {
+            if let Instruction::Function { result_id, .. } = inst {
+                skip = removed.contains(&result_id.into());
+            }
+            let end = if let Instruction::FunctionEnd = inst { true } else { false };
+            if !skip {
+                instructions.push(inst);
+            }
+            if end {
+                skip = false;
+            }
+        }
+
+        for inst in instructions.iter_mut() {
+            inst.map_ids(|id| ids.get(&id).cloned().unwrap_or(id));
+        }
+
+        instructions
+    }
+}
+
+/// Whether the instruction is a type or constant that can be merged with
+/// an identical one
+fn is_mergeable(inst: &Instruction) -> bool {
+    match *inst {
+        Instruction::ConstantTrue { .. } |
+        Instruction::ConstantFalse { .. } |
+        Instruction::Constant { .. } |
+        Instruction::ConstantComposite { .. } |
+        Instruction::ConstantSampler { .. } |
+        Instruction::ConstantNull { .. } => true,
+        _ => inst.defines_type().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc;
+    use instruction::{Decoration, Instruction};
+    use validate::validate;
+    use RawModule;
+
+    use super::{link, LinkError};
+
+    const MAIN : &'static str = "
+        OpCapability Shader
+        OpCapability Linkage
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Fragment %main \"main\"
+        OpExecutionMode %main OriginUpperLeft
+        OpSource GLSL 450
+        OpName %main \"main\"
+        OpModuleProcessed \"main\"
+        OpDecorate %f LinkageAttributes \"f\" Import
+        OpDecorate %arr ArrayStride 4
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %fn_float = OpTypeFunction %float
+        %uint = OpTypeInt 32 0
+        %four = OpConstant %uint 4
+        %arr = OpTypeArray %float %four
+        %f = OpFunction %float None %fn_float
+        OpFunctionEnd
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        %x = OpFunctionCall %float %f
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    const LIBRARY : &'static str = "
+        OpCapability Shader
+        OpCapability Linkage
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint GLCompute %k \"k\"
+        OpExecutionMode %k LocalSize 1 1 1
+        OpSource GLSL 450
+        OpName %f \"f\"
+        OpModuleProcessed \"library\"
+        OpDecorate %f LinkageAttributes \"f\" Export
+        OpDecorate %arr ArrayStride 4
+        OpDecorate %arr16 ArrayStride 16
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %fn_float = OpTypeFunction %float
+        %uint = OpTypeInt 32 0
+        %four = OpConstant %uint 4
+        %one = OpConstant %float 1
+        %arr = OpTypeArray %float %four
+        %arr16 = OpTypeArray %float %four
+        %f = OpFunction %float None %fn_float
+        %f_entry = OpLabel
+        OpReturnValue %one
+        OpFunctionEnd
+        %k = OpFunction %void None %fn_void
+        %k_entry = OpLabel
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    fn modules(main: &str, library: &str) -> Vec<RawModule> {
+        vec![assemble(main).unwrap(), assemble(library).unwrap()]
+    }
+
+    fn count(module: &RawModule, op: desc::Op) -> usize {
+        module.instructions().iter().filter(|inst| inst.opcode() == op as u16).count()
+    }
+
+    fn positions(module: &RawModule, op: desc::Op) -> Vec<usize> {
+        module.instructions().iter().enumerate()
+            .filter(|&(_, inst)| inst.opcode() == op as u16).map(|(i, _)| i).collect()
+    }
+
+    fn linkage(module: &RawModule) -> Vec<desc::LinkageType> {
+        module.instructions().iter().filter_map(|inst| match *inst {
+            Instruction::Decorate { decoration: Decoration::LinkageAttributes(_, ty), .. } => {
+                Some(ty)
+            }
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn imports_resolve_to_exports() {
+        let linked = link(&modules(MAIN, LIBRARY), false).unwrap();
+        assert!(validate(&linked).is_empty());
+
+        // The declaration is gone and the call goes to the export
+        assert_eq!(count(&linked, desc::Op::Function), 3);
+        let call = linked.instructions().iter().filter_map(|inst| match *inst {
+            Instruction::FunctionCall { func, .. } => Some(func),
+            _ => None
+        }).next().unwrap();
+        let body = linked.def_index(call).unwrap() + 1;
+        assert_eq!(linked.instructions()[body].opcode(), desc::Op::Label as u16);
+
+        assert_eq!(linkage(&linked), vec![]);
+        assert_eq!(count(&linked, desc::Op::Capability), 1);
+    }
+
+    #[test]
+    fn keep_exports() {
+        let linked = link(&modules(MAIN, LIBRARY), true).unwrap();
+        assert!(validate(&linked).is_empty());
+
+        // Only the export is left, so the output can be linked again
+        assert_eq!(linkage(&linked), vec![desc::LinkageType::Export]);
+        assert_eq!(count(&linked, desc::Op::Capability), 2);
+    }
+
+    #[test]
+    fn types_and_constants_are_merged() {
+        let linked = link(&modules(MAIN, LIBRARY), false).unwrap();
+
+        assert_eq!(count(&linked, desc::Op::TypeVoid), 1);
+        assert_eq!(count(&linked, desc::Op::TypeFloat), 1);
+        assert_eq!(count(&linked, desc::Op::TypeFunction), 2);
+        assert_eq!(count(&linked, desc::Op::Constant), 2);
+
+        // Arrays with the same decorations are merged, but not ones with
+        // different decorations
+        assert_eq!(count(&linked, desc::Op::TypeArray), 2);
+        assert_eq!(count(&linked, desc::Op::Decorate), 2);
+    }
+
+    #[test]
+    fn section_order() {
+        let linked = link(&modules(MAIN, LIBRARY), false).unwrap();
+
+        // Both entry points come before both execution modes, and so on
+        // through the debug sections
+        let order = [desc::Op::EntryPoint, desc::Op::ExecutionMode, desc::Op::Source,
+                     desc::Op::Name, desc::Op::ModuleProcessed, desc::Op::Decorate];
+        let mut last = 0;
+        for &op in &order {
+            let pos = positions(&linked, op);
+            assert_eq!(pos.len(), 2);
+            assert!(pos[0] > last, "{:?} is out of order", op);
+            last = pos[1];
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mismatch = LIBRARY.replace("%f = OpFunction %float None %fn_float",
+                                       "%f = OpFunction %void None %fn_void")
+            .replace("OpReturnValue %one", "OpReturn");
+        match link(&modules(MAIN, &mismatch), false) {
+            Err(LinkError::SymbolTypeMismatch(ref name)) if name == "f" => (),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+
+        let unresolved = LIBRARY.replace("\"f\" Export", "\"g\" Export");
+        match link(&modules(MAIN, &unresolved), false) {
+            Err(LinkError::UnresolvedSymbol(ref name)) if name == "f" => (),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+
+        let duplicate = LIBRARY.replace("\"k\"", "\"main\"").replace("GLCompute", "Fragment")
+            .replace("LocalSize 1 1 1", "OriginUpperLeft");
+        match link(&modules(MAIN, &duplicate), false) {
+            Err(LinkError::DuplicateEntryPoint(ref name)) if name == "main" => (),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+
+        match link(&[], false) {
+            Err(LinkError::NoModules) => (),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+    }
+}
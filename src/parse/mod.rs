@@ -9,13 +9,20 @@
 use std::{self, fmt, error};
 use std::io;
 
-use desc::Id;
+use desc::{self, Id};
+use diagnostic::Diagnostic;
+use instruction::{operand_names, Instruction};
 
 mod parser;
 mod read;
+mod slice;
 
-pub use self::read::Reader;
-pub use self::parser::parse_raw_instruction;
+pub use self::read::{Reader, ReaderLimits, StreamInstructions, StreamedInstruction};
+pub use self::parser::{parse_instruction, parse_raw_instruction};
+pub use self::slice::{parse_words, parse_words_with_limits, words_from_bytes, InstructionRef, Instructions};
+
+/// The first word of every module
+pub const MAGIC_NUMBER : u32 = 0x07230203;
 
 
 #[derive(Clone, Debug)]
@@ -25,6 +32,7 @@ pub struct RawInstruction {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Header {
     pub version: (u8, u8),
     pub generator_id: u32,
@@ -42,7 +50,151 @@ pub enum ParseError {
     InvalidParamValue(u32, &'static str),
     InstructionTooShort,
     IoError(io::Error),
-    InvalidMagicNumber(u32)
+    InvalidMagicNumber(u32),
+    HeaderTooShort,
+    /// The module isn't a whole number of words, the value is the number
+    /// of bytes left over
+    TrailingBytes(usize),
+    /// An error in an operand of an instruction, with the opcode and the
+    /// index of the operand in `core.desc`
+    Operand(u16, usize, Box<ParseError>),
+    /// The module ended in the middle of an instruction
+    TruncatedInstruction,
+    /// An instruction has a word count of zero
+    ZeroWordCount,
+    /// The id bound in the header is larger than the reader's limit
+    IdBoundTooLarge(u32),
+    /// The module has more words than the reader's limit
+    ModuleTooLarge(usize),
+    /// An error in the instruction at the given offset in words from the
+    /// start of the module
+    At(usize, Box<ParseError>)
+}
+
+impl ParseError {
+    /**
+     * Gives the offset in words of the instruction the error is in. If
+     * the error already has an offset, it's kept.
+     */
+    pub fn at(self, offset: usize) -> ParseError {
+        match self {
+            ParseError::At(..) => self,
+            e => ParseError::At(offset, Box::new(e))
+        }
+    }
+
+    /**
+     * Gets the offset in words of the instruction the error is in, if
+     * it's known
+     */
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            ParseError::At(offset, _) => Some(offset),
+            _ => None
+        }
+    }
+
+    /**
+     * Gets the opcode of the instruction and the index of the operand in
+     * `core.desc` the error is in, if they're known
+     */
+    pub fn operand(&self) -> Option<(u16, usize)> {
+        match *self {
+            ParseError::At(_, ref e) => e.operand(),
+            ParseError::Operand(opcode, operand, _) => Some((opcode, operand)),
+            _ => None
+        }
+    }
+
+    /**
+     * Gets a diagnostic for the error, with the offset, opcode and
+     * operand it's in if they're known
+     */
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new("parsing", Message(self.root()).to_string());
+        diagnostic.offset = self.offset();
+        if let Some((opcode, operand)) = self.operand() {
+            diagnostic.opcode = Some(opcode);
+            diagnostic.operand = Some(operand);
+        } else if let ParseError::UnknownOpcode(opcode) = *self.root() {
+            diagnostic.opcode = Some(opcode);
+        }
+        diagnostic
+    }
+
+    /**
+     * Gets the error without the offset or operand it's in
+     */
+    pub fn root(&self) -> &ParseError {
+        match *self {
+            ParseError::At(_, ref e) |
+            ParseError::Operand(_, _, ref e) => e.root(),
+            _ => self
+        }
+    }
+}
+
+/// An error in one instruction of a module read with
+/// `RawModule::read_module_lenient`
+#[derive(Debug)]
+pub struct InstructionError {
+    /// The index of the instruction in the module
+    pub index: usize,
+    pub error: ParseError
+}
+
+impl InstructionError {
+    /**
+     * Gets a diagnostic for the error, including the index of the
+     * instruction
+     */
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = self.error.diagnostic();
+        diagnostic.index = Some(self.index);
+        diagnostic
+    }
+}
+
+impl fmt::Display for InstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Error while parsing instruction {}: ", self.index));
+        self.error.write_message(f)
+    }
+}
+
+impl error::Error for InstructionError {
+    fn description(&self) -> &str {
+        error::Error::description(&self.error)
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        Some(&self.error)
+    }
+}
+
+/**
+ * Checks that the ids defined and used by the instruction, including its
+ * result type, are below the id bound from the header
+ */
+pub fn check_ids(inst: &Instruction, id_bound: u32) -> Result<()> {
+    if let Some(id) = inst.defines() {
+        if id.0 >= id_bound {
+            return Err(ParseError::IdOutOfRange(id));
+        }
+    }
+    if let Some(ty) = inst.type_id_of() {
+        if ty.0 >= id_bound {
+            return Err(ParseError::IdOutOfRange(ty.into()));
+        }
+    }
+    // Missing optional ids are given as zero
+    for id in inst.uses_iter() {
+        if id.0 != 0 && id.0 >= id_bound {
+            return Err(ParseError::IdOutOfRange(id));
+        }
+    }
+
+    Ok(())
 }
 
 impl From<io::Error> for ParseError {
@@ -53,9 +205,15 @@ impl From<io::Error> for ParseError {
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("Error while parsing: "));
+        self.write_message(f)
+    }
+}
+
+impl ParseError {
+    fn write_message(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::ParseError::*;
 
-        try!(f.write_str("Error while parsing: "));
         match *self {
             DuplicateId(id, idx) => {
                 write!(f, "duplicate definition of id `{:?}` at instruction {}",
@@ -76,10 +234,44 @@ impl fmt::Display for ParseError {
             InvalidMagicNumber(n) => {
                 write!(f, "invalid magic number: {:#08x}", n)
             }
+            HeaderTooShort => f.write_str("module is too short to have a header"),
+            TrailingBytes(n) => {
+                write!(f, "module has {} bytes left over after the last word", n)
+            }
+            TruncatedInstruction => f.write_str("module ends in the middle of an instruction"),
+            ZeroWordCount => f.write_str("instruction has a word count of zero"),
+            IdBoundTooLarge(bound) => {
+                write!(f, "id bound `{}` is larger than allowed", bound)
+            }
+            ModuleTooLarge(max) => {
+                write!(f, "module is larger than the limit of {} words", max)
+            }
+            Operand(opcode, operand, ref e) => {
+                match (desc::Op::from(opcode), operand_names(opcode).get(operand)) {
+                    (Some(op), Some(name)) => {
+                        try!(write!(f, "in operand `{}` of Op{}: ", name, op.name()));
+                    }
+                    _ => try!(write!(f, "in operand {} of opcode {}: ", operand, opcode))
+                }
+                e.write_message(f)
+            }
+            At(offset, ref e) => {
+                try!(write!(f, "at word {}: ", offset));
+                e.write_message(f)
+            }
         }
     }
 }
 
+/// Displays an error without the "Error while parsing" prefix
+struct Message<'a>(&'a ParseError);
+
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write_message(f)
+    }
+}
+
 impl error::Error for ParseError {
     fn description(&self) -> &str {
         use self::ParseError::*;
@@ -90,7 +282,15 @@ impl error::Error for ParseError {
             InvalidParamValue(_, _) => "parameter value not valid for type",
             InstructionTooShort => "instruction is too short",
             IoError(ref e) => e.description(),
-            InvalidMagicNumber(_) => "invalid magic number"
+            InvalidMagicNumber(_) => "invalid magic number",
+            HeaderTooShort => "module too short for header",
+            TrailingBytes(_) => "module not a whole number of words",
+            TruncatedInstruction => "module ends mid-instruction",
+            ZeroWordCount => "instruction word count is zero",
+            IdBoundTooLarge(_) => "id bound too large",
+            ModuleTooLarge(_) => "module too large",
+            Operand(_, _, ref e) |
+            At(_, ref e) => e.description()
         }
     }
 
@@ -98,6 +298,8 @@ impl error::Error for ParseError {
         use self::ParseError::*;
         match *self {
             IoError(ref e) => Some(e),
+            Operand(_, _, ref e) |
+            At(_, ref e) => Some(&**e),
             _ => None
         }
     }
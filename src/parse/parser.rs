@@ -15,7 +15,12 @@ use instruction::{self, Instruction, Decoration, ExecutionMode, ImageOperands};
 
 include!(concat!(env!("OUT_DIR"), "/inst_parser.rs"));
 
+pub fn parse_raw_instruction(raw_inst: RawInstruction) -> Result<Instruction> {
+    parse_instruction(raw_inst.opcode, &raw_inst.params)
+}
+
 struct InstructionParser<'a> {
+    opcode: u16,
     params: &'a [u32]
 }
 
@@ -41,6 +46,13 @@ impl<'a> InstructionParser<'a> {
     fn parse<T: ParamParse>(&mut self) -> Result<T> {
         T::parse(self)
     }
+
+    /// Parses the operand with the given index in `core.desc`, giving
+    /// any error the opcode and operand it's in
+    fn parse_operand<T: ParamParse>(&mut self, operand: usize) -> Result<T> {
+        let opcode = self.opcode;
+        T::parse(self).map_err(|e| ParseError::Operand(opcode, operand, Box::new(e)))
+    }
 }
 
 trait ParamParse : Sized {
@@ -6,111 +6,490 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std;
-use std::io::{Read};
 
-use super::{Result, RawInstruction, Header};
+use std::io::{self, Read};
 
-const MAGIC_NUMBER : u32 = 0x07230203;
+use instruction::Instruction;
+
+use super::{Result, RawInstruction, Header, ParseError, MAGIC_NUMBER};
+use super::parser::parse_instruction;
+
+/// Limits on the size of the modules a `Reader` will accept, to protect
+/// against untrusted input
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReaderLimits {
+    /// The most words a module can have, including the header
+    pub max_words: usize,
+    /// The largest id bound a module can have. Modules are usually
+    /// stored with a table indexed by id, so this limits how much
+    /// memory that table can use.
+    pub max_id_bound: u32
+}
+
+impl ReaderLimits {
+    /**
+     * Checks the header against the limits
+     */
+    pub fn check_header(&self, header: &Header) -> Result<()> {
+        if header.id_bound > self.max_id_bound {
+            Err(ParseError::IdBoundTooLarge(header.id_bound))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The default limits accept any module that is within the universal
+/// limits in the specification, which allows ids up to 4,194,303.
+impl Default for ReaderLimits {
+    fn default() -> ReaderLimits {
+        ReaderLimits {
+            max_words: usize::max_value(),
+            max_id_bound: 0x400000
+        }
+    }
+}
 
 pub struct Reader<R: ?Sized + Read> {
     need_swap: bool,
-    is_eof: bool,
-    read_header: bool,
+    header: Option<Header>,
+    offset: usize,
+    limits: ReaderLimits,
     reader: R
 }
 
 impl<R: Read> Reader<R> {
-    pub fn new(mut reader: R) -> Result<Reader<R>> {
-        let mut word : u32 = 0;
-        unsafe {
-            let buf : &mut [u8;4] = std::mem::transmute(&mut word);
-            try!(reader.read(buf));
+    pub fn new(reader: R) -> Result<Reader<R>> {
+        Reader::with_limits(reader, ReaderLimits::default())
+    }
+
+    /**
+     * Creates a reader that fails if the module goes over the given
+     * limits
+     */
+    pub fn with_limits(mut reader: R, limits: ReaderLimits) -> Result<Reader<R>> {
+        let mut buf = [0; 4];
+        if try!(read_fully(&mut reader, &mut buf)) != 4 {
+            return Err(ParseError::HeaderTooShort);
         }
+        let word = u32::from_ne_bytes(buf);
 
         let need_swap = if word == MAGIC_NUMBER {
             false
         } else if word.swap_bytes() == MAGIC_NUMBER {
             true
         } else {
-            return Err(super::ParseError::InvalidMagicNumber(word));
+            return Err(ParseError::InvalidMagicNumber(word));
         };
 
         Ok(Reader {
             need_swap: need_swap,
-            is_eof: false,
-            read_header: false,
+            header: None,
+            offset: 1,
+            limits: limits,
             reader: reader
         })
     }
 }
 
+/// Reads until the buffer is full or the end of the input is reached,
+/// returning the number of bytes read
+fn read_fully<R: ?Sized + Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(n)
+}
+
 impl<R: ?Sized + Read> Reader<R> {
-    fn read_word(&mut self) -> Result<u32> {
-        if self.is_eof { return Ok(0); }
-
-        let mut word : u32 = 0;
-        unsafe {
-            let buf : &mut [u8;4] = std::mem::transmute(&mut word);
-            let n = try!(self.reader.read(buf));
-            if n != 4 {
-                self.is_eof = true;
-                return Ok(0);
-            }
+    /// Reads the next word, or `None` if the input ended before it
+    fn read_word(&mut self) -> Result<Option<u32>> {
+        let mut buf = [0; 4];
+        match try!(read_fully(&mut self.reader, &mut buf)) {
+            0 => return Ok(None),
+            4 => (),
+            n => return Err(ParseError::TrailingBytes(n))
+        }
+
+        if self.offset >= self.limits.max_words {
+            return Err(ParseError::ModuleTooLarge(self.limits.max_words));
         }
+        self.offset += 1;
 
+        let word = u32::from_ne_bytes(buf);
         if self.need_swap {
-            Ok(word.swap_bytes())
+            Ok(Some(word.swap_bytes()))
         } else {
-            Ok(word)
+            Ok(Some(word))
         }
     }
 
+    /// Reads the next word, failing with the given error if the input
+    /// ended before it
+    fn expect_word(&mut self, err: ParseError) -> Result<u32> {
+        match try!(self.read_word()) {
+            Some(word) => Ok(word),
+            None => Err(err)
+        }
+    }
+
+    /**
+     * Gets the offset in words from the start of the module of the next
+     * word to be read
+     */
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /**
+     * Gets the limits the reader checks the module against
+     */
+    pub fn limits(&self) -> &ReaderLimits {
+        &self.limits
+    }
+
+    /**
+     * Skips over the given number of words, failing if the input ends
+     * first
+     */
+    pub fn skip_words(&mut self, count: usize) -> Result<()> {
+        for _ in 0..count {
+            try!(self.expect_word(ParseError::TruncatedInstruction));
+        }
+        Ok(())
+    }
+
+    /**
+     * Iterates over the remaining instructions without decoding them,
+     * reading the header first if it hasn't been read. The operands are
+     * only read for instructions with an opcode accepted by `filter`,
+     * the operands of the others are skipped.
+     */
+    pub fn instructions<F: FnMut(u16) -> bool>(&mut self, filter: F) -> StreamInstructions<'_, R, F> {
+        StreamInstructions {
+            reader: self,
+            filter: filter,
+            done: false
+        }
+    }
+
+    /**
+     * Reads the header, or gets it if it has already been read
+     */
     pub fn read_header(&mut self) -> Result<Header> {
-        assert!(!self.read_header, "Already read header");
-        let version = try!(self.read_word());
+        if let Some(ref header) = self.header {
+            return Ok(header.clone());
+        }
+
+        let version = try!(self.expect_word(ParseError::HeaderTooShort));
         let major = (version >> 16) as u8;
         let minor = ((version >> 8) & 0xFF) as u8;
 
-        let generator_id = try!(self.read_word());
-        let id_bound = try!(self.read_word());
+        let generator_id = try!(self.expect_word(ParseError::HeaderTooShort));
+        let id_bound = try!(self.expect_word(ParseError::HeaderTooShort));
 
         // Skip reserved word
-        try!(self.read_word());
-        self.read_header = true;
+        try!(self.expect_word(ParseError::HeaderTooShort));
 
-        Ok(Header {
+        let header = Header {
             version: (major, minor),
             generator_id: generator_id,
             id_bound: id_bound
-        })
+        };
+        try!(self.limits.check_header(&header));
+        self.header = Some(header.clone());
+
+        Ok(header)
     }
 
     pub fn skip_header(&mut self) -> Result<()> {
-        if !self.read_header {
-            self.read_header().map(|_| ())
-        } else {
-            Ok(())
-        }
+        self.read_header().map(|_| ())
     }
 
+    /**
+     * Reads the next instruction without decoding it, reading the header
+     * first if it hasn't been read. Returns `None` at the end of the
+     * module.
+     */
     pub fn read_instruction(&mut self) -> Result<Option<RawInstruction>> {
-        assert!(self.read_header, "Header needs to be read");
-        let op = try!(self.read_word());
-        if self.is_eof { return Ok(None); }
+        try!(self.skip_header());
+        let inst = try!(self.next_instruction(|_| true));
 
-        let code = (op & 0xFFFF) as u16;
-        let count = op >> 16;
-        let mut params = Vec::with_capacity(count as usize);
+        Ok(inst.map(|inst| RawInstruction {
+            opcode: inst.opcode,
+            params: inst.operands.unwrap_or_else(Vec::new)
+        }))
+    }
+
+    /// Reads the next instruction, only keeping the operands if `keep`
+    /// accepts the opcode. Errors are given the offset of the
+    /// instruction.
+    fn next_instruction<F: FnMut(u16) -> bool>(&mut self, keep: F)
+                                               -> Result<Option<StreamedInstruction>> {
+        let offset = self.offset;
+        self.read_operands(offset, keep).map_err(|e| e.at(offset))
+    }
 
-        for _ in 1..count {
-            let p = try!(self.read_word());
-            params.push(p);
+    fn read_operands<F: FnMut(u16) -> bool>(&mut self, offset: usize, mut keep: F)
+                                            -> Result<Option<StreamedInstruction>> {
+        let first = match try!(self.read_word()) {
+            Some(word) => word,
+            None => return Ok(None)
+        };
+
+        let opcode = (first & 0xFFFF) as u16;
+        let word_count = (first >> 16) as u16;
+        if word_count == 0 {
+            return Err(ParseError::ZeroWordCount);
         }
+        let operand_count = word_count as usize - 1;
+
+        let operands = if keep(opcode) {
+            let mut operands = Vec::with_capacity(operand_count);
+            for _ in 0..operand_count {
+                operands.push(try!(self.expect_word(ParseError::TruncatedInstruction)));
+            }
+            Some(operands)
+        } else {
+            try!(self.skip_words(operand_count));
+            None
+        };
 
-        Ok(Some(RawInstruction {
-            opcode: code,
-            params: params
+        Ok(Some(StreamedInstruction {
+            offset: offset,
+            opcode: opcode,
+            word_count: word_count,
+            operands: operands
         }))
     }
 }
+
+/// An instruction read by `Reader::instructions`
+#[derive(Clone, Debug)]
+pub struct StreamedInstruction {
+    /// The offset in words of the instruction from the start of the module
+    pub offset: usize,
+    pub opcode: u16,
+    /// The number of words in the instruction, including the first
+    pub word_count: u16,
+    /// The operands, if the instruction wasn't skipped
+    pub operands: Option<Vec<u32>>
+}
+
+impl StreamedInstruction {
+    /**
+     * Decodes the instruction, if it wasn't skipped
+     */
+    pub fn decode(&self) -> Option<Result<Instruction>> {
+        self.operands.as_ref().map(|operands| {
+            parse_instruction(self.opcode, operands).map_err(|e| e.at(self.offset))
+        })
+    }
+}
+
+/// Iterator over the instructions in a `Reader`, see
+/// `Reader::instructions`
+pub struct StreamInstructions<'a, R: ?Sized + Read + 'a, F> {
+    reader: &'a mut Reader<R>,
+    filter: F,
+    done: bool
+}
+
+impl<'a, R: ?Sized + Read, F: FnMut(u16) -> bool> Iterator for StreamInstructions<'a, R, F> {
+    type Item = Result<StreamedInstruction>;
+
+    fn next(&mut self) -> Option<Result<StreamedInstruction>> {
+        if self.done { return None; }
+
+        let inst = self.reader.skip_header().and_then(|_| {
+            self.reader.next_instruction(&mut self.filter)
+        });
+        match inst {
+            Ok(Some(inst)) => Some(Ok(inst)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // Nothing after a bad instruction can be trusted
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parse::{ParseError, MAGIC_NUMBER};
+    use RawModule;
+
+    use super::{Reader, ReaderLimits};
+
+    /// A module with a header and a single `OpNop`, with the given id
+    /// bound
+    fn module(id_bound: u32) -> Vec<u32> {
+        vec![MAGIC_NUMBER, 0x00010000, 0, id_bound, 0, 1 << 16]
+    }
+
+    fn bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_ne_bytes().to_vec()).collect()
+    }
+
+    /// Reads every instruction, giving the error that stops it
+    fn read_all(bytes: &[u8], limits: ReaderLimits) -> ParseError {
+        let mut reader = Reader::with_limits(bytes, limits).unwrap();
+        loop {
+            match reader.read_instruction() {
+                Ok(Some(_)) => (),
+                Ok(None) => panic!("module was read without an error"),
+                Err(e) => return e
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        let mut words = module(1);
+        // An `OpName` with its string missing
+        words.extend_from_slice(&[3 << 16 | 5, 1]);
+        match read_all(&bytes(&words), ReaderLimits::default()) {
+            ParseError::At(6, ref e) => match **e {
+                ParseError::TruncatedInstruction => (),
+                ref e => panic!("unexpected {:?}", e)
+            },
+            e => panic!("unexpected {:?}", e)
+        }
+    }
+
+    #[test]
+    fn zero_word_count() {
+        let mut words = module(1);
+        words.push(5);
+        match *read_all(&bytes(&words), ReaderLimits::default()).root() {
+            ParseError::ZeroWordCount => (),
+            ref e => panic!("unexpected {:?}", e)
+        }
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = bytes(&module(1));
+        bytes.extend_from_slice(&[1, 2, 3]);
+        match *read_all(&bytes, ReaderLimits::default()).root() {
+            ParseError::TrailingBytes(3) => (),
+            ref e => panic!("unexpected {:?}", e)
+        }
+    }
+
+    #[test]
+    fn id_bound_too_large() {
+        // The default limit applies to `read_module`
+        let words = module(0x400001);
+        match RawModule::read_module(&bytes(&words)[..]) {
+            Err(ParseError::IdBoundTooLarge(0x400001)) => (),
+            r => panic!("unexpected {:?}", r.err())
+        }
+        assert!(RawModule::read_module(&bytes(&module(0x400000))[..]).is_ok());
+
+        let limits = ReaderLimits { max_id_bound: 10, .. ReaderLimits::default() };
+        match RawModule::read_module_with_limits(&bytes(&module(11))[..], limits) {
+            Err(ParseError::IdBoundTooLarge(11)) => (),
+            r => panic!("unexpected {:?}", r.err())
+        }
+    }
+
+    #[test]
+    fn module_too_large() {
+        let mut words = module(1);
+        words.push(1 << 16);
+        let limits = ReaderLimits { max_words: 6, .. ReaderLimits::default() };
+        match RawModule::read_module_with_limits(&bytes(&words)[..], limits) {
+            Err(ref e) => match *e.root() {
+                ParseError::ModuleTooLarge(6) => assert_eq!(e.offset(), Some(6)),
+                ref e => panic!("unexpected {:?}", e)
+            },
+            Ok(_) => panic!("module over the limit was read")
+        }
+
+        let limits = ReaderLimits { max_words: 7, .. ReaderLimits::default() };
+        assert!(RawModule::read_module_with_limits(&bytes(&words)[..], limits).is_ok());
+    }
+
+    #[test]
+    fn stream_offsets() {
+        let module = RawModule::load_module("examples/vert.spv").unwrap();
+        let words = module.to_words();
+        let bytes = bytes(&words);
+        let variable = ::desc::Op::Variable as u16;
+
+        let mut reader = Reader::new(&bytes[..]).unwrap();
+        let mut offset = 5;
+        let mut count = 0;
+        let insts = reader.instructions(|op| op == variable);
+        for (inst, expected) in insts.zip(module.instructions()) {
+            let inst = inst.unwrap();
+            assert_eq!(inst.offset, offset);
+            assert_eq!(inst.opcode as u32, words[offset] & 0xFFFF);
+            assert_eq!(inst.word_count as u32, words[offset] >> 16);
+
+            // Only the instructions accepted by the filter are kept
+            match inst.decode() {
+                Some(decoded) => {
+                    assert_eq!(inst.opcode, variable);
+                    assert_eq!(format!("{:?}", decoded.unwrap()), format!("{:?}", expected));
+                }
+                None => assert!(inst.opcode != variable)
+            }
+
+            offset += inst.word_count as usize;
+            count += 1;
+        }
+        assert_eq!(count, module.instructions().len());
+        assert_eq!(reader.offset(), words.len());
+    }
+
+    #[test]
+    fn stream_error_offset() {
+        let mut words = RawModule::load_module("examples/vert.spv").unwrap().to_words();
+        let end = words.len();
+        // An `OpName` with its string missing
+        words.extend_from_slice(&[3 << 16 | 5, 1]);
+        let bytes = bytes(&words);
+
+        let mut reader = Reader::new(&bytes[..]).unwrap();
+        let results : Vec<_> = reader.instructions(|_| false).collect();
+        match *results.last().unwrap() {
+            Err(ParseError::At(offset, ref e)) => {
+                assert_eq!(offset, end);
+                match **e {
+                    ParseError::TruncatedInstruction => (),
+                    ref e => panic!("unexpected {:?}", e)
+                }
+            }
+            ref r => panic!("unexpected {:?}", r)
+        }
+        assert!(results[..results.len() - 1].iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn skip_words() {
+        let bytes = bytes(&module(1));
+        let mut reader = Reader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.offset(), 1);
+        reader.skip_words(4).unwrap();
+        assert_eq!(reader.offset(), 5);
+
+        // Past the end of the module
+        match reader.skip_words(2) {
+            Err(ParseError::TruncatedInstruction) => assert_eq!(reader.offset(), 6),
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parsing of modules that are already in memory, without copying or
+//! allocating for each instruction.
+
+use std::borrow::Cow;
+
+use desc;
+use instruction::Instruction;
+
+use super::{Header, ParseError, ReaderLimits, Result, MAGIC_NUMBER};
+use super::parser::parse_instruction;
+
+/// A borrowed view of a single instruction in a word slice
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InstructionRef<'a> {
+    /// The offset in words of the instruction from the start of the module
+    pub offset: usize,
+    pub opcode: u16,
+    /// The number of words in the instruction, including the first
+    pub word_count: u16,
+    pub operands: &'a [u32]
+}
+
+impl<'a> InstructionRef<'a> {
+    /**
+     * Gets the opcode as an `Op`, if it is known
+     */
+    pub fn op(&self) -> Option<desc::Op> {
+        desc::Op::from(self.opcode)
+    }
+
+    /**
+     * Decodes the operands into an `Instruction`
+     */
+    pub fn decode(&self) -> Result<Instruction> {
+        parse_instruction(self.opcode, self.operands).map_err(|e| e.at(self.offset))
+    }
+}
+
+/// Iterator over the instructions in a word slice, see `parse_words`
+#[derive(Clone, Debug)]
+pub struct Instructions<'a> {
+    words: &'a [u32],
+    offset: usize
+}
+
+impl<'a> Instructions<'a> {
+    /**
+     * Gets the words that haven't been iterated over yet
+     */
+    pub fn remaining_words(&self) -> &'a [u32] {
+        self.words
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<InstructionRef<'a>>;
+
+    fn next(&mut self) -> Option<Result<InstructionRef<'a>>> {
+        let first = match self.words.first() {
+            Some(&w) => w,
+            None => return None
+        };
+
+        let word_count = (first >> 16) as u16;
+        let len = word_count as usize;
+        let err = if len == 0 {
+            Some(ParseError::ZeroWordCount)
+        } else if len > self.words.len() {
+            Some(ParseError::TruncatedInstruction)
+        } else {
+            None
+        };
+        if let Some(err) = err {
+            // Nothing after a bad instruction can be trusted
+            self.words = &[];
+            return Some(Err(err.at(self.offset)));
+        }
+
+        let inst = InstructionRef {
+            offset: self.offset,
+            opcode: (first & 0xFFFF) as u16,
+            word_count: word_count,
+            operands: &self.words[1..len]
+        };
+        self.words = &self.words[len..];
+        self.offset += len;
+
+        Some(Ok(inst))
+    }
+}
+
+/**
+ * Parses the header of a module held as words, returning it with an
+ * iterator over the instructions. The words must be in the native byte
+ * order, use `words_from_bytes` to get them from a binary of either
+ * byte order.
+ */
+pub fn parse_words<'a>(words: &'a [u32]) -> Result<(Header, Instructions<'a>)> {
+    parse_words_with_limits(words, ReaderLimits::default())
+}
+
+/**
+ * Like `parse_words`, but fails if the module goes over the given limits
+ */
+pub fn parse_words_with_limits<'a>(words: &'a [u32], limits: ReaderLimits)
+                                   -> Result<(Header, Instructions<'a>)> {
+    if words.len() > limits.max_words {
+        return Err(ParseError::ModuleTooLarge(limits.max_words));
+    }
+    if words.len() < 5 {
+        return Err(ParseError::HeaderTooShort);
+    }
+    if words[0] != MAGIC_NUMBER {
+        return Err(ParseError::InvalidMagicNumber(words[0]));
+    }
+
+    let version = words[1];
+    let header = Header {
+        version: ((version >> 16) as u8, ((version >> 8) & 0xFF) as u8),
+        generator_id: words[2],
+        id_bound: words[3]
+    };
+    try!(limits.check_header(&header));
+
+    Ok((header, Instructions { words: &words[5..], offset: 5 }))
+}
+
+/**
+ * Gets the words of a module from its bytes. If the bytes are suitably
+ * aligned and in the native byte order, such as from a buffer of `u32`s,
+ * they are borrowed, otherwise they are copied. Bytes from
+ * `include_bytes!` are usually copied, as they aren't guaranteed to be
+ * aligned.
+ */
+pub fn words_from_bytes<'a>(bytes: &'a [u8]) -> Result<Cow<'a, [u32]>> {
+    if bytes.len() < 20 {
+        return Err(ParseError::HeaderTooShort);
+    }
+    if bytes.len() % 4 != 0 {
+        return Err(ParseError::TrailingBytes(bytes.len() % 4));
+    }
+
+    let (before, words, after) = unsafe { bytes.align_to::<u32>() };
+    if before.is_empty() && after.is_empty() && words[0] == MAGIC_NUMBER {
+        return Ok(Cow::Borrowed(words));
+    }
+
+    let read_le = |b: &[u8]| {
+        (b[0] as u32) | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+    };
+    let magic = read_le(&bytes[..4]);
+    let swap = if magic == MAGIC_NUMBER {
+        false
+    } else if magic.swap_bytes() == MAGIC_NUMBER {
+        true
+    } else {
+        return Err(ParseError::InvalidMagicNumber(magic));
+    };
+
+    let words = bytes.chunks(4).map(|b| {
+        let w = read_le(b);
+        if swap { w.swap_bytes() } else { w }
+    }).collect();
+
+    Ok(Cow::Owned(words))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::fs::File;
+    use std::io::Read;
+
+    use parse::ParseError;
+    use RawModule;
+
+    use super::{parse_words, words_from_bytes};
+
+    fn load_bytes(path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn to_bytes(words: &[u32], swap: bool) -> Vec<u8> {
+        words.iter().flat_map(|&w| {
+            if swap { w.swap_bytes() } else { w }.to_ne_bytes().to_vec()
+        }).collect()
+    }
+
+    #[test]
+    fn borrowed_and_copied() {
+        let words = RawModule::load_module("examples/vert.spv").unwrap().to_words();
+
+        // Aligned and in the native byte order
+        let bytes = unsafe { words.align_to::<u8>().1 };
+        match words_from_bytes(bytes).unwrap() {
+            Cow::Borrowed(w) => assert_eq!(w, &words[..]),
+            Cow::Owned(_) => panic!("aligned words were copied")
+        }
+
+        // Moved off the alignment of a `u32`
+        let native = to_bytes(&words, false);
+        let mut buf = vec![0; native.len() + 4];
+        let start = (0..4).find(|&i| (buf.as_ptr() as usize + i) % 4 != 0).unwrap();
+        buf[start..start + native.len()].copy_from_slice(&native);
+        match words_from_bytes(&buf[start..start + native.len()]).unwrap() {
+            Cow::Owned(w) => assert_eq!(w, words),
+            Cow::Borrowed(_) => panic!("unaligned words were borrowed")
+        }
+    }
+
+    #[test]
+    fn swapped_byte_order() {
+        let words = RawModule::load_module("examples/vert.spv").unwrap().to_words();
+        assert_eq!(words_from_bytes(&to_bytes(&words, true)).unwrap().into_owned(), words);
+
+        let big_endian = load_bytes("fuzz/corpus/read_module/vert_big_endian.spv");
+        assert_eq!(words_from_bytes(&big_endian).unwrap().into_owned(), words);
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = load_bytes("examples/vert.spv");
+        bytes.push(0);
+        match words_from_bytes(&bytes) {
+            Err(ParseError::TrailingBytes(1)) => (),
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+
+    #[test]
+    fn matches_read_module() {
+        let bytes = load_bytes("examples/vert.spv");
+        let module = RawModule::read_module(&bytes[..]).unwrap();
+
+        let words = words_from_bytes(&bytes).unwrap();
+        let (header, insts) = parse_words(&words).unwrap();
+        assert_eq!(header.version, module.header().version);
+        assert_eq!(header.id_bound, module.header().id_bound);
+
+        let mut offset = 5;
+        let mut count = 0;
+        for (inst, expected) in insts.zip(module.instructions()) {
+            let inst = inst.unwrap();
+            assert_eq!(inst.offset, offset);
+            assert_eq!(inst.opcode, expected.opcode());
+            assert_eq!(format!("{:?}", inst.decode().unwrap()), format!("{:?}", expected));
+            offset += inst.word_count as usize;
+            count += 1;
+        }
+        assert_eq!(count, module.instructions().len());
+        assert_eq!(offset, words.len());
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Statistics about what takes up the space in a module.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use desc::{self, Id};
+use disasm::Disassembler;
+use instruction::Instruction;
+use transform::strip_debug_info;
+use write::encode_instruction;
+use RawModule;
+
+/// The number of instructions and words used by something in a module
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Count {
+    pub name: String,
+    pub instructions: usize,
+    pub words: usize
+}
+
+/// Statistics about a module, see `module_stats`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleStats {
+    /// The total size, including the header
+    pub words: usize,
+    pub instructions: usize,
+    pub types: usize,
+    pub constants: usize,
+    pub functions: usize,
+    /// Counts for each opcode, largest first
+    pub opcodes: Vec<Count>,
+    /// Counts for each group of instructions in `core.desc`, largest first
+    pub groups: Vec<Count>,
+    /// Counts for each section of the module, in the order the sections
+    /// appear. Sections with no instructions are left out.
+    pub sections: Vec<Count>,
+    /// The functions in the module, largest first
+    pub largest_functions: Vec<Count>,
+    /// The instructions and words that `strip_debug_info` would remove
+    pub debug_info: Count
+}
+
+/**
+ * Gathers statistics about the module
+ */
+pub fn module_stats(module: &RawModule) -> ModuleStats {
+    let names = Disassembler::with_names(module);
+
+    let mut opcodes = Counts::default();
+    let mut groups = Counts::default();
+    let mut sections = Counts::default();
+    let mut functions = Counts::default();
+
+    let mut stats = ModuleStats {
+        words: 5,
+        instructions: module.instructions().len(),
+        types: 0,
+        constants: 0,
+        functions: 0,
+        opcodes: Vec::new(),
+        groups: Vec::new(),
+        sections: Vec::new(),
+        largest_functions: Vec::new(),
+        debug_info: Count { name: "Debug info".to_owned(), instructions: 0, words: 0 }
+    };
+    sections.add("Header".to_owned(), 0, 5);
+
+    let mut current_function = None;
+    let mut words = Vec::new();
+    for inst in module.instructions() {
+        words.clear();
+        encode_instruction(inst, &mut words);
+        let len = words.len();
+        stats.words += len;
+
+        let opcode = inst.opcode();
+        let name = match desc::Op::from(opcode) {
+            Some(op) => format!("Op{}", op.name()),
+            None => format!("OpUnknown({})", opcode)
+        };
+        opcodes.add(name, 1, len);
+        groups.add(inst.group().unwrap_or("Unknown").to_owned(), 1, len);
+
+        if let Instruction::Function { result_id, .. } = *inst {
+            current_function = Some(names.id_name(Id::from(result_id)));
+            stats.functions += 1;
+        }
+
+        let section = if current_function.is_some() {
+            "Functions"
+        } else {
+            section_name(inst)
+        };
+        sections.add(section.to_owned(), 1, len);
+
+        if let Some(ref name) = current_function {
+            functions.add(name.clone(), 1, len);
+        } else if inst.defines_type().is_some() {
+            stats.types += 1;
+        } else if inst.group() == Some("Constant") {
+            stats.constants += 1;
+        }
+
+        if let Instruction::FunctionEnd = *inst {
+            current_function = None;
+        }
+    }
+
+    let (_, strip_stats) = strip_debug_info(module);
+    stats.debug_info.instructions = strip_stats.instructions_removed;
+    stats.debug_info.words = strip_stats.words_removed;
+
+    stats.opcodes = opcodes.largest_first();
+    stats.groups = groups.largest_first();
+    stats.sections = sections.counts;
+    stats.largest_functions = functions.largest_first();
+
+    stats
+}
+
+/// Counts in the order they were first added
+#[derive(Default)]
+struct Counts {
+    counts: Vec<Count>,
+    indices: HashMap<String, usize>
+}
+
+impl Counts {
+    fn add(&mut self, name: String, instructions: usize, words: usize) {
+        let idx = match self.indices.get(&name) {
+            Some(&idx) => idx,
+            None => {
+                self.counts.push(Count { name: name.clone(), instructions: 0, words: 0 });
+                self.indices.insert(name, self.counts.len() - 1);
+                self.counts.len() - 1
+            }
+        };
+
+        self.counts[idx].instructions += instructions;
+        self.counts[idx].words += words;
+    }
+
+    fn largest_first(mut self) -> Vec<Count> {
+        // The sort is stable, so ties stay in the order they were found
+        self.counts.sort_by(|a, b| b.words.cmp(&a.words));
+        self.counts
+    }
+}
+
+fn section_name(inst: &Instruction) -> &'static str {
+    use instruction::Instruction::*;
+    match *inst {
+        Capability { .. } => "Capabilities",
+        Extension { .. } => "Extensions",
+        ExtInstImport { .. } => "Extended instruction imports",
+        MemoryModel { .. } => "Memory model",
+        EntryPoint { .. } => "Entry points",
+        ExecutionMode { .. } => "Execution modes",
+        SourceContinued { .. } | Source { .. } | SourceExtension { .. } |
+        Name { .. } | MemberName { .. } | String { .. } |
+        ModuleProcessed { .. } => "Debug",
+        Decorate { .. } | MemberDecorate { .. } | DecorationGroup { .. } |
+        GroupDecorate { .. } | GroupMemberDecorate { .. } => "Annotations",
+        _ => "Types, constants and globals"
+    }
+}
+
+impl ModuleStats {
+    /**
+     * Formats the statistics as JSON
+     */
+    pub fn to_json(&self) -> String {
+        fn counts(out: &mut String, name: &str, counts: &[Count]) {
+            out.push_str(&format!("  \"{}\": [", name));
+            for (i, c) in counts.iter().enumerate() {
+                if i > 0 { out.push(','); }
+                out.push_str("\n    ");
+                count(out, c);
+            }
+            out.push_str("\n  ],\n");
+        }
+
+        fn count(out: &mut String, c: &Count) {
+            out.push_str(&format!("{{ \"name\": \"{}\", \"instructions\": {}, \"words\": {} }}",
+                                  json_escape(&c.name), c.instructions, c.words));
+        }
+
+        let mut out = String::from("{\n");
+        out.push_str(&format!("  \"words\": {},\n", self.words));
+        out.push_str(&format!("  \"instructions\": {},\n", self.instructions));
+        out.push_str(&format!("  \"types\": {},\n", self.types));
+        out.push_str(&format!("  \"constants\": {},\n", self.constants));
+        out.push_str(&format!("  \"functions\": {},\n", self.functions));
+        counts(&mut out, "opcodes", &self.opcodes);
+        counts(&mut out, "groups", &self.groups);
+        counts(&mut out, "sections", &self.sections);
+        counts(&mut out, "largest_functions", &self.largest_functions);
+        out.push_str("  \"debug_info\": ");
+        count(&mut out, &self.debug_info);
+        out.push_str("\n}\n");
+        out
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out
+}
+
+/// Formats the statistics as human-readable tables
+impl fmt::Display for ModuleStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn table(f: &mut fmt::Formatter, title: &str, counts: &[Count], total: usize)
+                 -> fmt::Result {
+            let width = counts.iter().map(|c| c.name.len()).max().unwrap_or(0)
+                .max(title.len());
+
+            try!(writeln!(f, "{:<width$}  {:>8}  {:>8}  {:>6}", title, "Insts", "Words", "%",
+                          width=width));
+            for c in counts {
+                let percent = if total > 0 { c.words as f64 * 100.0 / total as f64 } else { 0.0 };
+                try!(writeln!(f, "{:<width$}  {:>8}  {:>8}  {:>6.1}", c.name, c.instructions,
+                              c.words, percent, width=width));
+            }
+            writeln!(f, "")
+        }
+
+        try!(writeln!(f, "Size: {} words ({} bytes)", self.words, self.words * 4));
+        try!(writeln!(f, "Instructions: {}", self.instructions));
+        try!(writeln!(f, "Types: {}", self.types));
+        try!(writeln!(f, "Constants: {}", self.constants));
+        try!(writeln!(f, "Functions: {}", self.functions));
+        try!(writeln!(f, "Debug info: {} instructions, {} words\n",
+                      self.debug_info.instructions, self.debug_info.words));
+
+        try!(table(f, "Section", &self.sections, self.words));
+        try!(table(f, "Group", &self.groups, self.words));
+        try!(table(f, "Opcode", &self.opcodes, self.words));
+        table(f, "Function", &self.largest_functions, self.words)
+    }
+}
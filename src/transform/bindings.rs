@@ -0,0 +1,352 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{fmt, error};
+use std::collections::HashMap;
+
+use desc::{self, Id, TypeId};
+use instruction::{Decoration, Instruction};
+use RawModule;
+
+/// The kind of resource bound to a descriptor
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceType {
+    Sampler,
+    /// An image that is sampled with a separate sampler
+    SampledImage,
+    /// An image and sampler together, an `OpTypeSampledImage`
+    CombinedImageSampler,
+    StorageImage,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    UniformBuffer,
+    StorageBuffer,
+    InputAttachment
+}
+
+/**
+ * Describes how descriptor sets and bindings are reassigned.
+ *
+ * Variables are first looked up by name, then by their current set and
+ * binding. Variables that aren't in either map have `set_shift` added to
+ * their set and the shift for their resource type added to their
+ * binding.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct BindingMap {
+    pub names: HashMap<String, (u32, u32)>,
+    pub bindings: HashMap<(u32, u32), (u32, u32)>,
+    pub set_shift: u32,
+    pub binding_shifts: HashMap<ResourceType, u32>
+}
+
+impl BindingMap {
+    pub fn new() -> BindingMap {
+        BindingMap::default()
+    }
+
+    /// Gets the new set and binding of a resource, or `None` if shifting
+    /// them would overflow
+    pub fn map(&self, name: Option<&str>, ty: Option<ResourceType>,
+               set: u32, binding: u32) -> Option<(u32, u32)> {
+        if let Some(&new) = name.and_then(|name| self.names.get(name)) {
+            return Some(new);
+        }
+        if let Some(&new) = self.bindings.get(&(set, binding)) {
+            return Some(new);
+        }
+
+        let shift = ty.and_then(|ty| self.binding_shifts.get(&ty)).cloned().unwrap_or(0);
+        match (set.checked_add(self.set_shift), binding.checked_add(shift)) {
+            (Some(set), Some(binding)) => Some((set, binding)),
+            _ => None
+        }
+    }
+}
+
+/// An error from `remap_bindings`
+#[derive(Debug)]
+pub enum BindingError {
+    /// Shifting the set and binding of the variable would overflow. The
+    /// values are the variable and its current set and binding.
+    Overflow(Id, u32, u32)
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindingError::Overflow(var, set, binding) => {
+                write!(f, "Error while remapping bindings: shifting set {} binding {} of \
+                           `{:?}` overflows", set, binding, var)
+            }
+        }
+    }
+}
+
+impl error::Error for BindingError {
+    fn description(&self) -> &str {
+        match *self {
+            BindingError::Overflow(..) => "set or binding overflow"
+        }
+    }
+}
+
+/**
+ * Reassign the `DescriptorSet` and `Binding` decorations of every
+ * resource variable according to the given map.
+ *
+ * A name matches either the variable or, for blocks, the block type.
+ * Variables with a binding but no set are in set 0, and are given a
+ * `DescriptorSet` decoration if they are moved to another set. Fails if
+ * shifting any set or binding would overflow, rather than leave that
+ * resource where it is to clash with one moved onto it.
+ */
+pub fn remap_bindings(module: &RawModule, map: &BindingMap)
+                      -> Result<RawModule, BindingError> {
+    let mut names = HashMap::new();
+    let mut sets = HashMap::new();
+    let mut bindings = HashMap::new();
+
+    for inst in module.instructions() {
+        match *inst {
+            Instruction::Name { id, ref name } => {
+                names.insert(id, &name[..]);
+            }
+            Instruction::Decorate { target, decoration: Decoration::DescriptorSet(set) } => {
+                sets.insert(target, set);
+            }
+            Instruction::Decorate { target, decoration: Decoration::Binding(binding) } => {
+                bindings.insert(target, binding);
+            }
+            _ => ()
+        }
+    }
+
+    let mut new_bindings = HashMap::new();
+    for (&var, &binding) in &bindings {
+        let set = sets.get(&var).cloned().unwrap_or(0);
+
+        let ty = pointee(module, var);
+        let name = names.get(&var).cloned().filter(|name| name.len() > 0).or_else(|| {
+            ty.and_then(|ty| names.get(&Id::from(ty))).cloned()
+        });
+        let resource = ty.and_then(|ty| resource_type(module, var, ty));
+
+        match map.map(name, resource, set, binding) {
+            Some(new) => new_bindings.insert(var, new),
+            None => return Err(BindingError::Overflow(var, set, binding))
+        };
+    }
+
+    let mut instructions = Vec::with_capacity(module.instructions().len());
+    for inst in module.instructions() {
+        match *inst {
+            Instruction::Decorate { target, decoration: Decoration::DescriptorSet(_) } => {
+                if let Some(&(set, _)) = new_bindings.get(&target) {
+                    instructions.push(Instruction::Decorate {
+                        target: target,
+                        decoration: Decoration::DescriptorSet(set)
+                    });
+                    continue;
+                }
+            }
+            Instruction::Decorate { target, decoration: Decoration::Binding(_) } => {
+                let (set, binding) = new_bindings[&target];
+                instructions.push(Instruction::Decorate {
+                    target: target,
+                    decoration: Decoration::Binding(binding)
+                });
+                if set != 0 && !sets.contains_key(&target) {
+                    instructions.push(Instruction::Decorate {
+                        target: target,
+                        decoration: Decoration::DescriptorSet(set)
+                    });
+                }
+                continue;
+            }
+            _ => ()
+        }
+        instructions.push(inst.clone());
+    }
+
+    Ok(RawModule::from_instructions(module.header().clone(), instructions))
+}
+
+/// Gets the type of the variable, looking through any arrays
+fn pointee(module: &RawModule, var: Id) -> Option<TypeId> {
+    let ptr = match module.def(var) {
+        Some(&Instruction::Variable { result_type, .. }) => result_type,
+        _ => return None
+    };
+    let mut ty = match module.def(ptr) {
+        Some(&Instruction::TypePointer { pointee, .. }) => pointee,
+        _ => return None
+    };
+
+    loop {
+        match module.def(ty) {
+            Some(&Instruction::TypeArray { element, .. }) |
+            Some(&Instruction::TypeRuntimeArray { element, .. }) => ty = element,
+            _ => return Some(ty)
+        }
+    }
+}
+
+fn resource_type(module: &RawModule, var: Id, ty: TypeId) -> Option<ResourceType> {
+    let storage_class = match module.def(var) {
+        Some(&Instruction::Variable { storage_class, .. }) => storage_class,
+        _ => return None
+    };
+
+    match module.def(ty) {
+        Some(&Instruction::TypeSampler { .. }) => Some(ResourceType::Sampler),
+        Some(&Instruction::TypeSampledImage { .. }) => Some(ResourceType::CombinedImageSampler),
+        Some(&Instruction::TypeImage { dim, sampled, .. }) => {
+            Some(match (dim, sampled) {
+                (desc::Dim::SubpassData, _) => ResourceType::InputAttachment,
+                (desc::Dim::Buffer, 2) => ResourceType::StorageTexelBuffer,
+                (desc::Dim::Buffer, _) => ResourceType::UniformTexelBuffer,
+                (_, 2) => ResourceType::StorageImage,
+                _ => ResourceType::SampledImage
+            })
+        }
+        Some(&Instruction::TypeStruct { .. }) if storage_class == desc::StorageClass::Uniform => {
+            let buffer_block = module.instructions().iter().any(|inst| {
+                if let Instruction::Decorate { target, decoration: Decoration::BufferBlock } = *inst {
+                    target == ty.into()
+                } else {
+                    false
+                }
+            });
+            if buffer_block {
+                Some(ResourceType::StorageBuffer)
+            } else {
+                Some(ResourceType::UniformBuffer)
+            }
+        }
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use asm::assemble;
+    use instruction::{Decoration, Instruction};
+    use RawModule;
+
+    use super::{remap_bindings, BindingError, BindingMap, ResourceType};
+
+    const MODULE : &'static str = "
+        OpCapability Shader
+        OpMemoryModel Logical GLSL450
+        OpName %image \"image\"
+        OpName %combined \"combined\"
+        OpName %block \"Block\"
+        OpDecorate %image DescriptorSet 0
+        OpDecorate %image Binding 0
+        OpDecorate %combined DescriptorSet 0
+        OpDecorate %combined Binding 1
+        OpDecorate %sampler Binding 2
+        OpDecorate %block Block
+        OpDecorate %ubo DescriptorSet 1
+        OpDecorate %ubo Binding 0
+        %float = OpTypeFloat 32
+        %image_ty = OpTypeImage %float 2D 0 0 0 1 Unknown
+        %combined_ty = OpTypeSampledImage %image_ty
+        %sampler_ty = OpTypeSampler
+        %block = OpTypeStruct %float
+        %ptr_image = OpTypePointer UniformConstant %image_ty
+        %ptr_combined = OpTypePointer UniformConstant %combined_ty
+        %ptr_sampler = OpTypePointer UniformConstant %sampler_ty
+        %ptr_block = OpTypePointer Uniform %block
+        %image = OpVariable %ptr_image UniformConstant
+        %combined = OpVariable %ptr_combined UniformConstant
+        %sampler = OpVariable %ptr_sampler UniformConstant
+        %ubo = OpVariable %ptr_block Uniform
+    ";
+
+    /// Gets the set and binding of each variable, by the instruction
+    /// that defines it
+    fn bindings(module: &RawModule) -> HashMap<usize, (u32, u32)> {
+        let mut out = HashMap::new();
+        for inst in module.instructions() {
+            if let Instruction::Decorate { target, ref decoration } = *inst {
+                let idx = module.def_index(target).unwrap();
+                let entry = out.entry(idx).or_insert((0, 0));
+                match *decoration {
+                    Decoration::DescriptorSet(set) => entry.0 = set,
+                    Decoration::Binding(binding) => entry.1 = binding,
+                    _ => ()
+                }
+            }
+        }
+        out
+    }
+
+    fn var(module: &RawModule, n: usize) -> usize {
+        module.instructions().iter().enumerate()
+            .filter(|&(_, inst)| inst.opcode() == ::desc::Op::Variable as u16)
+            .nth(n).unwrap().0
+    }
+
+    #[test]
+    fn shifts_by_resource_type() {
+        let module = assemble(MODULE).unwrap();
+        let mut map = BindingMap::new();
+        map.set_shift = 2;
+        map.binding_shifts.insert(ResourceType::SampledImage, 10);
+        map.binding_shifts.insert(ResourceType::CombinedImageSampler, 20);
+        map.binding_shifts.insert(ResourceType::Sampler, 30);
+        map.binding_shifts.insert(ResourceType::UniformBuffer, 40);
+
+        let remapped = remap_bindings(&module, &map).unwrap();
+        let b = bindings(&remapped);
+        assert_eq!(b[&var(&remapped, 0)], (2, 10));
+        assert_eq!(b[&var(&remapped, 1)], (2, 21));
+        // The sampler had no set, so it's given one
+        assert_eq!(b[&var(&remapped, 2)], (2, 32));
+        assert_eq!(b[&var(&remapped, 3)], (3, 40));
+    }
+
+    #[test]
+    fn names_and_bindings() {
+        let module = assemble(MODULE).unwrap();
+        let mut map = BindingMap::new();
+        map.names.insert("combined".to_owned(), (5, 5));
+        // Blocks can be found by the name of their type
+        map.names.insert("Block".to_owned(), (6, 6));
+        map.bindings.insert((0, 2), (0, 7));
+        map.set_shift = 1;
+
+        let remapped = remap_bindings(&module, &map).unwrap();
+        let b = bindings(&remapped);
+        assert_eq!(b[&var(&remapped, 0)], (1, 0));
+        assert_eq!(b[&var(&remapped, 1)], (5, 5));
+        assert_eq!(b[&var(&remapped, 2)], (0, 7));
+        assert_eq!(b[&var(&remapped, 3)], (6, 6));
+    }
+
+    #[test]
+    fn overflow() {
+        let module = assemble(MODULE).unwrap();
+        let mut map = BindingMap::new();
+        map.binding_shifts.insert(ResourceType::CombinedImageSampler, !0);
+
+        match remap_bindings(&module, &map) {
+            Err(BindingError::Overflow(id, 0, 1)) => {
+                assert_eq!(module.def_index(id), Some(var(&module, 1)));
+            }
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+        assert_eq!(map.map(None, Some(ResourceType::CombinedImageSampler), 0, 0),
+                   Some((0, !0)));
+        assert_eq!(map.map(None, None, 0, 1), Some((0, 1)));
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{HashMap, HashSet};
+
+use desc::Id;
+use instruction::Instruction;
+use parse::Header;
+use RawModule;
+
+/**
+ * Renumber the ids in the module so they are dense and assigned in the
+ * order they are defined. Ids that are used without being defined are
+ * numbered after all the defined ids, in the order they are first used.
+ *
+ * The id bound of the resulting module is one more than the highest id.
+ *
+ * The operands of `Unknown` instructions can't be rewritten, since which
+ * of them are ids isn't known, so a module containing any is returned
+ * unchanged rather than left with ids that no longer match. The same
+ * goes for extended instructions from sets other than `GLSL.std.450` and
+ * the `NonSemantic` sets, as sets like `OpenCL.std` mix literals in with
+ * the ids.
+ */
+pub fn compact_ids(module: &RawModule) -> RawModule {
+    let mut id_sets = HashSet::new();
+    for inst in module.instructions() {
+        if let Instruction::ExtInstImport { result_id, ref name } = *inst {
+            if name == "GLSL.std.450" || name.starts_with("NonSemantic.") {
+                id_sets.insert(result_id.to_value_id());
+            }
+        }
+    }
+
+    let unknown = module.instructions().iter().any(|inst| {
+        match *inst {
+            Instruction::Unknown(..) => true,
+            Instruction::ExtInst { set, .. } => !id_sets.contains(&set),
+            _ => false
+        }
+    });
+    if unknown {
+        return RawModule::from_instructions(module.header().clone(),
+                                            module.instructions().to_vec());
+    }
+
+    let mut map = IdMap {
+        ids: HashMap::new(),
+        next: 1
+    };
+
+    for inst in module.instructions() {
+        if let Some(id) = inst.defines() {
+            map.get(id);
+        }
+    }
+
+    let instructions : Vec<_> = module.instructions().iter().map(|inst| {
+        let mut inst = inst.clone();
+        inst.for_each_id_mut(|id, _| *id = map.get(Id(*id)).0);
+        inst
+    }).collect();
+
+    let header = Header {
+        id_bound: map.next,
+        .. module.header().clone()
+    };
+
+    RawModule::from_instructions(header, instructions)
+}
+
+struct IdMap {
+    /// The new id for each id seen so far. Ids can be anything up to
+    /// the largest `u32`, so this only has entries for the ids used.
+    ids: HashMap<Id, Id>,
+    next: u32
+}
+
+impl IdMap {
+    /// Gets the new id for the given id, assigning the next free id if
+    /// it hasn't been seen before
+    fn get(&mut self, id: Id) -> Id {
+        if !id.is_valid() {
+            return id;
+        }
+
+        let next = &mut self.next;
+        *self.ids.entry(id).or_insert_with(|| {
+            *next += 1;
+            Id(*next - 1)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc::Id;
+    use instruction::Instruction;
+    use parse::Header;
+    use validate::validate;
+    use RawModule;
+
+    use super::compact_ids;
+
+    const MODULE : &'static str = "
+        OpCapability Shader
+        %glsl = OpExtInstImport \"GLSL.std.450\"
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Fragment %main \"main\"
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %one = OpConstant %float 1
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        %x = OpExtInst %float %glsl 4 %one
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    /// Spreads the ids of the module out, up to near the largest id
+    fn spread(module: &RawModule) -> RawModule {
+        let instructions = module.instructions().iter().map(|inst| {
+            let mut inst = inst.clone();
+            inst.map_ids(|id| Id(0xFFFFFFFF - id.0 * 1000));
+            inst
+        }).collect();
+        let header = Header { id_bound: 0xFFFFFFFF, .. module.header().clone() };
+        RawModule::from_instructions(header, instructions)
+    }
+
+    #[test]
+    fn dense_ids() {
+        let module = assemble(MODULE).unwrap();
+        let compacted = compact_ids(&spread(&module));
+        assert!(validate(&compacted).is_empty());
+        assert_eq!(compacted.header().id_bound, 9);
+
+        // Numbered in the order they're defined
+        let defs : Vec<_> = compacted.instructions().iter().filter_map(|inst| inst.defines())
+            .map(|id| id.0).collect();
+        assert_eq!(defs, (1..9).collect::<Vec<_>>());
+
+        // The extended instruction's operand is renumbered, but not the
+        // instruction number
+        match compacted.instructions()[compacted.instructions().len() - 3] {
+            Instruction::ExtInst { set, instruction, ref operands, .. } => {
+                assert_eq!(set.0, 1);
+                assert_eq!(instruction, 4);
+                assert_eq!(operands[..], [Id(5)]);
+            }
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+    }
+
+    #[test]
+    fn undefined_ids() {
+        let module = assemble(MODULE).unwrap();
+        let mut instructions = spread(&module).instructions().to_vec();
+        instructions.insert(1, Instruction::Name {
+            id: Id(0xFFFFFFFE),
+            name: "undefined".to_owned()
+        });
+        let module = RawModule::from_instructions(Header {
+            id_bound: 0xFFFFFFFF,
+            .. module.header().clone()
+        }, instructions);
+
+        let compacted = compact_ids(&module);
+        assert_eq!(compacted.header().id_bound, 10);
+        match compacted.instructions()[1] {
+            Instruction::Name { id, .. } => assert_eq!(id, Id(9)),
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+    }
+
+    #[test]
+    fn unknown_operands() {
+        // Some `OpenCL.std` instructions have literal operands
+        let module = assemble(&MODULE.replace("GLSL.std.450", "OpenCL.std")).unwrap();
+        let module = spread(&module);
+        let compacted = compact_ids(&module);
+        assert_eq!(compacted.to_words(), module.to_words());
+
+        let module = spread(&assemble(MODULE).unwrap());
+        let mut instructions = module.instructions().to_vec();
+        instructions.insert(1, Instruction::Unknown(0xFFFF, vec![1, 2].into_boxed_slice()));
+        let module = RawModule::from_instructions(module.header().clone(), instructions);
+        let compacted = compact_ids(&module);
+        assert_eq!(compacted.to_words(), module.to_words());
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashSet;
+
+use desc::{self, Id, ValueId};
+use instruction::{Decoration, Instruction};
+use RawModule;
+
+use super::functions;
+
+/**
+ * Remove functions that cannot be reached from any entry point or
+ * function exported with `LinkageAttributes`, along with any global
+ * variables, constants and types that are no longer used. Exported
+ * globals are always kept. Names and decorations that only refer to
+ * removed ids are also removed.
+ *
+ * Instructions from extended instruction sets outside of functions are
+ * always kept, so debug information can keep globals alive. Strip the
+ * debug information first for the best results.
+ */
+pub fn eliminate_dead_code(module: &RawModule) -> RawModule {
+    let insts = module.instructions();
+    let funcs = functions(module);
+
+    let exported : Vec<ValueId> = insts.iter().filter_map(|inst| {
+        match *inst {
+            Instruction::Decorate {
+                target,
+                decoration: Decoration::LinkageAttributes(_, desc::LinkageType::Export)
+            } => Some(target.to_value_id()),
+            _ => None
+        }
+    }).collect();
+
+    // Find the functions that are reachable from the entry points and
+    // exports
+    let mut live_funcs = HashSet::new();
+    let mut worklist : Vec<ValueId> = insts.iter().filter_map(|inst| {
+        if let Instruction::EntryPoint { func, .. } = *inst {
+            Some(func)
+        } else {
+            None
+        }
+    }).chain(exported.iter().cloned()).collect();
+
+    while let Some(func) = worklist.pop() {
+        if !live_funcs.insert(func) { continue; }
+
+        if let Some(&(_, ref range)) = funcs.iter().find(|f| f.0 == func) {
+            for inst in &insts[range.clone()] {
+                if let Instruction::FunctionCall { func, .. } = *inst {
+                    worklist.push(func);
+                }
+            }
+        }
+    }
+
+    let mut in_function = vec![false; insts.len()];
+    let mut live = vec![false; insts.len()];
+    for &(id, ref range) in &funcs {
+        let is_live = live_funcs.contains(&id);
+        for idx in range.clone() {
+            in_function[idx] = true;
+            live[idx] = is_live;
+        }
+    }
+
+    for (idx, inst) in insts.iter().enumerate() {
+        if !in_function[idx] && is_root(inst) {
+            live[idx] = true;
+        }
+    }
+
+    // Builtins like `WorkgroupSize` have meaning without being used
+    for inst in insts {
+        if let Instruction::Decorate { target, decoration: Decoration::BuiltIn(_) } = *inst {
+            if let Some(idx) = module.def_index(target) {
+                live[idx] = true;
+            }
+        }
+    }
+
+    // As do exported globals, which another module may use
+    for &id in &exported {
+        if let Some(idx) = module.def_index(id) {
+            if !in_function[idx] {
+                live[idx] = true;
+            }
+        }
+    }
+
+    // Mark the definitions of everything used by live instructions
+    let mut worklist : Vec<usize> = (0..insts.len()).filter(|&idx| live[idx]).collect();
+    while let Some(idx) = worklist.pop() {
+        let inst = &insts[idx];
+        let uses = inst.uses_iter().chain(inst.type_id_of().map(Id::from));
+        for id in uses {
+            if let Some(def) = module.def_index(id) {
+                if !live[def] && !in_function[def] {
+                    live[def] = true;
+                    worklist.push(def);
+                }
+            }
+        }
+    }
+
+    let is_live = |id: Id| {
+        module.def_index(id).map(|idx| live[idx]).unwrap_or(false)
+    };
+
+    // Decoration groups are live if they're still applied to something
+    let mut live_groups = HashSet::new();
+    for inst in insts {
+        match *inst {
+            Instruction::GroupDecorate { grp, ref targets } => {
+                if targets.iter().any(|&t| is_live(t)) {
+                    live_groups.insert(grp);
+                }
+            }
+            Instruction::GroupMemberDecorate { grp, ref targets } => {
+                if targets.iter().any(|&(t, _)| is_live(t.into())) {
+                    live_groups.insert(grp);
+                }
+            }
+            _ => ()
+        }
+    }
+
+    let is_live_target = |id: Id| {
+        is_live(id) || live_groups.contains(&id.to_value_id())
+    };
+
+    let mut instructions = Vec::with_capacity(insts.len());
+    for (idx, inst) in insts.iter().enumerate() {
+        if live[idx] {
+            instructions.push(inst.clone());
+            continue;
+        }
+        if in_function[idx] { continue; }
+
+        let inst = match *inst {
+            Instruction::Name { id, .. } |
+            Instruction::Decorate { target: id, .. } => {
+                if !is_live_target(id) { continue; }
+                inst.clone()
+            }
+            Instruction::MemberName { type_id, .. } |
+            Instruction::MemberDecorate { target: type_id, .. } |
+            Instruction::TypeForwardPointer { type_id, .. } => {
+                if !is_live(type_id.into()) { continue; }
+                inst.clone()
+            }
+            Instruction::DecorationGroup { result_id } => {
+                if !live_groups.contains(&result_id.to_value_id()) { continue; }
+                inst.clone()
+            }
+            Instruction::GroupDecorate { grp, ref targets } => {
+                let targets : Vec<_> = targets.iter().cloned().filter(|&t| is_live(t)).collect();
+                if targets.len() == 0 { continue; }
+                Instruction::GroupDecorate {
+                    grp: grp,
+                    targets: targets.into_boxed_slice()
+                }
+            }
+            Instruction::GroupMemberDecorate { grp, ref targets } => {
+                let targets : Vec<_> = targets.iter().cloned().filter(|&(t, _)| {
+                    is_live(t.into())
+                }).collect();
+                if targets.len() == 0 { continue; }
+                Instruction::GroupMemberDecorate {
+                    grp: grp,
+                    targets: targets.into_boxed_slice()
+                }
+            }
+            _ => continue
+        };
+
+        instructions.push(inst);
+    }
+
+    RawModule::from_instructions(module.header().clone(), instructions)
+}
+
+/// Whether an instruction outside of a function must be kept regardless
+/// of whether anything uses it
+fn is_root(inst: &Instruction) -> bool {
+    use instruction::Instruction::*;
+
+    if inst.defines_type().is_some() {
+        return false;
+    }
+
+    match *inst {
+        // Only kept if they're used
+        ConstantTrue { .. } |
+        ConstantFalse { .. } |
+        Constant { .. } |
+        ConstantComposite { .. } |
+        ConstantSampler { .. } |
+        ConstantNull { .. } |
+        SpecConstantTrue { .. } |
+        SpecConstantFalse { .. } |
+        SpecConstant { .. } |
+        SpecConstantComposite { .. } |
+        SpecConstantOp { .. } |
+        Variable { .. } |
+        Undef { .. } |
+        String { .. } => false,
+        // Only kept if what they refer to is kept
+        Name { .. } |
+        MemberName { .. } |
+        Decorate { .. } |
+        MemberDecorate { .. } |
+        DecorationGroup { .. } |
+        GroupDecorate { .. } |
+        GroupMemberDecorate { .. } |
+        TypeForwardPointer { .. } => false,
+        _ => true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc;
+    use validate::validate;
+    use RawModule;
+
+    use super::eliminate_dead_code;
+
+    fn count(module: &RawModule, op: desc::Op) -> usize {
+        module.instructions().iter().filter(|inst| inst.opcode() == op as u16).count()
+    }
+
+    #[test]
+    fn entry_points() {
+        let module = assemble("
+            OpCapability Shader
+            OpMemoryModel Logical GLSL450
+            OpEntryPoint Fragment %main \"main\"
+            OpName %dead \"dead\"
+            OpName %unused \"unused\"
+            OpDecorate %unused Location 0
+            %void = OpTypeVoid
+            %fn_void = OpTypeFunction %void
+            %float = OpTypeFloat 32
+            %ptr_float = OpTypePointer Private %float
+            %ptr_out = OpTypePointer Output %float
+            %one = OpConstant %float 1
+            %two = OpConstant %float 2
+            %used = OpVariable %ptr_float Private
+            %unused = OpVariable %ptr_out Output
+            %main = OpFunction %void None %fn_void
+            %entry = OpLabel
+            %r = OpFunctionCall %void %helper
+            OpReturn
+            OpFunctionEnd
+            %helper = OpFunction %void None %fn_void
+            %h_entry = OpLabel
+            OpStore %used %one
+            OpReturn
+            OpFunctionEnd
+            %dead = OpFunction %void None %fn_void
+            %d_entry = OpLabel
+            OpStore %used %two
+            OpReturn
+            OpFunctionEnd
+        ").unwrap();
+
+        let module = eliminate_dead_code(&module);
+        assert!(validate(&module).is_empty());
+
+        // `main` and `helper` are kept, with what they use
+        assert_eq!(count(&module, desc::Op::Function), 2);
+        assert_eq!(count(&module, desc::Op::Constant), 1);
+        assert_eq!(count(&module, desc::Op::Variable), 1);
+        assert_eq!(count(&module, desc::Op::TypePointer), 1);
+        assert_eq!(count(&module, desc::Op::Name), 0);
+        assert_eq!(count(&module, desc::Op::Decorate), 0);
+    }
+
+    #[test]
+    fn exports() {
+        let module = assemble("
+            OpCapability Shader
+            OpCapability Linkage
+            OpMemoryModel Logical GLSL450
+            OpDecorate %f LinkageAttributes \"f\" Export
+            OpDecorate %g LinkageAttributes \"g\" Export
+            %void = OpTypeVoid
+            %fn_void = OpTypeFunction %void
+            %float = OpTypeFloat 32
+            %ptr_float = OpTypePointer Private %float
+            %one = OpConstant %float 1
+            %g = OpVariable %ptr_float Private
+            %h = OpVariable %ptr_float Private
+            %f = OpFunction %void None %fn_void
+            %entry = OpLabel
+            OpStore %h %one
+            OpReturn
+            OpFunctionEnd
+            %dead = OpFunction %void None %fn_void
+            %d_entry = OpLabel
+            OpReturn
+            OpFunctionEnd
+        ").unwrap();
+
+        let module = eliminate_dead_code(&module);
+        assert!(validate(&module).is_empty());
+
+        // Only `dead` is removed
+        assert_eq!(count(&module, desc::Op::Function), 1);
+        assert_eq!(count(&module, desc::Op::Decorate), 2);
+        assert_eq!(count(&module, desc::Op::Variable), 2);
+        assert_eq!(count(&module, desc::Op::Constant), 1);
+        assert_eq!(module.instructions().len(), 17);
+    }
+}
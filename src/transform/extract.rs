@@ -0,0 +1,152 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use desc::{self, Capability, ExecutionModel};
+use instruction::{Decoration, Instruction};
+use RawModule;
+
+use super::eliminate_dead_code;
+
+/**
+ * Extract a single entry point into a module of its own. The new module
+ * contains the `EntryPoint` and its `ExecutionMode`s, along with only
+ * the functions, globals, types, constants, names and decorations it
+ * needs. If there are several entry points with the given name, the
+ * first is used.
+ *
+ * Unused extended instruction set imports are removed. Capabilities
+ * that are only needed for stages or types that are no longer present
+ * in the module are also removed, other capabilities are kept.
+ *
+ * Returns `None` if there is no entry point with the given name.
+ */
+pub fn extract_entry_point(module: &RawModule, name: &str) -> Option<RawModule> {
+    let (model, func) = match module.instructions().iter().filter_map(|inst| {
+        if let Instruction::EntryPoint { execution_model, func, name: ref n, .. } = *inst {
+            if n == name {
+                return Some((execution_model, func));
+            }
+        }
+        None
+    }).next() {
+        Some(entry) => entry,
+        None => return None
+    };
+
+    let mut found = false;
+    let instructions = module.instructions().iter().filter(|inst| {
+        match **inst {
+            Instruction::EntryPoint { func: f, .. } => {
+                let keep = f == func && !found;
+                found |= keep;
+                keep
+            }
+            Instruction::ExecutionMode { entry_point, .. } => entry_point == func,
+            _ => true
+        }
+    }).cloned().collect();
+
+    let module = eliminate_dead_code(&RawModule::from_instructions(module.header().clone(),
+                                                                   instructions));
+
+    let mut features = Features::default();
+    for inst in module.instructions() {
+        features.add(inst);
+    }
+
+    let instructions = module.instructions().iter().filter(|inst| {
+        match **inst {
+            Instruction::Capability { capability } => features.needs(model, capability),
+            Instruction::ExtInstImport { result_id, .. } => {
+                module.use_indices(result_id).map(|uses| uses.len() > 0).unwrap_or(false)
+            }
+            _ => true
+        }
+    }).cloned().collect();
+
+    Some(RawModule::from_instructions(module.header().clone(), instructions))
+}
+
+/// The stage-specific and type features used in a module
+#[derive(Default)]
+struct Features {
+    int8: bool,
+    int16: bool,
+    int64: bool,
+    float16: bool,
+    float64: bool,
+    geometry: bool,
+    tessellation: bool
+}
+
+impl Features {
+    fn add(&mut self, inst: &Instruction) {
+        match *inst {
+            Instruction::TypeInt { width, .. } => {
+                match width {
+                    8 => self.int8 = true,
+                    16 => self.int16 = true,
+                    64 => self.int64 = true,
+                    _ => ()
+                }
+            }
+            Instruction::TypeFloat { width, .. } => {
+                match width {
+                    16 => self.float16 = true,
+                    64 => self.float64 = true,
+                    _ => ()
+                }
+            }
+            Instruction::EmitVertex |
+            Instruction::EndPrimitive |
+            Instruction::EmitStreamVertex { .. } |
+            Instruction::EndStreamPrimitive { .. } => self.geometry = true,
+            Instruction::Decorate { decoration: Decoration::BuiltIn(builtin), .. } |
+            Instruction::MemberDecorate { decoration: Decoration::BuiltIn(builtin), .. } => {
+                match builtin {
+                    desc::BuiltIn::PrimitiveId |
+                    desc::BuiltIn::InvocationId => {
+                        self.geometry = true;
+                        self.tessellation = true;
+                    }
+                    desc::BuiltIn::Layer |
+                    desc::BuiltIn::ViewportIndex => self.geometry = true,
+                    desc::BuiltIn::TessLevelOuter |
+                    desc::BuiltIn::TessLevelInner |
+                    desc::BuiltIn::TessCoord |
+                    desc::BuiltIn::PatchVertices => self.tessellation = true,
+                    _ => ()
+                }
+            }
+            _ => ()
+        }
+    }
+
+    /// Whether the capability could still be needed
+    fn needs(&self, model: ExecutionModel, capability: Capability) -> bool {
+        let geometry = self.geometry || model == ExecutionModel::Geometry;
+        let tessellation = self.tessellation ||
+            model == ExecutionModel::TesselationControl ||
+            model == ExecutionModel::TesselationEvaluation;
+
+        match capability {
+            Capability::Int8 => self.int8,
+            Capability::Int16 => self.int16,
+            Capability::Int64 |
+            Capability::Int64Atomics => self.int64,
+            Capability::Float16 => self.float16,
+            Capability::Float64 => self.float64,
+            Capability::Geometry |
+            Capability::GeometryPointSize |
+            Capability::GeometryStreams => geometry,
+            Capability::Tessellation |
+            Capability::TessellationPointSize => tessellation,
+            _ => true
+        }
+    }
+}
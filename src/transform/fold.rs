@@ -0,0 +1,841 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{HashMap, HashSet};
+
+use desc::{TypeId, ValueId, ResultId};
+use instruction::{Decoration, Instruction};
+use parse::Header;
+use RawModule;
+
+/**
+ * Evaluate arithmetic, bitwise, relational and composite instructions
+ * whose operands are all constants, replacing them with constant
+ * definitions using the same result id.
+ *
+ * Only 32-bit and 64-bit integers and floats are folded. Floating-point
+ * operations are only folded when IEEE 754 fully defines the result, and
+ * instructions decorated with `NoContraction` are left alone. Specialization
+ * constants are never treated as constant.
+ */
+pub fn fold_constants(module: &RawModule) -> RawModule {
+    let mut folder = Folder {
+        types: HashMap::new(),
+        consts: HashMap::new(),
+        scalars: HashMap::new(),
+        new_consts: Vec::new(),
+        next_id: module.header().id_bound
+    };
+
+    let mut no_contraction = HashSet::new();
+    let mut first_function = None;
+
+    for (idx, inst) in module.instructions().iter().enumerate() {
+        match *inst {
+            Instruction::Decorate { target, decoration: Decoration::NoContraction } => {
+                no_contraction.insert(target.to_value_id());
+            }
+            Instruction::Function { .. } => {
+                if first_function.is_none() {
+                    first_function = Some(idx);
+                }
+            }
+            _ => ()
+        }
+
+        if first_function.is_none() {
+            folder.add_global(inst);
+        }
+    }
+
+    let first_function = first_function.unwrap_or(module.instructions().len());
+
+    let mut body = Vec::new();
+    for inst in &module.instructions()[first_function..] {
+        let folded = match inst.defines_value() {
+            Some(id) if !no_contraction.contains(&id) => folder.fold(inst),
+            _ => None
+        };
+
+        match folded {
+            Some(c) => {
+                let rt = inst.type_id_of().unwrap();
+                let id = inst.defines_value().unwrap();
+                let def = folder.define(rt, ResultId(id.0), c);
+                folder.new_consts.push(def);
+            }
+            None => body.push(inst.clone())
+        }
+    }
+
+    let mut instructions = Vec::with_capacity(module.instructions().len());
+    instructions.extend_from_slice(&module.instructions()[..first_function]);
+    instructions.extend(folder.new_consts.drain(..));
+    instructions.extend(body);
+
+    let header = Header {
+        id_bound: folder.next_id,
+        .. module.header().clone()
+    };
+
+    RawModule::from_instructions(header, instructions)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Type {
+    Bool,
+    Int(u32, bool),
+    Float(u32),
+    Vector(TypeId, u32),
+    Other
+}
+
+#[derive(Clone, Debug)]
+enum Const {
+    /// Scalar value, integers are zero-extended
+    Scalar(u64),
+    Composite(Vec<ValueId>),
+    Null
+}
+
+#[derive(Copy, Clone, Debug)]
+enum BinOp {
+    IAdd, ISub, IMul, UDiv, SDiv, UMod, SRem, SMod,
+    FAdd, FSub, FMul, FDiv, FRem,
+    ShiftRightLogical, ShiftRightArithmetic, ShiftLeftLogical,
+    BitwiseOr, BitwiseXor, BitwiseAnd,
+    LogicalEqual, LogicalNotEqual, LogicalOr, LogicalAnd,
+    IEqual, INotEqual,
+    UGreaterThan, SGreaterThan, UGreaterThanEqual, SGreaterThanEqual,
+    ULessThan, SLessThan, ULessThanEqual, SLessThanEqual,
+    FOrdEqual, FUnordEqual, FOrdNotEqual, FUnordNotEqual,
+    FOrdLessThan, FUnordLessThan, FOrdGreaterThan, FUnordGreaterThan,
+    FOrdLessThanEqual, FUnordLessThanEqual,
+    FOrdGreaterThanEqual, FUnordGreaterThanEqual
+}
+
+#[derive(Copy, Clone, Debug)]
+enum UnOp {
+    SNegate, FNegate, Not, LogicalNot
+}
+
+struct Folder {
+    types: HashMap<TypeId, Type>,
+    consts: HashMap<ValueId, (TypeId, Const)>,
+    scalars: HashMap<(TypeId, u64), ValueId>,
+    new_consts: Vec<Instruction>,
+    next_id: u32
+}
+
+impl Folder {
+    fn add_global(&mut self, inst: &Instruction) {
+        use instruction::Instruction::*;
+        match *inst {
+            TypeBool { result_type } => {
+                self.types.insert(result_type, Type::Bool);
+            }
+            TypeInt { result_type, width, signed } => {
+                self.types.insert(result_type, Type::Int(width, signed));
+            }
+            TypeFloat { result_type, width } => {
+                self.types.insert(result_type, Type::Float(width));
+            }
+            // Vectors have between 2 and 16 components, anything else
+            // isn't folded rather than trusted for allocating components
+            TypeVector { result_type, type_id, len } if len >= 2 && len <= 16 => {
+                self.types.insert(result_type, Type::Vector(type_id, len));
+            }
+            _ => {
+                if let Some(ty) = inst.defines_type() {
+                    self.types.insert(ty, Type::Other);
+                }
+            }
+        }
+
+        let c = match *inst {
+            ConstantTrue { .. } => Const::Scalar(1),
+            ConstantFalse { .. } => Const::Scalar(0),
+            Constant { result_type, ref val, .. } => {
+                match (self.ty(result_type), val.len()) {
+                    (Type::Int(32, _), 1) | (Type::Float(32), 1) => {
+                        Const::Scalar(val[0] as u64)
+                    }
+                    (Type::Int(64, _), 2) | (Type::Float(64), 2) => {
+                        Const::Scalar((val[0] as u64) | ((val[1] as u64) << 32))
+                    }
+                    _ => return
+                }
+            }
+            ConstantComposite { ref flds, .. } => {
+                Const::Composite(flds.to_vec())
+            }
+            ConstantNull { .. } => Const::Null,
+            _ => return
+        };
+
+        let ty = inst.type_id_of().unwrap();
+        let id = inst.defines_value().unwrap();
+        if let Const::Scalar(bits) = c {
+            self.scalars.entry((ty, bits)).or_insert(id);
+        }
+        self.consts.insert(id, (ty, c));
+    }
+
+    fn ty(&self, ty: TypeId) -> Type {
+        self.types.get(&ty).cloned().unwrap_or(Type::Other)
+    }
+
+    fn value(&self, id: ValueId) -> Option<&(TypeId, Const)> {
+        self.consts.get(&id)
+    }
+
+    /// Build the instruction defining a constant, and record its value
+    fn define(&mut self, ty: TypeId, id: ResultId, c: Const) -> Instruction {
+        let inst = match c {
+            Const::Scalar(bits) => {
+                match self.ty(ty) {
+                    Type::Bool => {
+                        if bits != 0 {
+                            Instruction::ConstantTrue { result_type: ty, result_id: id }
+                        } else {
+                            Instruction::ConstantFalse { result_type: ty, result_id: id }
+                        }
+                    }
+                    Type::Int(64, _) | Type::Float(64) => {
+                        Instruction::Constant {
+                            result_type: ty,
+                            result_id: id,
+                            val: vec![bits as u32, (bits >> 32) as u32].into_boxed_slice()
+                        }
+                    }
+                    _ => {
+                        Instruction::Constant {
+                            result_type: ty,
+                            result_id: id,
+                            val: vec![bits as u32].into_boxed_slice()
+                        }
+                    }
+                }
+            }
+            Const::Composite(ref flds) => {
+                Instruction::ConstantComposite {
+                    result_type: ty,
+                    result_id: id,
+                    flds: flds.clone().into_boxed_slice()
+                }
+            }
+            Const::Null => {
+                Instruction::ConstantNull { result_type: ty, result_id: id }
+            }
+        };
+
+        let value_id = id.to_value_id();
+        if let Const::Scalar(bits) = c {
+            self.scalars.entry((ty, bits)).or_insert(value_id);
+        }
+        self.consts.insert(value_id, (ty, c));
+
+        inst
+    }
+
+    /// Gets the id of a scalar constant, defining a new one if needed
+    fn scalar(&mut self, ty: TypeId, bits: u64) -> ValueId {
+        if let Some(&id) = self.scalars.get(&(ty, bits)) {
+            return id;
+        }
+
+        let id = ResultId(self.next_id);
+        self.next_id += 1;
+
+        let def = self.define(ty, id, Const::Scalar(bits));
+        self.new_consts.push(def);
+
+        id.to_value_id()
+    }
+
+    /// Gets the ids of the components of a constant vector
+    fn components(&mut self, id: ValueId) -> Option<Vec<ValueId>> {
+        let (ty, c) = match self.value(id) {
+            Some(&(ty, ref c)) => (ty, c.clone()),
+            None => return None
+        };
+
+        match (self.ty(ty), c) {
+            (Type::Vector(_, len), Const::Composite(flds)) => {
+                if flds.len() == len as usize {
+                    Some(flds)
+                } else {
+                    None
+                }
+            }
+            (Type::Vector(comp, len), Const::Null) => {
+                let zero = self.scalar(comp, 0);
+                Some(vec![zero; len as usize])
+            }
+            _ => None
+        }
+    }
+
+    fn scalar_value(&self, id: ValueId) -> Option<(Type, u64)> {
+        match self.value(id) {
+            Some(&(ty, Const::Scalar(bits))) => Some((self.ty(ty), bits)),
+            Some(&(ty, Const::Null)) => {
+                match self.ty(ty) {
+                    Type::Vector(..) | Type::Other => None,
+                    t => Some((t, 0))
+                }
+            }
+            _ => None
+        }
+    }
+
+    fn fold(&mut self, inst: &Instruction) -> Option<Const> {
+        use instruction::Instruction::*;
+
+        let rt = match inst.type_id_of() {
+            Some(rt) => rt,
+            None => return None
+        };
+
+        match *inst {
+            SNegate { value_id, .. } => self.unary(UnOp::SNegate, rt, value_id),
+            FNegate { value_id, .. } => self.unary(UnOp::FNegate, rt, value_id),
+            Not { value_id, .. } => self.unary(UnOp::Not, rt, value_id),
+            LogicalNot { value_id, .. } => self.unary(UnOp::LogicalNot, rt, value_id),
+
+            IAdd { lhs, rhs, .. } => self.binary(BinOp::IAdd, rt, lhs, rhs),
+            ISub { lhs, rhs, .. } => self.binary(BinOp::ISub, rt, lhs, rhs),
+            IMul { lhs, rhs, .. } => self.binary(BinOp::IMul, rt, lhs, rhs),
+            UDiv { lhs, rhs, .. } => self.binary(BinOp::UDiv, rt, lhs, rhs),
+            SDiv { lhs, rhs, .. } => self.binary(BinOp::SDiv, rt, lhs, rhs),
+            UMod { lhs, rhs, .. } => self.binary(BinOp::UMod, rt, lhs, rhs),
+            SRem { lhs, rhs, .. } => self.binary(BinOp::SRem, rt, lhs, rhs),
+            SMod { lhs, rhs, .. } => self.binary(BinOp::SMod, rt, lhs, rhs),
+            FAdd { lhs, rhs, .. } => self.binary(BinOp::FAdd, rt, lhs, rhs),
+            FSub { lhs, rhs, .. } => self.binary(BinOp::FSub, rt, lhs, rhs),
+            FMul { lhs, rhs, .. } => self.binary(BinOp::FMul, rt, lhs, rhs),
+            FDiv { lhs, rhs, .. } => self.binary(BinOp::FDiv, rt, lhs, rhs),
+            FRem { lhs, rhs, .. } => self.binary(BinOp::FRem, rt, lhs, rhs),
+
+            ShiftRightLogical { base, shift, .. } => {
+                self.binary(BinOp::ShiftRightLogical, rt, base, shift)
+            }
+            ShiftRightArithmetic { base, shift, .. } => {
+                self.binary(BinOp::ShiftRightArithmetic, rt, base, shift)
+            }
+            ShiftLeftLogical { base, shift, .. } => {
+                self.binary(BinOp::ShiftLeftLogical, rt, base, shift)
+            }
+            BitwiseOr { lhs, rhs, .. } => self.binary(BinOp::BitwiseOr, rt, lhs, rhs),
+            BitwiseXor { lhs, rhs, .. } => self.binary(BinOp::BitwiseXor, rt, lhs, rhs),
+            BitwiseAnd { lhs, rhs, .. } => self.binary(BinOp::BitwiseAnd, rt, lhs, rhs),
+
+            LogicalEqual { lhs, rhs, .. } => self.binary(BinOp::LogicalEqual, rt, lhs, rhs),
+            LogicalNotEqual { lhs, rhs, .. } => self.binary(BinOp::LogicalNotEqual, rt, lhs, rhs),
+            LogicalOr { lhs, rhs, .. } => self.binary(BinOp::LogicalOr, rt, lhs, rhs),
+            LogicalAnd { lhs, rhs, .. } => self.binary(BinOp::LogicalAnd, rt, lhs, rhs),
+            IEqual { lhs, rhs, .. } => self.binary(BinOp::IEqual, rt, lhs, rhs),
+            INotEqual { lhs, rhs, .. } => self.binary(BinOp::INotEqual, rt, lhs, rhs),
+            UGreaterThan { lhs, rhs, .. } => self.binary(BinOp::UGreaterThan, rt, lhs, rhs),
+            SGreaterThan { lhs, rhs, .. } => self.binary(BinOp::SGreaterThan, rt, lhs, rhs),
+            UGreaterThanEqual { lhs, rhs, .. } => {
+                self.binary(BinOp::UGreaterThanEqual, rt, lhs, rhs)
+            }
+            SGreaterThanEqual { lhs, rhs, .. } => {
+                self.binary(BinOp::SGreaterThanEqual, rt, lhs, rhs)
+            }
+            ULessThan { lhs, rhs, .. } => self.binary(BinOp::ULessThan, rt, lhs, rhs),
+            SLessThan { lhs, rhs, .. } => self.binary(BinOp::SLessThan, rt, lhs, rhs),
+            ULessThanEqual { lhs, rhs, .. } => self.binary(BinOp::ULessThanEqual, rt, lhs, rhs),
+            SLessThanEqual { lhs, rhs, .. } => self.binary(BinOp::SLessThanEqual, rt, lhs, rhs),
+            FOrdEqual { lhs, rhs, .. } => self.binary(BinOp::FOrdEqual, rt, lhs, rhs),
+            FUnordEqual { lhs, rhs, .. } => self.binary(BinOp::FUnordEqual, rt, lhs, rhs),
+            FOrdNotEqual { lhs, rhs, .. } => self.binary(BinOp::FOrdNotEqual, rt, lhs, rhs),
+            FUnordNotEqual { lhs, rhs, .. } => self.binary(BinOp::FUnordNotEqual, rt, lhs, rhs),
+            FOrdLessThan { lhs, rhs, .. } => self.binary(BinOp::FOrdLessThan, rt, lhs, rhs),
+            FUnordLessThan { lhs, rhs, .. } => self.binary(BinOp::FUnordLessThan, rt, lhs, rhs),
+            FOrdGreaterThan { lhs, rhs, .. } => self.binary(BinOp::FOrdGreaterThan, rt, lhs, rhs),
+            FUnordGreaterThan { lhs, rhs, .. } => {
+                self.binary(BinOp::FUnordGreaterThan, rt, lhs, rhs)
+            }
+            FOrdLessThanEqual { lhs, rhs, .. } => {
+                self.binary(BinOp::FOrdLessThanEqual, rt, lhs, rhs)
+            }
+            FUnordLessThanEqual { lhs, rhs, .. } => {
+                self.binary(BinOp::FUnordLessThanEqual, rt, lhs, rhs)
+            }
+            FOrdGreaterThanEqual { lhs, rhs, .. } => {
+                self.binary(BinOp::FOrdGreaterThanEqual, rt, lhs, rhs)
+            }
+            FUnordGreaterThanEqual { lhs, rhs, .. } => {
+                self.binary(BinOp::FUnordGreaterThanEqual, rt, lhs, rhs)
+            }
+
+            Select { condition, obj_1, obj_2, .. } => self.select(condition, obj_1, obj_2),
+            CompositeConstruct { ref fields, .. } => self.construct(rt, fields),
+            CompositeExtract { obj, ref indices, .. } => self.extract(obj, indices),
+            CopyObject { value_id, .. } => self.value(value_id).map(|v| v.1.clone()),
+
+            _ => None
+        }
+    }
+
+    fn unary(&mut self, op: UnOp, rt: TypeId, val: ValueId) -> Option<Const> {
+        if let Type::Vector(comp_ty, _) = self.ty(rt) {
+            let vals = match self.components(val) {
+                Some(vals) => vals,
+                None => return None
+            };
+
+            let mut comps = Vec::with_capacity(vals.len());
+            for v in vals {
+                let (ty, bits) = match self.scalar_value(v) {
+                    Some(v) => v,
+                    None => return None
+                };
+                let res = match eval_unary(op, ty, bits) {
+                    Some(res) => res,
+                    None => return None
+                };
+                comps.push(self.scalar(comp_ty, res));
+            }
+            Some(Const::Composite(comps))
+        } else {
+            self.scalar_value(val).and_then(|(ty, bits)| {
+                eval_unary(op, ty, bits)
+            }).map(Const::Scalar)
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, rt: TypeId, lhs: ValueId, rhs: ValueId) -> Option<Const> {
+        if let Type::Vector(comp_ty, _) = self.ty(rt) {
+            let (lhs, rhs) = match (self.components(lhs), self.components(rhs)) {
+                (Some(lhs), Some(rhs)) => (lhs, rhs),
+                _ => return None
+            };
+            if lhs.len() != rhs.len() { return None; }
+
+            let mut comps = Vec::with_capacity(lhs.len());
+            for (l, r) in lhs.into_iter().zip(rhs) {
+                let res = match (self.scalar_value(l), self.scalar_value(r)) {
+                    (Some(l), Some(r)) => eval_binary(op, l, r),
+                    _ => None
+                };
+                match res {
+                    Some(res) => comps.push(self.scalar(comp_ty, res)),
+                    None => return None
+                }
+            }
+            Some(Const::Composite(comps))
+        } else {
+            match (self.scalar_value(lhs), self.scalar_value(rhs)) {
+                (Some(l), Some(r)) => eval_binary(op, l, r).map(Const::Scalar),
+                _ => None
+            }
+        }
+    }
+
+    fn select(&mut self, cond: ValueId, a: ValueId, b: ValueId) -> Option<Const> {
+        if let Some((Type::Bool, c)) = self.scalar_value(cond) {
+            let chosen = if c != 0 { a } else { b };
+            return self.value(chosen).map(|v| v.1.clone());
+        }
+
+        // Vector conditions select component-wise
+        let (conds, a, b) = match (self.components(cond), self.components(a), self.components(b)) {
+            (Some(c), Some(a), Some(b)) => (c, a, b),
+            _ => return None
+        };
+        if conds.len() != a.len() || conds.len() != b.len() { return None; }
+
+        let mut comps = Vec::with_capacity(conds.len());
+        for i in 0..conds.len() {
+            match self.scalar_value(conds[i]) {
+                Some((Type::Bool, c)) => comps.push(if c != 0 { a[i] } else { b[i] }),
+                _ => return None
+            }
+        }
+
+        Some(Const::Composite(comps))
+    }
+
+    fn construct(&mut self, rt: TypeId, fields: &[ValueId]) -> Option<Const> {
+        if let Type::Vector(_, len) = self.ty(rt) {
+            // Vectors can be constructed from a mix of scalars and
+            // vectors, the components of which are concatenated
+            let mut comps = Vec::with_capacity(len as usize);
+            for &f in fields {
+                match self.value(f) {
+                    Some(&(ty, _)) => {
+                        if let Type::Vector(..) = self.ty(ty) {
+                            match self.components(f) {
+                                Some(c) => comps.extend(c),
+                                None => return None
+                            }
+                        } else {
+                            comps.push(f);
+                        }
+                    }
+                    None => return None
+                }
+            }
+
+            if comps.len() == len as usize {
+                Some(Const::Composite(comps))
+            } else {
+                None
+            }
+        } else {
+            if fields.iter().all(|f| self.consts.contains_key(f)) {
+                Some(Const::Composite(fields.to_vec()))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn extract(&mut self, obj: ValueId, indices: &[u32]) -> Option<Const> {
+        let mut cur = obj;
+        for &idx in indices {
+            match self.value(cur) {
+                Some(&(_, Const::Composite(ref flds))) => {
+                    match flds.get(idx as usize) {
+                        Some(&f) => cur = f,
+                        None => return None
+                    }
+                }
+                Some(&(_, Const::Null)) => return Some(Const::Null),
+                _ => return None
+            }
+        }
+
+        self.value(cur).map(|v| v.1.clone())
+    }
+}
+
+fn mask(width: u32) -> u64 {
+    if width >= 64 { !0 } else { (1 << width) - 1 }
+}
+
+fn sext(width: u32, bits: u64) -> i64 {
+    let shift = 64 - width;
+    ((bits << shift) as i64) >> shift
+}
+
+fn eval_unary(op: UnOp, ty: Type, a: u64) -> Option<u64> {
+    match (op, ty) {
+        (UnOp::SNegate, Type::Int(w, _)) if w == 32 || w == 64 => {
+            Some(a.wrapping_neg() & mask(w))
+        }
+        (UnOp::Not, Type::Int(w, _)) if w == 32 || w == 64 => Some(!a & mask(w)),
+        // Negation only flips the sign bit, so it's always exact
+        (UnOp::FNegate, Type::Float(w)) if w == 32 || w == 64 => Some(a ^ (1 << (w - 1))),
+        (UnOp::LogicalNot, Type::Bool) => Some((a == 0) as u64),
+        _ => None
+    }
+}
+
+fn eval_binary(op: BinOp, lhs: (Type, u64), rhs: (Type, u64)) -> Option<u64> {
+    use self::BinOp::*;
+
+    let (lty, a) = lhs;
+    let (rty, b) = rhs;
+
+    match (lty, rty) {
+        (Type::Bool, Type::Bool) => {
+            let (a, b) = (a != 0, b != 0);
+            let res = match op {
+                LogicalEqual => a == b,
+                LogicalNotEqual => a != b,
+                LogicalOr => a || b,
+                LogicalAnd => a && b,
+                _ => return None
+            };
+            Some(res as u64)
+        }
+        (Type::Int(w, _), Type::Int(rw, _)) => {
+            if !(w == 32 || w == 64) || !(rw == 32 || rw == 64) {
+                return None;
+            }
+
+            // Shifts can have differently sized operands, everything
+            // else needs matching widths
+            match op {
+                ShiftRightLogical | ShiftRightArithmetic | ShiftLeftLogical => {
+                    if b >= w as u64 { return None; }
+                    let res = match op {
+                        ShiftRightLogical => a >> b,
+                        ShiftRightArithmetic => (sext(w, a) >> b) as u64,
+                        _ => a << b
+                    };
+                    return Some(res & mask(w));
+                }
+                _ => ()
+            }
+
+            if w != rw { return None; }
+
+            let (sa, sb) = (sext(w, a), sext(w, b));
+            let min = sext(w, 1 << (w - 1));
+            let res = match op {
+                IAdd => a.wrapping_add(b),
+                ISub => a.wrapping_sub(b),
+                IMul => a.wrapping_mul(b),
+                UDiv => if b == 0 { return None } else { a / b },
+                UMod => if b == 0 { return None } else { a % b },
+                SDiv | SRem | SMod => {
+                    if b == 0 || (sa == min && sb == -1) { return None; }
+                    let res = match op {
+                        SDiv => sa / sb,
+                        SRem => sa % sb,
+                        _ => {
+                            let r = sa % sb;
+                            if r != 0 && (r < 0) != (sb < 0) { r + sb } else { r }
+                        }
+                    };
+                    res as u64
+                }
+                BitwiseOr => a | b,
+                BitwiseXor => a ^ b,
+                BitwiseAnd => a & b,
+                IEqual => (a == b) as u64,
+                INotEqual => (a != b) as u64,
+                UGreaterThan => (a > b) as u64,
+                SGreaterThan => (sa > sb) as u64,
+                UGreaterThanEqual => (a >= b) as u64,
+                SGreaterThanEqual => (sa >= sb) as u64,
+                ULessThan => (a < b) as u64,
+                SLessThan => (sa < sb) as u64,
+                ULessThanEqual => (a <= b) as u64,
+                SLessThanEqual => (sa <= sb) as u64,
+                _ => return None
+            };
+
+            Some(res & mask(w))
+        }
+        (Type::Float(32), Type::Float(32)) => {
+            let (x, y) = (f32::from_bits(a as u32), f32::from_bits(b as u32));
+            eval_float(op, x as f64, y as f64, |v| (v as f32).to_bits() as u64)
+        }
+        (Type::Float(64), Type::Float(64)) => {
+            let (x, y) = (f64::from_bits(a), f64::from_bits(b));
+            eval_float(op, x, y, |v| v.to_bits())
+        }
+        _ => None
+    }
+}
+
+fn eval_float<F: Fn(f64) -> u64>(op: BinOp, x: f64, y: f64, to_bits: F) -> Option<u64> {
+    use self::BinOp::*;
+
+    let unord = x.is_nan() || y.is_nan();
+    let res = match op {
+        // Single precision operations are performed in double precision
+        // and then rounded. The double result of a sum, difference,
+        // product or quotient of two singles is rounded only once, so
+        // rounding again to single gives the correctly rounded result.
+        FAdd => return Some(to_bits(x + y)),
+        FSub => return Some(to_bits(x - y)),
+        FMul => return Some(to_bits(x * y)),
+        FDiv => return Some(to_bits(x / y)),
+        // The remainder is always exact
+        FRem => return Some(to_bits(x % y)),
+        FOrdEqual => x == y,
+        FUnordEqual => unord || x == y,
+        FOrdNotEqual => !unord && x != y,
+        FUnordNotEqual => x != y,
+        FOrdLessThan => x < y,
+        FUnordLessThan => unord || x < y,
+        FOrdGreaterThan => x > y,
+        FUnordGreaterThan => unord || x > y,
+        FOrdLessThanEqual => x <= y,
+        FUnordLessThanEqual => unord || x <= y,
+        FOrdGreaterThanEqual => x >= y,
+        FUnordGreaterThanEqual => unord || x >= y,
+        _ => return None
+    };
+
+    Some(res as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc;
+    use instruction::Instruction;
+    use validate::validate;
+    use RawModule;
+
+    use super::{eval_binary, fold_constants, BinOp, Type};
+
+    /// Folds the instructions, which go in a function after some
+    /// constants named after their values
+    fn fold(body: &str) -> RawModule {
+        fold_with(body, "")
+    }
+
+    fn fold_with(body: &str, decorations: &str) -> RawModule {
+        let module = assemble(&format!("
+            OpCapability Shader
+            OpMemoryModel Logical GLSL450
+            {}
+            %void = OpTypeVoid
+            %fn_void = OpTypeFunction %void
+            %int = OpTypeInt 32 1
+            %uint = OpTypeInt 32 0
+            %float = OpTypeFloat 32
+            %v2int = OpTypeVector %int 2
+            %zero = OpConstant %int 0
+            %one = OpConstant %int 1
+            %two = OpConstant %int 2
+            %n1 = OpConstant %int -1
+            %min = OpConstant %int -2147483648
+            %u32 = OpConstant %uint 32
+            %f_1 = OpConstant %float 1
+            %f_3 = OpConstant %float 3
+            %v_1_2 = OpConstantComposite %v2int %one %two
+            %main = OpFunction %void None %fn_void
+            %entry = OpLabel
+            {}
+            OpReturn
+            OpFunctionEnd
+        ", decorations, body)).unwrap();
+        assert!(validate(&module).is_empty());
+
+        let folded = fold_constants(&module);
+        assert!(validate(&folded).is_empty());
+        folded
+    }
+
+    fn count(module: &RawModule, op: desc::Op) -> usize {
+        module.instructions().iter().filter(|inst| inst.opcode() == op as u16).count()
+    }
+
+    /// Whether the instructions with the given opcode were folded away
+    fn folded(body: &str, op: desc::Op) -> bool {
+        count(&fold(body), op) == 0
+    }
+
+    #[test]
+    fn integers() {
+        assert!(folded("%x = OpIAdd %int %one %two", desc::Op::IAdd));
+        // Wrapping is defined
+        assert!(folded("%x = OpIAdd %int %min %n1", desc::Op::IAdd));
+        assert!(folded("%x = OpSDiv %int %min %two", desc::Op::SDiv));
+        assert!(folded("%x = OpIAdd %v2int %v_1_2 %v_1_2", desc::Op::IAdd));
+
+        let module = fold("%x = OpISub %int %one %two");
+        let x = module.instructions().iter().find(|inst| {
+            if let Instruction::Constant { ref val, .. } = **inst { val[0] == !0 } else { false }
+        });
+        assert!(x.is_some());
+    }
+
+    #[test]
+    fn undefined_integers() {
+        // Division by zero
+        assert!(!folded("%x = OpSDiv %int %one %zero", desc::Op::SDiv));
+        assert!(!folded("%x = OpUDiv %int %one %zero", desc::Op::UDiv));
+        assert!(!folded("%x = OpUMod %int %one %zero", desc::Op::UMod));
+        assert!(!folded("%x = OpSMod %int %one %zero", desc::Op::SMod));
+
+        // Overflow of signed division
+        assert!(!folded("%x = OpSDiv %int %min %n1", desc::Op::SDiv));
+        assert!(!folded("%x = OpSRem %int %min %n1", desc::Op::SRem));
+
+        // Shifts by the width or more
+        assert!(!folded("%x = OpShiftLeftLogical %int %one %u32", desc::Op::ShiftLeftLogical));
+        assert!(!folded("%x = OpShiftRightLogical %int %one %u32", desc::Op::ShiftRightLogical));
+        assert!(folded("%x = OpShiftLeftLogical %int %one %two", desc::Op::ShiftLeftLogical));
+    }
+
+    #[test]
+    fn no_contraction() {
+        let body = "
+            %x = OpFMul %float %f_1 %f_3
+            %y = OpFAdd %float %x %f_1
+        ";
+        let module = fold_with(body, "OpDecorate %y NoContraction");
+        assert_eq!(count(&module, desc::Op::FMul), 0);
+        assert_eq!(count(&module, desc::Op::FAdd), 1);
+
+        let module = fold(body);
+        assert_eq!(count(&module, desc::Op::FAdd), 0);
+    }
+
+    #[test]
+    fn constants_before_functions() {
+        let module = fold("
+            %x = OpIAdd %int %one %two
+            %y = OpIMul %v2int %v_1_2 %v_1_2
+            %z = OpFDiv %float %f_1 %f_3
+        ");
+        let function = module.instructions().iter().position(|inst| {
+            inst.opcode() == desc::Op::Function as u16
+        }).unwrap();
+        for (idx, inst) in module.instructions().iter().enumerate() {
+            if inst.group() == Some("Constant") {
+                assert!(idx < function);
+            }
+        }
+        // 1 * 1 is already defined, 2 * 2 and the results aren't
+        assert_eq!(count(&module, desc::Op::Constant), 11);
+    }
+
+    #[test]
+    fn single_precision_is_exact() {
+        // Compare with single precision arithmetic on values that are
+        // spread over the whole range of floats
+        let mut seed = 1u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let hi = seed >> 16;
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            hi << 16 | seed >> 16
+        };
+
+        for _ in 0..100000 {
+            let (a, b) = (next(), next());
+            let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+            for &(op, expected) in &[(BinOp::FAdd, x + y), (BinOp::FSub, x - y),
+                                     (BinOp::FMul, x * y), (BinOp::FDiv, x / y),
+                                     (BinOp::FRem, x % y)] {
+                let res = eval_binary(op, (Type::Float(32), a as u64),
+                                      (Type::Float(32), b as u64)).unwrap();
+                let res = f32::from_bits(res as u32);
+                if expected.is_nan() {
+                    assert!(res.is_nan());
+                } else {
+                    assert_eq!(res.to_bits(), expected.to_bits(), "{:?} {:?} {:?}", op, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_vector_length() {
+        let module = assemble("
+            OpCapability Shader
+            OpMemoryModel Logical GLSL450
+            %void = OpTypeVoid
+            %fn_void = OpTypeFunction %void
+            %int = OpTypeInt 32 1
+            %vbig = OpTypeVector %int 2147483647
+            %null = OpConstantNull %vbig
+            %main = OpFunction %void None %fn_void
+            %entry = OpLabel
+            %x = OpIAdd %vbig %null %null
+            OpReturn
+            OpFunctionEnd
+        ").unwrap();
+
+        let folded = fold_constants(&module);
+        assert_eq!(count(&folded, desc::Op::IAdd), 1);
+    }
+}
+
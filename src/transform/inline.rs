@@ -0,0 +1,1069 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use desc::{self, Id, TypeId, ValueId, ResultId};
+use instruction::{Decoration, IdRole, Instruction};
+use parse::Header;
+use write::encode_instruction;
+use RawModule;
+
+use super::{annotations_end, functions};
+
+/**
+ * Inline every function call, then remove any functions that are no
+ * longer called. Entry points and functions exported with
+ * `LinkageAttributes` are always kept.
+ *
+ * Callees that return from more than one place are wrapped in a loop
+ * that runs once, so the returns become breaks from the loop and the
+ * structured control flow stays valid. If the callee has loops of its
+ * own, a return from inside one of them can't break out of the wrapper
+ * directly. Instead the return value is stored to a variable and a flag
+ * is set, the return breaks from the innermost loop, and the merge block
+ * of each loop checks the flag and breaks from the next loop out.
+ *
+ * Recursive calls, calls to functions without a body, and calls where a
+ * loop that is returned from merges straight into the header of another
+ * loop are left alone.
+ */
+pub fn inline_functions(module: &RawModule) -> RawModule {
+    let funcs = functions(module);
+
+    let mut decorations : HashMap<Id, Vec<Decoration>> = HashMap::new();
+    let mut exported = HashSet::new();
+    for inst in module.instructions() {
+        if let Instruction::Decorate { target, ref decoration } = *inst {
+            if let Decoration::LinkageAttributes(_, desc::LinkageType::Export) = *decoration {
+                exported.insert(target.to_value_id());
+            }
+            decorations.entry(target).or_insert_with(Vec::new).push(decoration.clone());
+        }
+    }
+
+    let mut globals = HashMap::new();
+    for inst in module.instructions() {
+        if let Some(def) = inst.defines() {
+            if inst.defines_type().is_some() || inst.group() == Some("Constant") {
+                globals.entry(global_key(inst)).or_insert(def);
+            }
+        }
+    }
+
+    let mut inliner = Inliner {
+        module: module,
+        funcs: funcs.iter().cloned().collect(),
+        decorations: decorations,
+        inlined: HashMap::new(),
+        in_progress: HashSet::new(),
+        new_decorations: Vec::new(),
+        globals: globals,
+        new_globals: Vec::new(),
+        next_id: module.header().id_bound
+    };
+
+    for &(id, _) in &funcs {
+        inliner.process(id);
+    }
+
+    // Find the functions that are still used after inlining
+    let mut kept = HashSet::new();
+    let mut worklist : Vec<ValueId> = exported.into_iter().collect();
+    for inst in module.instructions() {
+        if let Instruction::EntryPoint { func, .. } = *inst {
+            worklist.push(func);
+        }
+    }
+
+    while let Some(func) = worklist.pop() {
+        if !kept.insert(func) { continue; }
+        if let Some(body) = inliner.inlined.get(&func) {
+            for inst in body {
+                if let Instruction::FunctionCall { func, .. } = *inst {
+                    worklist.push(func);
+                }
+            }
+        }
+    }
+
+    // Names and decorations of anything defined in a removed function
+    // need to be removed too
+    let mut in_function = vec![false; module.instructions().len()];
+    let mut removed_ids = HashSet::new();
+    for &(id, ref range) in &funcs {
+        for idx in range.clone() {
+            in_function[idx] = true;
+            if !kept.contains(&id) {
+                if let Some(def) = module.instructions()[idx].defines() {
+                    removed_ids.insert(def);
+                }
+            }
+        }
+    }
+
+    let annotations_end = annotations_end(module);
+    let mut instructions = Vec::with_capacity(module.instructions().len());
+    for (idx, inst) in module.instructions().iter().enumerate() {
+        if idx == annotations_end {
+            instructions.extend(inliner.new_decorations.drain(..));
+        }
+        if in_function[idx] { continue; }
+
+        match *inst {
+            Instruction::Name { id, .. } |
+            Instruction::Decorate { target: id, .. } => {
+                if removed_ids.contains(&id) { continue; }
+            }
+            _ => ()
+        }
+
+        instructions.push(inst.clone());
+    }
+    instructions.extend(inliner.new_decorations.drain(..));
+    instructions.extend(inliner.new_globals.drain(..));
+
+    // A function defined more than once only has one body, which is kept
+    // in place of the first
+    for &(id, _) in &funcs {
+        if kept.contains(&id) {
+            if let Some(body) = inliner.inlined.remove(&id) {
+                instructions.extend(body);
+            }
+        }
+    }
+
+    let header = Header {
+        id_bound: inliner.next_id,
+        .. module.header().clone()
+    };
+
+    RawModule::from_instructions(header, instructions)
+}
+
+struct Inliner<'a> {
+    module: &'a RawModule,
+    funcs: HashMap<ValueId, Range<usize>>,
+    decorations: HashMap<Id, Vec<Decoration>>,
+    /// Function bodies with all possible calls inlined
+    inlined: HashMap<ValueId, Vec<Instruction>>,
+    in_progress: HashSet<ValueId>,
+    new_decorations: Vec<Instruction>,
+    /// The types and constants in the module, keyed by their encoding
+    /// with a placeholder result id
+    globals: HashMap<Vec<u32>, Id>,
+    /// Types and constants that inlining needed but the module didn't
+    /// have
+    new_globals: Vec<Instruction>,
+    next_id: u32
+}
+
+/// A loop in a callee
+struct Loop {
+    merge: ValueId,
+    /// The blocks inside the loop, not including the merge block
+    blocks: HashSet<ValueId>
+}
+
+/// How the returns in a callee were rewritten to set a flag
+struct Lowered {
+    body: Vec<Instruction>,
+    flag: ValueId,
+    flag_ptr_type: TypeId,
+    /// The variable the return value is stored to, and its type
+    ret_var: Option<(ValueId, TypeId)>
+}
+
+/// The instructions in a function from the callee's point of view
+struct Callee {
+    params: Vec<ValueId>,
+    body: Vec<Instruction>,
+    single_exit: bool,
+    has_loops: bool
+}
+
+impl<'a> Inliner<'a> {
+    fn fresh_id(&mut self) -> ValueId {
+        let id = ValueId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn is_inlinable_call(&self, inst: &Instruction) -> bool {
+        if let Instruction::FunctionCall { func, .. } = *inst {
+            self.inlined.contains_key(&func)
+        } else {
+            false
+        }
+    }
+
+    /// Inline all the calls in the given function
+    fn process(&mut self, func: ValueId) {
+        if self.inlined.contains_key(&func) || self.in_progress.contains(&func) {
+            return;
+        }
+
+        let range = match self.funcs.get(&func) {
+            Some(range) => range.clone(),
+            None => return
+        };
+        let insts = &self.module.instructions()[range];
+
+        self.in_progress.insert(func);
+        for inst in insts {
+            if let Instruction::FunctionCall { func: callee, .. } = *inst {
+                self.process(callee);
+            }
+        }
+        self.in_progress.remove(&func);
+
+        let mut out = Vec::with_capacity(insts.len());
+        let mut hoisted = Vec::new();
+        let mut var_pos = None;
+        let mut renames = HashMap::new();
+
+        for block in split_blocks(insts) {
+            let calls : Vec<_> = block.iter().map(|inst| self.is_inlinable_call(inst)).collect();
+
+            if !calls.iter().any(|&c| c) {
+                let start = out.len();
+                out.extend_from_slice(block);
+                if var_pos.is_none() {
+                    if let Some(&Instruction::Label { .. }) = block.first() {
+                        let vars = leading_count(&block[1..]);
+                        var_pos = Some(start + 1 + vars);
+                    }
+                }
+                continue;
+            }
+
+            let label = match block[0] {
+                Instruction::Label { result_id } => result_id.to_value_id(),
+                _ => {
+                    // Not a well-formed function, so leave it as it is
+                    self.inlined.insert(func, insts.to_vec());
+                    return;
+                }
+            };
+
+            let lead = 1 + leading_count(&block[1..]);
+            out.extend_from_slice(&block[..lead]);
+            if var_pos.is_none() {
+                var_pos = Some(out.len());
+            }
+
+            let mut current = label;
+
+            // The merge instruction of a loop has to stay in the header,
+            // so the rest of the header is moved into a new block
+            let loop_merge = block.iter().position(|inst| {
+                if let Instruction::LoopMerge { .. } = *inst { true } else { false }
+            });
+            if let Some(idx) = loop_merge {
+                let body = self.fresh_id();
+                out.push(block[idx].clone());
+                out.push(Instruction::Branch { target: body });
+                out.push(Instruction::Label { result_id: ResultId(body.0) });
+                current = body;
+            }
+
+            for (idx, inst) in block.iter().enumerate().skip(lead) {
+                if Some(idx) == loop_merge { continue; }
+
+                if calls[idx] {
+                    current = self.inline_call(inst, &mut out, &mut hoisted);
+                } else {
+                    out.push(inst.clone());
+                }
+            }
+
+            if current != label {
+                renames.insert(label, current);
+            }
+        }
+
+        // Blocks that had calls inlined now end in a different block, so
+        // update any phis that refer to them
+        if renames.len() > 0 {
+            for inst in out.iter_mut() {
+                if let Instruction::Phi { ref mut values, .. } = *inst {
+                    for v in values.iter_mut() {
+                        if let Some(&new) = renames.get(&v.1) {
+                            v.1 = new;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(pos) = var_pos {
+            let tail = out.split_off(pos);
+            out.extend(hoisted);
+            out.extend(tail);
+        }
+
+        self.inlined.insert(func, out);
+    }
+
+    fn callee(&self, func: ValueId) -> Callee {
+        let body = &self.inlined[&func];
+
+        let params = body.iter().filter_map(|inst| {
+            if let Instruction::FunctionParameter { result_id, .. } = *inst {
+                Some(result_id.to_value_id())
+            } else {
+                None
+            }
+        }).collect();
+
+        let body : Vec<_> = body.iter().filter(|inst| {
+            match **inst {
+                Instruction::Function { .. } |
+                Instruction::FunctionParameter { .. } |
+                Instruction::FunctionEnd => false,
+                _ => true
+            }
+        }).cloned().collect();
+
+        let returns = body.iter().filter(|inst| is_return(inst)).count();
+        let single_exit = returns == 1 && body.last().map(is_return).unwrap_or(false);
+        let has_loops = body.iter().any(|inst| {
+            if let Instruction::LoopMerge { .. } = *inst { true } else { false }
+        });
+
+        Callee {
+            params: params,
+            body: body,
+            single_exit: single_exit,
+            has_loops: has_loops
+        }
+    }
+
+    /// Inline the given call, returning the label of the block the code
+    /// after the call ends up in
+    fn inline_call(&mut self, call: &Instruction, out: &mut Vec<Instruction>,
+                   hoisted: &mut Vec<Instruction>) -> ValueId {
+        let (result_type, result_id, func, args) = match *call {
+            Instruction::FunctionCall { result_type, result_id, func, ref args } => {
+                (result_type, result_id, func, args)
+            }
+            _ => unreachable!()
+        };
+
+        let callee = self.callee(func);
+        // The caller's block was started before any calls were inlined
+        let current = out.iter().rev().filter_map(|inst| {
+            if let Instruction::Label { result_id } = *inst {
+                Some(result_id.to_value_id())
+            } else {
+                None
+            }
+        }).next().unwrap_or(ValueId(0));
+
+        let entry = match callee.body.first() {
+            Some(&Instruction::Label { result_id }) => result_id.to_value_id(),
+            _ => {
+                // A declaration without a body
+                out.push(call.clone());
+                return current;
+            }
+        };
+
+        let use_flag = !callee.single_exit && callee.has_loops;
+        if use_flag && !can_lower_returns(&callee.body) {
+            out.push(call.clone());
+            return current;
+        }
+
+        // Map the parameters to the arguments, and everything defined in
+        // the callee to new ids
+        let mut ids : HashMap<Id, Id> = HashMap::new();
+        for (&param, &arg) in callee.params.iter().zip(args.iter()) {
+            ids.insert(param.into(), arg.into());
+        }
+        for inst in &callee.body {
+            if let Some(def) = inst.defines() {
+                let new = self.fresh_id();
+                ids.insert(def, new.into());
+
+                if let Some(decorations) = self.decorations.get(&def) {
+                    for d in decorations {
+                        self.new_decorations.push(Instruction::Decorate {
+                            target: new.into(),
+                            decoration: d.clone()
+                        });
+                    }
+                }
+            }
+        }
+
+        let merge = self.fresh_id();
+        let entry = ids[&Id::from(entry)].to_value_id();
+
+        let mut body : Vec<_> = callee.body.iter().map(|inst| {
+            let mut inst = inst.clone();
+            inst.map_ids(|id| ids.get(&id).cloned().unwrap_or(id));
+            inst
+        }).collect();
+
+        let mut lowered = None;
+        if use_flag {
+            let mut l = self.lower_returns(&body, result_type, merge);
+            body = std::mem::replace(&mut l.body, Vec::new());
+            lowered = Some(l);
+        }
+
+        let mut cont = None;
+        if callee.single_exit {
+            out.push(Instruction::Branch { target: entry });
+        } else {
+            if let Some(ref l) = lowered {
+                let bool_type = self.bool_type();
+                let no = self.global(Instruction::ConstantFalse {
+                    result_type: bool_type,
+                    result_id: ResultId(0)
+                });
+                hoisted.push(Instruction::Variable {
+                    result_type: l.flag_ptr_type,
+                    result_id: Id::from(l.flag).to_result_id(),
+                    storage_class: desc::StorageClass::Function,
+                    init: ValueId(0)
+                });
+                if let Some((var, ty)) = l.ret_var {
+                    hoisted.push(Instruction::Variable {
+                        result_type: ty,
+                        result_id: Id::from(var).to_result_id(),
+                        storage_class: desc::StorageClass::Function,
+                        init: ValueId(0)
+                    });
+                }
+                // The flag is set by the previous call when this one is
+                // in a loop
+                out.push(Instruction::Store {
+                    ptr: l.flag,
+                    obj: no.to_value_id(),
+                    memory_access: desc::MemoryAccess::empty()
+                });
+            }
+
+            // Wrap the body in a loop, so returns can be breaks
+            let header = self.fresh_id();
+            let cont_id = self.fresh_id();
+            out.push(Instruction::Branch { target: header });
+            out.push(Instruction::Label { result_id: ResultId(header.0) });
+            out.push(Instruction::LoopMerge {
+                merge: merge,
+                cont: cont_id,
+                loop_control: desc::LoopControl::empty()
+            });
+            out.push(Instruction::Branch { target: entry });
+            cont = Some((header, cont_id));
+        }
+
+        let mut returns = Vec::new();
+        let mut block = entry;
+
+        for inst in body {
+            match inst {
+                Instruction::Label { result_id } => {
+                    block = result_id.to_value_id();
+                    out.push(inst);
+                }
+                Instruction::Variable { result_type, result_id, storage_class, init } => {
+                    // Variables are moved to the caller's entry block, so
+                    // initializers have to be stored on every call
+                    if init.is_valid() {
+                        out.push(Instruction::Store {
+                            ptr: result_id.to_value_id(),
+                            obj: init,
+                            memory_access: desc::MemoryAccess::empty()
+                        });
+                    }
+                    hoisted.push(Instruction::Variable {
+                        result_type: result_type,
+                        result_id: result_id,
+                        storage_class: storage_class,
+                        init: ValueId(0)
+                    });
+                }
+                Instruction::Return => {
+                    out.push(Instruction::Branch { target: merge });
+                }
+                Instruction::ReturnValue { value_id } => {
+                    returns.push((value_id, block));
+                    out.push(Instruction::Branch { target: merge });
+                }
+                inst => out.push(inst)
+            }
+        }
+
+        if let Some((header, cont)) = cont {
+            out.push(Instruction::Label { result_id: ResultId(cont.0) });
+            out.push(Instruction::Branch { target: header });
+        }
+
+        out.push(Instruction::Label { result_id: ResultId(merge.0) });
+        if let Some(Lowered { ret_var: Some((var, _)), .. }) = lowered {
+            out.push(Instruction::Load {
+                result_type: result_type,
+                result_id: result_id,
+                value_id: var,
+                memory_access: desc::MemoryAccess::empty()
+            });
+        } else if returns.len() > 0 {
+            out.push(Instruction::Phi {
+                result_type: result_type,
+                result_id: result_id,
+                values: returns.into_boxed_slice()
+            });
+        }
+
+        merge
+    }
+
+    /// Gets the id of a type or constant, adding it to the module if it
+    /// isn't there already. The id it defines is replaced.
+    fn global(&mut self, mut inst: Instruction) -> Id {
+        let key = global_key(&inst);
+        if let Some(&id) = self.globals.get(&key) {
+            return id;
+        }
+
+        let id = self.fresh_id();
+        inst.for_each_id_mut(|i, role| {
+            if role == IdRole::Result { *i = id.0; }
+        });
+        self.new_globals.push(inst);
+        self.globals.insert(key, id.into());
+        id.into()
+    }
+
+    fn bool_type(&mut self) -> TypeId {
+        self.global(Instruction::TypeBool { result_type: TypeId(0) }).to_type_id()
+    }
+
+    fn pointer_type(&mut self, pointee: TypeId) -> TypeId {
+        self.global(Instruction::TypePointer {
+            result_type: TypeId(0),
+            storage_class: desc::StorageClass::Function,
+            pointee: pointee
+        }).to_type_id()
+    }
+
+    /// Rewrites the returns in the body of a callee with loops so they
+    /// set a flag and break from the innermost loop, with the merge block
+    /// of each loop checking the flag and breaking again. Returns at the
+    /// top level break from the wrapper loop, which merges at `merge`.
+    /// The body must have passed `can_lower_returns`.
+    fn lower_returns(&mut self, body: &[Instruction], result_type: TypeId,
+                     merge: ValueId) -> Lowered {
+        let blocks = body_blocks(body);
+        let loops = find_loops(&blocks);
+
+        // Where a block breaks to when the callee returns
+        let exit = |label: ValueId| {
+            loops.iter().filter(|l| l.blocks.contains(&label))
+                .min_by_key(|l| l.blocks.len())
+                .map(|l| l.merge)
+                .unwrap_or(merge)
+        };
+        let returns_from = |l: &Loop| {
+            blocks.iter().any(|&(label, block)| {
+                l.blocks.contains(&label) && block.last().map(is_return).unwrap_or(false)
+            })
+        };
+        let checked : HashSet<ValueId> = loops.iter()
+            .filter(|l| returns_from(l))
+            .map(|l| l.merge)
+            .collect();
+
+        let bool_type = self.bool_type();
+        let yes = self.global(Instruction::ConstantTrue {
+            result_type: bool_type,
+            result_id: ResultId(0)
+        }).to_value_id();
+        let flag_ptr_type = self.pointer_type(bool_type);
+        let flag = self.fresh_id();
+
+        let has_value = body.iter().any(|inst| {
+            if let Instruction::ReturnValue { .. } = *inst { true } else { false }
+        });
+        let ret_var = if has_value {
+            let ty = self.pointer_type(result_type);
+            Some((self.fresh_id(), ty))
+        } else {
+            None
+        };
+
+        let mut out = Vec::with_capacity(body.len());
+        // The blocks that now branch to a merge block, keyed by the merge
+        // block, and the blocks that were split
+        let mut new_preds : HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+        let mut renames = HashMap::new();
+
+        for &(label, block) in &blocks {
+            let mut current = label;
+            let mut rest = block;
+
+            if checked.contains(&label) {
+                let phis = block[1..].iter().take_while(|inst| {
+                    match **inst {
+                        Instruction::Phi { .. } |
+                        Instruction::Line { .. } |
+                        Instruction::NoLine => true,
+                        _ => false
+                    }
+                }).count();
+                out.extend_from_slice(&block[..1 + phis]);
+                rest = &block[1 + phis..];
+
+                let returned = self.fresh_id();
+                let brk = self.fresh_id();
+                let cont = self.fresh_id();
+                out.push(Instruction::Load {
+                    result_type: bool_type,
+                    result_id: ResultId(returned.0),
+                    value_id: flag,
+                    memory_access: desc::MemoryAccess::empty()
+                });
+                out.push(Instruction::SelectionMerge {
+                    merge: cont,
+                    selection_control: desc::SelectionControl::empty()
+                });
+                out.push(Instruction::BranchConditional {
+                    cond: returned,
+                    then: brk,
+                    els: cont,
+                    true_weight: None,
+                    false_weight: None
+                });
+                out.push(Instruction::Label { result_id: ResultId(brk.0) });
+                out.push(Instruction::Branch { target: exit(label) });
+                out.push(Instruction::Label { result_id: ResultId(cont.0) });
+                new_preds.entry(exit(label)).or_insert_with(Vec::new).push(brk);
+                renames.insert(label, cont);
+                current = cont;
+            } else {
+                out.push(block[0].clone());
+                rest = &rest[1..];
+            }
+
+            for inst in rest {
+                match *inst {
+                    Instruction::Return | Instruction::ReturnValue { .. } => {
+                        if let Instruction::ReturnValue { value_id } = *inst {
+                            out.push(Instruction::Store {
+                                ptr: ret_var.unwrap().0,
+                                obj: value_id,
+                                memory_access: desc::MemoryAccess::empty()
+                            });
+                        }
+                        out.push(Instruction::Store {
+                            ptr: flag,
+                            obj: yes,
+                            memory_access: desc::MemoryAccess::empty()
+                        });
+                        out.push(Instruction::Branch { target: exit(label) });
+                        new_preds.entry(exit(label)).or_insert_with(Vec::new).push(current);
+                    }
+                    _ => out.push(inst.clone())
+                }
+            }
+        }
+
+        // The split blocks now end in a different block, and the merge
+        // blocks have new predecessors, which the phis need values for
+        let mut undefs : Vec<(TypeId, ValueId)> = Vec::new();
+        let mut phi_block = ValueId(0);
+        for inst in out.iter_mut() {
+            match *inst {
+                Instruction::Label { result_id } => phi_block = result_id.to_value_id(),
+                Instruction::Phi { result_type, ref mut values, .. } => {
+                    let mut new_values : Vec<_> = values.iter().map(|&(v, pred)| {
+                        (v, renames.get(&pred).cloned().unwrap_or(pred))
+                    }).collect();
+                    if let Some(preds) = new_preds.get(&phi_block) {
+                        let undef = match undefs.iter().find(|u| u.0 == result_type) {
+                            Some(&(_, id)) => id,
+                            None => {
+                                let id = ValueId(self.next_id);
+                                self.next_id += 1;
+                                undefs.push((result_type, id));
+                                id
+                            }
+                        };
+                        for &pred in preds {
+                            new_values.push((undef, pred));
+                        }
+                    }
+                    *values = new_values.into_boxed_slice();
+                }
+                _ => ()
+            }
+        }
+
+        // The entry block dominates everything, so the undefs go there
+        let undefs = undefs.into_iter().map(|(ty, id)| {
+            Instruction::Undef { result_type: ty, result_id: ResultId(id.0) }
+        });
+        let tail = out.split_off(1);
+        out.extend(undefs);
+        out.extend(tail);
+
+        Lowered {
+            body: out,
+            flag: flag,
+            flag_ptr_type: flag_ptr_type,
+            ret_var: ret_var
+        }
+    }
+}
+
+/// Gets the key for looking up a type or constant in `Inliner::globals`
+fn global_key(inst: &Instruction) -> Vec<u32> {
+    let mut inst = inst.clone();
+    inst.for_each_id_mut(|id, role| {
+        if role == IdRole::Result { *id = 0; }
+    });
+    let mut words = Vec::new();
+    encode_instruction(&inst, &mut words);
+    words
+}
+
+/// Splits the body of a callee into its blocks, with their labels
+fn body_blocks(body: &[Instruction]) -> Vec<(ValueId, &[Instruction])> {
+    split_blocks(body).into_iter().filter_map(|block| {
+        match block.first() {
+            Some(&Instruction::Label { result_id }) => Some((result_id.to_value_id(), block)),
+            _ => None
+        }
+    }).collect()
+}
+
+fn successors(block: &[Instruction]) -> Vec<ValueId> {
+    match block.last() {
+        Some(&Instruction::Branch { target }) => vec![target],
+        Some(&Instruction::BranchConditional { then, els, .. }) => vec![then, els],
+        Some(&Instruction::Switch { default, ref targets, .. }) => {
+            let mut succs = vec![default];
+            succs.extend(targets.iter().map(|t| t.1));
+            succs
+        }
+        _ => Vec::new()
+    }
+}
+
+/// Finds the loops in a callee. The blocks in a loop are the ones that
+/// can be reached from its header without going through its merge block.
+fn find_loops(blocks: &[(ValueId, &[Instruction])]) -> Vec<Loop> {
+    let by_label : HashMap<ValueId, &[Instruction]> = blocks.iter().cloned().collect();
+
+    blocks.iter().filter_map(|&(header, block)| {
+        let merge = block.iter().filter_map(|inst| {
+            if let Instruction::LoopMerge { merge, .. } = *inst { Some(merge) } else { None }
+        }).next();
+        merge.map(|merge| {
+            let mut seen = HashSet::new();
+            let mut worklist = vec![header];
+            while let Some(label) = worklist.pop() {
+                if label == merge || !seen.insert(label) { continue; }
+                if let Some(block) = by_label.get(&label) {
+                    worklist.extend(successors(block));
+                }
+            }
+            Loop { merge: merge, blocks: seen }
+        })
+    }).collect()
+}
+
+/// Checks that the returns in a callee can be rewritten by
+/// `Inliner::lower_returns`. The merge block of each loop that is returned
+/// from is split to check the return flag, so it can't be the header of
+/// another loop, and it has to exist.
+fn can_lower_returns(body: &[Instruction]) -> bool {
+    let blocks = body_blocks(body);
+    let by_label : HashMap<ValueId, &[Instruction]> = blocks.iter().cloned().collect();
+
+    find_loops(&blocks).iter().all(|l| {
+        let returns = blocks.iter().any(|&(label, block)| {
+            l.blocks.contains(&label) && block.last().map(is_return).unwrap_or(false)
+        });
+        if !returns {
+            return true;
+        }
+        match by_label.get(&l.merge) {
+            Some(block) => !block.iter().any(|inst| {
+                if let Instruction::LoopMerge { .. } = *inst { true } else { false }
+            }),
+            None => false
+        }
+    })
+}
+
+fn is_return(inst: &Instruction) -> bool {
+    match *inst {
+        Instruction::Return |
+        Instruction::ReturnValue { .. } => true,
+        _ => false
+    }
+}
+
+/// Counts the phis, variables and line information at the start of a block
+fn leading_count(insts: &[Instruction]) -> usize {
+    insts.iter().take_while(|inst| {
+        match **inst {
+            Instruction::Phi { .. } |
+            Instruction::Variable { .. } |
+            Instruction::Line { .. } |
+            Instruction::NoLine => true,
+            _ => false
+        }
+    }).count()
+}
+
+/// Splits the instructions of a function at each label. The first slice
+/// holds the `Function` and its parameters, and the last holds the
+/// `FunctionEnd`
+fn split_blocks(insts: &[Instruction]) -> Vec<&[Instruction]> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+
+    for (idx, inst) in insts.iter().enumerate() {
+        match *inst {
+            Instruction::Label { .. } |
+            Instruction::FunctionEnd => {
+                blocks.push(&insts[start..idx]);
+                start = idx;
+            }
+            _ => ()
+        }
+    }
+    blocks.push(&insts[start..]);
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc;
+    use instruction::Instruction;
+    use validate::validate;
+    use RawModule;
+
+    use super::inline_functions;
+
+    const PRELUDE : &'static str = "
+        OpCapability Shader
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Fragment %main \"main\"
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %int = OpTypeInt 32 1
+        %bool = OpTypeBool
+        %fn_int = OpTypeFunction %int %int
+        %ptr_int = OpTypePointer Function %int
+        %zero = OpConstant %int 0
+        %one = OpConstant %int 1
+        %ten = OpConstant %int 10
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        %r = OpFunctionCall %int %callee %ten
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    /// Inlines the callee into `main`, checking that the result is valid
+    /// and that the call and the callee are gone
+    fn inline(callee: &str) -> RawModule {
+        let module = assemble(&format!("{}{}", PRELUDE, callee)).unwrap();
+        assert!(validate(&module).is_empty());
+
+        let inlined = inline_functions(&module);
+        assert!(validate(&inlined).is_empty());
+
+        let functions = inlined.instructions().iter().filter(|inst| {
+            if let Instruction::Function { .. } = **inst { true } else { false }
+        }).count();
+        assert_eq!(functions, 1);
+        assert_eq!(count(&inlined, desc::Op::FunctionCall), 0);
+        inlined
+    }
+
+    fn count(module: &RawModule, op: desc::Op) -> usize {
+        module.instructions().iter().filter(|inst| inst.opcode() == op as u16).count()
+    }
+
+    #[test]
+    fn single_exit() {
+        let module = inline("
+            %callee = OpFunction %int None %fn_int
+            %n = OpFunctionParameter %int
+            %c_entry = OpLabel
+            %sum = OpIAdd %int %n %one
+            OpReturnValue %sum
+            OpFunctionEnd
+        ");
+
+        // The argument is used in place of the parameter
+        let add = module.instructions().iter().filter_map(|inst| {
+            if let Instruction::IAdd { lhs, .. } = *inst { Some(lhs) } else { None }
+        }).next().unwrap();
+        assert_eq!(module.def(add).unwrap().opcode(), desc::Op::Constant as u16);
+        assert_eq!(count(&module, desc::Op::LoopMerge), 0);
+        let phi = module.instructions().iter().filter_map(|inst| {
+            if let Instruction::Phi { ref values, .. } = *inst { Some(values.len()) } else { None }
+        }).next();
+        assert_eq!(phi, Some(1));
+    }
+
+    #[test]
+    fn multiple_returns() {
+        let module = inline("
+            %callee = OpFunction %int None %fn_int
+            %n = OpFunctionParameter %int
+            %c_entry = OpLabel
+            %neg = OpSLessThan %bool %n %zero
+            OpSelectionMerge %c_merge None
+            OpBranchConditional %neg %c_then %c_merge
+            %c_then = OpLabel
+            OpReturnValue %zero
+            %c_merge = OpLabel
+            OpReturnValue %n
+            OpFunctionEnd
+        ");
+
+        // The body is wrapped in a loop, and the return values are
+        // merged with a phi
+        assert_eq!(count(&module, desc::Op::LoopMerge), 1);
+        let phi = module.instructions().iter().filter_map(|inst| {
+            if let Instruction::Phi { ref values, .. } = *inst { Some(values.len()) } else { None }
+        }).next();
+        assert_eq!(phi, Some(2));
+    }
+
+    #[test]
+    fn multiple_returns_with_loop() {
+        let module = inline("
+            %callee = OpFunction %int None %fn_int
+            %n = OpFunctionParameter %int
+            %c_entry = OpLabel
+            OpBranch %header
+            %header = OpLabel
+            %i = OpPhi %int %zero %c_entry %next %cont
+            OpLoopMerge %l_merge %cont None
+            OpBranch %body
+            %body = OpLabel
+            %found = OpIEqual %bool %i %n
+            OpSelectionMerge %sel None
+            OpBranchConditional %found %ret %sel
+            %ret = OpLabel
+            OpReturnValue %i
+            %sel = OpLabel
+            OpBranch %cont
+            %cont = OpLabel
+            %next = OpIAdd %int %i %one
+            %more = OpSLessThan %bool %next %ten
+            OpBranchConditional %more %header %l_merge
+            %l_merge = OpLabel
+            %last = OpPhi %int %next %cont
+            OpReturnValue %last
+            OpFunctionEnd
+        ");
+
+        // The callee's loop and the wrapper loop
+        assert_eq!(count(&module, desc::Op::LoopMerge), 2);
+
+        // The return breaks from the callee's loop, so the phi in its
+        // merge block gets an undefined value for it
+        let phi = module.instructions().iter().filter_map(|inst| {
+            match *inst {
+                Instruction::Phi { ref values, .. } if values.len() == 2 => Some(values.clone()),
+                _ => None
+            }
+        }).last().unwrap();
+        assert_eq!(module.def(phi[1].0).unwrap().opcode(), desc::Op::Undef as u16);
+
+        // The result is loaded from the return value variable
+        let result = module.instructions().iter().filter_map(|inst| {
+            if let Instruction::Load { value_id, .. } = *inst { Some(value_id) } else { None }
+        }).last().unwrap();
+        match *module.def(result).unwrap() {
+            Instruction::Variable { storage_class: desc::StorageClass::Function, .. } => (),
+            ref inst => panic!("result loaded from {:?}", inst)
+        }
+    }
+
+    #[test]
+    fn hoisted_variables() {
+        let module = inline("
+            %callee = OpFunction %int None %fn_int
+            %n = OpFunctionParameter %int
+            %c_entry = OpLabel
+            %var = OpVariable %ptr_int Function %one
+            %old = OpLoad %int %var
+            %sum = OpIAdd %int %old %n
+            OpStore %var %sum
+            OpReturnValue %sum
+            OpFunctionEnd
+        ");
+
+        // The variable is at the start of the caller's entry block, and
+        // its initializer becomes a store
+        let insts = module.instructions();
+        let label = insts.iter().position(|inst| inst.opcode() == desc::Op::Label as u16).unwrap();
+        let var = match insts[label + 1] {
+            Instruction::Variable { result_id, init, .. } => {
+                assert!(!init.is_valid());
+                result_id.to_value_id()
+            }
+            ref inst => panic!("expected a variable, found {:?}", inst)
+        };
+        let init = insts.iter().any(|inst| {
+            if let Instruction::Store { ptr, obj, .. } = *inst {
+                ptr == var && module.def(obj).unwrap().opcode() == desc::Op::Constant as u16
+            } else {
+                false
+            }
+        });
+        assert!(init);
+    }
+
+    #[test]
+    fn malformed_functions() {
+        // Like a module read leniently, with an instruction that failed
+        // to parse and two functions with the same id
+        let module = assemble(&format!("{}{}", PRELUDE, "
+            %callee = OpFunction %int None %fn_int
+            %n = OpFunctionParameter %int
+            %c_entry = OpLabel
+            OpReturnValue %n
+            OpFunctionEnd
+            %other = OpFunction %void None %fn_void
+            %o_entry = OpLabel
+            OpReturn
+            OpFunctionEnd
+        ")).unwrap();
+
+        let main = module.instructions().iter().find(|inst| {
+            inst.opcode() == desc::Op::Function as u16
+        }).unwrap().defines().unwrap();
+        let mut insts = module.instructions().to_vec();
+        let last = insts.iter().rposition(|inst| inst.opcode() == desc::Op::Function as u16);
+        if let Instruction::Function { ref mut result_id, .. } = insts[last.unwrap()] {
+            *result_id = main.to_result_id();
+        }
+        insts.insert(3, Instruction::Unknown(32, vec![27, 251658247, 9].into_boxed_slice()));
+        let module = RawModule::from_instructions(module.header().clone(), insts);
+
+        let inlined = inline_functions(&module);
+        assert_eq!(count(&inlined, desc::Op::Function), 1);
+    }
+}
+
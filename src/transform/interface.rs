@@ -0,0 +1,547 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{HashMap, HashSet};
+
+use desc::{self, Id, TypeId};
+use instruction::{Decoration, Instruction};
+use RawModule;
+
+/// How new locations are assigned to interface variables
+#[derive(Clone, Debug)]
+pub enum LocationAssignment {
+    /// Assign the given location and component to each variable by name.
+    /// Variables that aren't in the map keep their current location.
+    Names(HashMap<String, (u32, u32)>),
+    /// Pack the variables into the fewest locations
+    Pack
+}
+
+/**
+ * Reassign the `Location` and `Component` decorations of the interface
+ * variables with the given storage class, which should be `Input` or
+ * `Output`.
+ *
+ * When packing, scalars and vectors with the same component type and
+ * interpolation decorations share locations where they fit. Arrays,
+ * matrices and structures always start a new location.
+ */
+pub fn remap_locations(module: &RawModule, storage_class: desc::StorageClass,
+                       assignment: &LocationAssignment) -> RawModule {
+    let vars = interface_vars(module, storage_class);
+    let locations = assign(&vars, assignment);
+
+    rewrite(module, storage_class, &locations, &HashSet::new())
+}
+
+/**
+ * Reassign the locations of the outputs of `producer` and the inputs of
+ * `consumer` so they stay consistent with each other. Outputs and inputs
+ * are matched by their current location and component, and matched
+ * pairs are given the same new location.
+ *
+ * Outputs that the consumer never reads are removed, as long as they are
+ * only ever stored to.
+ */
+pub fn link_interfaces(producer: &RawModule, consumer: &RawModule,
+                       assignment: &LocationAssignment) -> (RawModule, RawModule) {
+    let outputs = interface_vars(producer, desc::StorageClass::Output);
+    let inputs = interface_vars(consumer, desc::StorageClass::Input);
+
+    let read : HashSet<_> = inputs.iter().map(|var| var.location).collect();
+    let written : HashSet<_> = outputs.iter().map(|var| var.location).collect();
+
+    let mut removed = HashSet::new();
+    let mut vars = Vec::new();
+    for var in outputs {
+        if !read.contains(&var.location) {
+            if let Some(ids) = removable(producer, var.id) {
+                removed.extend(ids);
+                continue;
+            }
+        }
+        vars.push(var);
+    }
+    vars.extend(inputs.into_iter().filter(|var| !written.contains(&var.location)));
+
+    let locations = assign(&vars, assignment);
+
+    (rewrite(producer, desc::StorageClass::Output, &locations, &removed),
+     rewrite(consumer, desc::StorageClass::Input, &locations, &HashSet::new()))
+}
+
+struct InterfaceVar {
+    id: Id,
+    name: Option<String>,
+    location: (u32, u32),
+    locations: u32,
+    /// The number of components used, and what the variable can share a
+    /// location with, if it fits in less than a whole location
+    packing: Option<(u32, PackingKey)>
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct PackingKey {
+    scalar: TypeId,
+    decorations: Vec<u32>
+}
+
+fn interface_vars(module: &RawModule, storage_class: desc::StorageClass) -> Vec<InterfaceVar> {
+    let mut names = HashMap::new();
+    let mut locations = HashMap::new();
+    let mut components = HashMap::new();
+    let mut decorations : HashMap<Id, Vec<Decoration>> = HashMap::new();
+
+    for inst in module.instructions() {
+        match *inst {
+            Instruction::Name { id, ref name } => {
+                names.insert(id, name.clone());
+            }
+            Instruction::Decorate { target, decoration: Decoration::Location(loc) } => {
+                locations.insert(target, loc);
+            }
+            Instruction::Decorate { target, decoration: Decoration::Component(comp) } => {
+                components.insert(target, comp);
+            }
+            Instruction::Decorate { target, ref decoration } => {
+                decorations.entry(target).or_insert_with(Vec::new).push(decoration.clone());
+            }
+            _ => ()
+        }
+    }
+
+    // Some stages have an array of inputs or outputs, one per vertex
+    let arrayed = module.instructions().iter().any(|inst| {
+        if let Instruction::EntryPoint { execution_model, .. } = *inst {
+            match (execution_model, storage_class) {
+                (desc::ExecutionModel::Geometry, desc::StorageClass::Input) |
+                (desc::ExecutionModel::TesselationControl, _) |
+                (desc::ExecutionModel::TesselationEvaluation, desc::StorageClass::Input) => true,
+                _ => false
+            }
+        } else {
+            false
+        }
+    });
+
+    module.instructions().iter().filter_map(|inst| {
+        let (ptr, id) = match *inst {
+            Instruction::Variable { result_type, result_id, storage_class: sc, .. }
+                if sc == storage_class => (result_type, Id::from(result_id)),
+            _ => return None
+        };
+        let location = match locations.get(&id) {
+            Some(&loc) => loc,
+            None => return None
+        };
+
+        let decorations = decorations.get(&id).map(|d| &d[..]).unwrap_or(&[]);
+        let patch = decorations.iter().any(|d| {
+            if let Decoration::Patch = *d { true } else { false }
+        });
+
+        let mut ty = match module.def(ptr) {
+            Some(&Instruction::TypePointer { pointee, .. }) => pointee,
+            _ => return None
+        };
+        if arrayed && !patch {
+            if let Some(&Instruction::TypeArray { element, .. }) = module.def(ty) {
+                ty = element;
+            }
+        }
+
+        let (locations, packing) = footprint(module, ty);
+        let packing = packing.map(|(comps, scalar)| {
+            let mut interp : Vec<_> = decorations.iter().filter_map(|d| {
+                match *d {
+                    Decoration::NoPerspective | Decoration::Flat |
+                    Decoration::Patch | Decoration::Centroid |
+                    Decoration::Sample => Some(d.to_desc() as u32),
+                    _ => None
+                }
+            }).collect();
+            interp.sort();
+
+            (comps, PackingKey { scalar: scalar, decorations: interp })
+        });
+
+        Some(InterfaceVar {
+            id: id,
+            name: names.get(&id).cloned(),
+            location: (location, components.get(&id).cloned().unwrap_or(0)),
+            locations: locations,
+            packing: packing
+        })
+    }).collect()
+}
+
+/// Gets the number of locations used by a type, and the number of
+/// components and the scalar type if it fits in a single location
+fn footprint(module: &RawModule, ty: TypeId) -> (u32, Option<(u32, TypeId)>) {
+    match module.def(ty) {
+        Some(&Instruction::TypeBool { .. }) => (1, Some((1, ty))),
+        Some(&Instruction::TypeInt { width, .. }) |
+        Some(&Instruction::TypeFloat { width, .. }) => {
+            (1, Some((if width == 64 { 2 } else { 1 }, ty)))
+        }
+        Some(&Instruction::TypeVector { type_id, len, .. }) => {
+            match footprint(module, type_id) {
+                (_, Some((comps, scalar))) if comps.saturating_mul(len) <= 4 => {
+                    (1, Some((comps * len, scalar)))
+                }
+                _ => (2, None)
+            }
+        }
+        Some(&Instruction::TypeMatrix { type_id, cols, .. }) => {
+            (footprint(module, type_id).0.saturating_mul(cols), None)
+        }
+        Some(&Instruction::TypeArray { element, len, .. }) => {
+            let len = match module.def(len) {
+                Some(&Instruction::Constant { ref val, .. }) => val.get(0).cloned().unwrap_or(1),
+                _ => 1
+            };
+            (footprint(module, element).0.saturating_mul(len), None)
+        }
+        Some(&Instruction::TypeStruct { ref fields, .. }) => {
+            let size = fields.iter().fold(0u32, |size, &f| {
+                size.saturating_add(footprint(module, f).0)
+            });
+            (size, None)
+        }
+        _ => (1, None)
+    }
+}
+
+/// Works out the new location of each variable, keyed by its current
+/// location
+fn assign(vars: &[InterfaceVar], assignment: &LocationAssignment) -> HashMap<(u32, u32), (u32, u32)> {
+    let mut locations = HashMap::new();
+
+    match *assignment {
+        LocationAssignment::Names(ref names) => {
+            for var in vars {
+                if let Some(&new) = var.name.as_ref().and_then(|name| names.get(name)) {
+                    locations.insert(var.location, new);
+                }
+            }
+        }
+        LocationAssignment::Pack => {
+            // Place the largest variables first, so the smaller ones can
+            // fill the gaps
+            let mut order : Vec<_> = vars.iter().collect();
+            order.sort_by_key(|var| {
+                !var.packing.as_ref().map(|&(comps, _)| comps).unwrap_or(4)
+            });
+
+            let mut next = 0;
+            let mut partial : Vec<(u32, u32, &PackingKey)> = Vec::new();
+            for var in order {
+                let (comps, key) = match var.packing {
+                    Some((comps, ref key)) if comps < 4 => (comps, key),
+                    _ => {
+                        locations.insert(var.location, (next, 0));
+                        next = next.saturating_add(var.locations);
+                        continue;
+                    }
+                };
+
+                if let Some(slot) = partial.iter_mut().find(|s| s.2 == key && s.1 + comps <= 4) {
+                    locations.insert(var.location, (slot.0, slot.1));
+                    slot.1 += comps;
+                    continue;
+                }
+
+                locations.insert(var.location, (next, 0));
+                partial.push((next, comps, key));
+                next += 1;
+            }
+        }
+    }
+
+    locations
+}
+
+/// Gets the instructions that have to be removed along with the given
+/// variable, if it is only ever stored to
+fn removable(module: &RawModule, var: Id) -> Option<Vec<usize>> {
+    let mut removed = Vec::new();
+    let mut worklist = vec![var];
+    let mut seen = HashSet::new();
+
+    while let Some(id) = worklist.pop() {
+        for &idx in module.use_indices(id).unwrap_or(&[]) {
+            if !seen.insert(idx) { continue; }
+
+            match module.instructions()[idx] {
+                Instruction::AccessChain { result_id, base, .. } |
+                Instruction::InBoundsAccessChain { result_id, base, .. } |
+                Instruction::PtrAccessChain { result_id, base, .. } |
+                Instruction::InBoundsPtrAccessChain { result_id, base, .. } |
+                Instruction::CopyObject { result_id, value_id: base, .. } => {
+                    if Id::from(base) != id { return None; }
+                    worklist.push(result_id.into());
+                }
+                Instruction::Store { ptr, .. } => {
+                    if Id::from(ptr) != id { return None; }
+                }
+                Instruction::Name { .. } |
+                Instruction::Decorate { .. } |
+                Instruction::EntryPoint { .. } => continue,
+                _ => return None
+            }
+            removed.push(idx);
+        }
+    }
+
+    // The variable may not be in the definition map of a malformed module
+    removed.push(match module.def_index(var) {
+        Some(idx) => idx,
+        None => return None
+    });
+    Some(removed)
+}
+
+fn rewrite(module: &RawModule, storage_class: desc::StorageClass,
+           locations: &HashMap<(u32, u32), (u32, u32)>,
+           removed: &HashSet<usize>) -> RawModule {
+    let removed_ids : HashSet<Id> = removed.iter().filter_map(|&idx| {
+        module.instructions()[idx].defines()
+    }).collect();
+
+    let vars = interface_vars(module, storage_class);
+    let new_locations : HashMap<Id, (u32, u32)> = vars.iter().filter_map(|var| {
+        locations.get(&var.location).map(|&new| (var.id, new))
+    }).collect();
+    let has_component : HashSet<Id> = module.instructions().iter().filter_map(|inst| {
+        if let Instruction::Decorate { target, decoration: Decoration::Component(_) } = *inst {
+            Some(target)
+        } else {
+            None
+        }
+    }).collect();
+
+    let mut instructions = Vec::with_capacity(module.instructions().len());
+    for (idx, inst) in module.instructions().iter().enumerate() {
+        if removed.contains(&idx) { continue; }
+
+        match *inst {
+            Instruction::Name { id: target, .. } |
+            Instruction::Decorate { target, .. } if removed_ids.contains(&target) => continue,
+            Instruction::EntryPoint { execution_model, func, ref name, ref interface } => {
+                let interface : Vec<_> = interface.iter().cloned()
+                    .filter(|id| !removed_ids.contains(id)).collect();
+                instructions.push(Instruction::EntryPoint {
+                    execution_model: execution_model,
+                    func: func,
+                    name: name.clone(),
+                    interface: interface.into_boxed_slice()
+                });
+                continue;
+            }
+            Instruction::Decorate { target, decoration: Decoration::Location(_) } => {
+                if let Some(&(loc, comp)) = new_locations.get(&target) {
+                    instructions.push(Instruction::Decorate {
+                        target: target,
+                        decoration: Decoration::Location(loc)
+                    });
+                    if comp != 0 && !has_component.contains(&target) {
+                        instructions.push(Instruction::Decorate {
+                            target: target,
+                            decoration: Decoration::Component(comp)
+                        });
+                    }
+                    continue;
+                }
+            }
+            Instruction::Decorate { target, decoration: Decoration::Component(_) } => {
+                if let Some(&(_, comp)) = new_locations.get(&target) {
+                    instructions.push(Instruction::Decorate {
+                        target: target,
+                        decoration: Decoration::Component(comp)
+                    });
+                    continue;
+                }
+            }
+            _ => ()
+        }
+
+        instructions.push(inst.clone());
+    }
+
+    RawModule::from_instructions(module.header().clone(), instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use asm::assemble;
+    use desc::{self, Id};
+    use instruction::{Decoration, Instruction};
+    use validate::validate;
+    use RawModule;
+
+    use super::{link_interfaces, remap_locations, LocationAssignment};
+
+    const PRODUCER : &'static str = "
+        OpCapability Shader
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Vertex %main \"main\" %a %b %c %d
+        OpName %a \"a\"
+        OpName %b \"b\"
+        OpName %c \"c\"
+        OpName %d \"d\"
+        OpDecorate %a Location 0
+        OpDecorate %b Location 1
+        OpDecorate %c Location 2
+        OpDecorate %d Location 3
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %v2float = OpTypeVector %float 2
+        %v3float = OpTypeVector %float 3
+        %v4float = OpTypeVector %float 4
+        %ptr_float = OpTypePointer Output %float
+        %ptr_v2float = OpTypePointer Output %v2float
+        %ptr_v3float = OpTypePointer Output %v3float
+        %ptr_v4float = OpTypePointer Output %v4float
+        %one = OpConstant %float 1
+        %v3_one = OpConstantComposite %v3float %one %one %one
+        %a = OpVariable %ptr_float Output
+        %b = OpVariable %ptr_v2float Output
+        %c = OpVariable %ptr_v3float Output
+        %d = OpVariable %ptr_v4float Output
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        OpStore %a %one
+        OpStore %c %v3_one
+        %a_value = OpLoad %float %a
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    const CONSUMER : &'static str = "
+        OpCapability Shader
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Fragment %main \"main\" %b_in %d_in
+        OpExecutionMode %main OriginUpperLeft
+        OpDecorate %b_in Location 1
+        OpDecorate %d_in Location 3
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %v2float = OpTypeVector %float 2
+        %v4float = OpTypeVector %float 4
+        %ptr_v2float = OpTypePointer Input %v2float
+        %ptr_v4float = OpTypePointer Input %v4float
+        %b_in = OpVariable %ptr_v2float Input
+        %d_in = OpVariable %ptr_v4float Input
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    /// Gets the location and component of each variable, in the order the
+    /// variables are defined
+    fn locations(module: &RawModule, storage_class: desc::StorageClass) -> Vec<(u32, u32)> {
+        let mut locations = HashMap::new();
+        for inst in module.instructions() {
+            if let Instruction::Decorate { target, ref decoration } = *inst {
+                let entry = locations.entry(target).or_insert((0, 0));
+                match *decoration {
+                    Decoration::Location(loc) => entry.0 = loc,
+                    Decoration::Component(comp) => entry.1 = comp,
+                    _ => ()
+                }
+            }
+        }
+
+        module.instructions().iter().filter_map(|inst| match *inst {
+            Instruction::Variable { result_id, storage_class: sc, .. } if sc == storage_class => {
+                locations.get(&Id::from(result_id)).cloned()
+            }
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn by_name() {
+        let module = assemble(PRODUCER).unwrap();
+        let mut names = HashMap::new();
+        names.insert("b".to_owned(), (5, 0));
+        names.insert("c".to_owned(), (6, 1));
+
+        let remapped = remap_locations(&module, desc::StorageClass::Output,
+                                       &LocationAssignment::Names(names));
+        assert!(validate(&remapped).is_empty());
+        assert_eq!(locations(&remapped, desc::StorageClass::Output),
+                   vec![(0, 0), (5, 0), (6, 1), (3, 0)]);
+    }
+
+    #[test]
+    fn pack() {
+        let module = assemble(PRODUCER).unwrap();
+        let remapped = remap_locations(&module, desc::StorageClass::Output,
+                                       &LocationAssignment::Pack);
+        assert!(validate(&remapped).is_empty());
+
+        // The vec4 takes a whole location, then the float fills the end
+        // of the vec3's
+        assert_eq!(locations(&remapped, desc::StorageClass::Output),
+                   vec![(1, 3), (2, 0), (1, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn link() {
+        let producer = assemble(PRODUCER).unwrap();
+        let consumer = assemble(CONSUMER).unwrap();
+        let (producer, consumer) = link_interfaces(&producer, &consumer,
+                                                   &LocationAssignment::Pack);
+        assert!(validate(&producer).is_empty());
+        assert!(validate(&consumer).is_empty());
+
+        // `c` isn't read by the consumer and is only ever stored to, so
+        // it's removed, but `a` is loaded by the producer so it's kept
+        assert_eq!(locations(&producer, desc::StorageClass::Output),
+                   vec![(1, 2), (1, 0), (0, 0)]);
+        assert_eq!(locations(&consumer, desc::StorageClass::Input),
+                   vec![(1, 0), (0, 0)]);
+        match producer.instructions()[2] {
+            Instruction::EntryPoint { ref interface, .. } => assert_eq!(interface.len(), 3),
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+        let stores = producer.instructions().iter().filter(|inst| {
+            inst.opcode() == desc::Op::Store as u16
+        }).count();
+        assert_eq!(stores, 1);
+    }
+
+    #[test]
+    fn variable_past_id_bound() {
+        // Move the outputs past the id bound, so they aren't in the
+        // definition map
+        let module = assemble(PRODUCER).unwrap();
+        let vars : Vec<_> = module.instructions().iter().filter_map(|inst| match *inst {
+            Instruction::Variable { result_id, .. } => Some(Id::from(result_id)),
+            _ => None
+        }).collect();
+        let bound = module.header().id_bound;
+        let instructions = module.instructions().iter().map(|inst| {
+            let mut inst = inst.clone();
+            inst.map_ids(|id| if vars.contains(&id) { Id(id.0 + bound) } else { id });
+            inst
+        }).collect();
+        let producer = RawModule::from_instructions(module.header().clone(), instructions);
+        assert_eq!(producer.def_index(Id(vars[0].0 + bound)), None);
+
+        let consumer = assemble(CONSUMER).unwrap();
+        let (producer, _) = link_interfaces(&producer, &consumer, &LocationAssignment::Pack);
+        assert_eq!(locations(&producer, desc::StorageClass::Output).len(), 4);
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transformations over modules. Each transform takes a module and
+//! produces a new module.
+
+use std::ops::Range;
+
+use desc::ValueId;
+use instruction::Instruction;
+use RawModule;
+
+mod bindings;
+mod compact;
+mod dce;
+mod extract;
+mod fold;
+mod inline;
+mod interface;
+mod strip;
+
+pub use self::bindings::{remap_bindings, BindingError, BindingMap, ResourceType};
+pub use self::compact::compact_ids;
+pub use self::dce::eliminate_dead_code;
+pub use self::extract::extract_entry_point;
+pub use self::fold::fold_constants;
+pub use self::inline::inline_functions;
+pub use self::interface::{link_interfaces, remap_locations, LocationAssignment};
+pub use self::strip::{strip_debug_info, StripStats};
+
+/// Gets the id and the range of instructions, from the `Function` to the
+/// `FunctionEnd` inclusive, of each function in the module
+fn functions(module: &RawModule) -> Vec<(ValueId, Range<usize>)> {
+    let mut funcs = Vec::new();
+    let mut current = None;
+
+    for (idx, inst) in module.instructions().iter().enumerate() {
+        match *inst {
+            Instruction::Function { result_id, .. } => {
+                current = Some((result_id.to_value_id(), idx));
+            }
+            Instruction::FunctionEnd => {
+                if let Some((id, start)) = current.take() {
+                    funcs.push((id, start..idx + 1));
+                }
+            }
+            _ => ()
+        }
+    }
+
+    funcs
+}
+
+/// Gets the index just past the annotation section of the module, where
+/// new decorations can be inserted
+fn annotations_end(module: &RawModule) -> usize {
+    module.instructions().iter().position(|inst| {
+        use instruction::Instruction::*;
+        match *inst {
+            Capability { .. } | Extension { .. } | ExtInstImport { .. } |
+            MemoryModel { .. } | EntryPoint { .. } | ExecutionMode { .. } |
+            SourceContinued { .. } | Source { .. } | SourceExtension { .. } |
+            Name { .. } | MemberName { .. } | String { .. } |
+            ModuleProcessed { .. } | Decorate { .. } | MemberDecorate { .. } |
+            DecorationGroup { .. } | GroupDecorate { .. } |
+            GroupMemberDecorate { .. } => false,
+            _ => true
+        }
+    }).unwrap_or(module.instructions().len())
+}
@@ -0,0 +1,148 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashSet;
+
+use desc::ValueId;
+use instruction::Instruction;
+use write::encode_instruction;
+use RawModule;
+
+const NON_SEMANTIC_PREFIX : &'static str = "NonSemantic.";
+const NON_SEMANTIC_EXTENSION : &'static str = "SPV_KHR_non_semantic_info";
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StripStats {
+    pub instructions_removed: usize,
+    pub words_removed: usize
+}
+
+/**
+ * Remove all debug information from the module. This removes names,
+ * source information, strings, line information and any instructions
+ * from non-semantic extended instruction sets.
+ *
+ * The id bound of the resulting module is left unchanged.
+ */
+pub fn strip_debug_info(module: &RawModule) -> (RawModule, StripStats) {
+    // Find the ids of any non-semantic instruction sets, they can be
+    // removed along with everything that uses them
+    let non_semantic_sets : HashSet<ValueId> = module.instructions().iter().filter_map(|inst| {
+        if let Instruction::ExtInstImport { result_id, ref name } = *inst {
+            if name.starts_with(NON_SEMANTIC_PREFIX) {
+                return Some(result_id.to_value_id());
+            }
+        }
+        None
+    }).collect();
+
+    let mut stats = StripStats::default();
+    let mut words = Vec::new();
+    let instructions : Vec<_> = module.instructions().iter().filter(|inst| {
+        if !is_debug_info(inst, &non_semantic_sets) {
+            return true;
+        }
+        words.clear();
+        encode_instruction(inst, &mut words);
+        stats.instructions_removed += 1;
+        stats.words_removed += words.len();
+        false
+    }).cloned().collect();
+
+    let stripped = RawModule::from_instructions(module.header().clone(), instructions);
+
+    (stripped, stats)
+}
+
+fn is_debug_info(inst: &Instruction, non_semantic_sets: &HashSet<ValueId>) -> bool {
+    use instruction::Instruction::*;
+    match *inst {
+        SourceContinued { .. } |
+        Source { .. } |
+        SourceExtension { .. } |
+        Name { .. } |
+        MemberName { .. } |
+        String { .. } |
+        Line { .. } |
+        NoLine |
+        ModuleProcessed { .. } => true,
+        ExtInstImport { result_id, .. } => {
+            non_semantic_sets.contains(&result_id.to_value_id())
+        }
+        ExtInst { set, .. } => non_semantic_sets.contains(&set),
+        // The extension is only needed for non-semantic instruction
+        // sets, which are all removed
+        Extension { ref name } => &name[..] == NON_SEMANTIC_EXTENSION,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::assemble;
+    use desc;
+    use validate::validate;
+    use RawModule;
+
+    use super::strip_debug_info;
+
+    const MODULE : &'static str = "
+        OpCapability Shader
+        OpExtension \"SPV_KHR_non_semantic_info\"
+        %glsl = OpExtInstImport \"GLSL.std.450\"
+        %debug = OpExtInstImport \"NonSemantic.Shader.DebugInfo.100\"
+        OpMemoryModel Logical GLSL450
+        OpEntryPoint Fragment %main \"main\"
+        %file = OpString \"shader.frag\"
+        OpName %main \"main\"
+        %void = OpTypeVoid
+        %fn_void = OpTypeFunction %void
+        %float = OpTypeFloat 32
+        %one = OpConstant %float 1
+        %src = OpExtInst %void %debug 35 %file
+        %main = OpFunction %void None %fn_void
+        %entry = OpLabel
+        OpLine %file 1 1
+        %x = OpExtInst %float %glsl 4 %one
+        OpReturn
+        OpFunctionEnd
+    ";
+
+    fn count(module: &RawModule, op: desc::Op) -> usize {
+        module.instructions().iter().filter(|inst| inst.opcode() == op as u16).count()
+    }
+
+    #[test]
+    fn non_semantic() {
+        let module = assemble(MODULE).unwrap();
+        let (stripped, stats) = strip_debug_info(&module);
+        assert!(validate(&stripped).is_empty());
+
+        // The extension, the import and the instruction from the
+        // non-semantic set go, the GLSL ones stay
+        assert_eq!(count(&stripped, desc::Op::Extension), 0);
+        assert_eq!(count(&stripped, desc::Op::ExtInstImport), 1);
+        assert_eq!(count(&stripped, desc::Op::ExtInst), 1);
+        assert_eq!(count(&stripped, desc::Op::String), 0);
+        assert_eq!(count(&stripped, desc::Op::Name), 0);
+        assert_eq!(count(&stripped, desc::Op::Line), 0);
+
+        assert_eq!(stats.instructions_removed, 6);
+        assert_eq!(stats.instructions_removed,
+                   module.instructions().len() - stripped.instructions().len());
+        assert_eq!(stats.words_removed, module.to_words().len() - stripped.to_words().len());
+    }
+
+    #[test]
+    fn other_extensions_kept() {
+        let module = assemble(&MODULE.replace("SPV_KHR_non_semantic_info",
+                                              "SPV_KHR_storage_buffer_storage_class")).unwrap();
+        let (stripped, _) = strip_debug_info(&module);
+        assert_eq!(count(&stripped, desc::Op::Extension), 1);
+    }
+}
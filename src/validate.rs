@@ -0,0 +1,277 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Basic validation of modules.
+//!
+//! This only checks the structure of a module: that its instructions are
+//! in the right sections, that functions are made of properly terminated
+//! blocks and that ids refer to something of the right kind. It is not a
+//! replacement for a full validator.
+
+use std::{fmt, error};
+
+use desc::Id;
+use diagnostic::Diagnostic;
+use instruction::Instruction;
+use RawModule;
+
+/// A problem found in a module. Each error gives the index of the
+/// instruction it was found at, if there is one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// An instruction isn't allowed in the section of the module it's in
+    OutOfOrder(usize),
+    /// An instruction inside a function isn't allowed where it is
+    BadFunctionLayout(usize),
+    /// A function or block isn't ended before the end of the module
+    UnterminatedFunction(usize),
+    UndefinedId(usize, Id),
+    /// An id is used as a type, but doesn't refer to one
+    NotAType(usize, Id),
+    /// An entry point refers to something other than a function
+    NotAFunction(usize, Id),
+    MissingMemoryModel,
+    DuplicateMemoryModel(usize)
+}
+
+impl ValidationError {
+    /**
+     * Gets the index of the instruction the error was found at, if there
+     * is one
+     */
+    pub fn index(&self) -> Option<usize> {
+        use self::ValidationError::*;
+        match *self {
+            OutOfOrder(idx) | BadFunctionLayout(idx) | UnterminatedFunction(idx) |
+            UndefinedId(idx, _) | NotAType(idx, _) | NotAFunction(idx, _) |
+            DuplicateMemoryModel(idx) => Some(idx),
+            MissingMemoryModel => None
+        }
+    }
+
+    /**
+     * Gets a diagnostic for the error, with the index of the instruction
+     * if there is one
+     */
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new("validating", self.message());
+        diagnostic.index = self.index();
+        diagnostic
+    }
+
+    /// The error without the index of the instruction
+    fn message(&self) -> String {
+        use self::ValidationError::*;
+        match *self {
+            OutOfOrder(_) => "not allowed in this section of the module".to_owned(),
+            BadFunctionLayout(_) => "not allowed here in a function".to_owned(),
+            UnterminatedFunction(_) => "function is not terminated".to_owned(),
+            UndefinedId(_, id) => format!("uses undefined id `{:?}`", id),
+            NotAType(_, id) => format!("uses `{:?}` as a type, but it isn't one", id),
+            NotAFunction(_, id) => {
+                format!("entry point refers to `{:?}`, which isn't a function", id)
+            }
+            MissingMemoryModel => "module has no memory model".to_owned(),
+            DuplicateMemoryModel(_) => "second memory model".to_owned()
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("Error while validating: "));
+        if let Some(idx) = self.index() {
+            try!(write!(f, "instruction {}: ", idx));
+        }
+        f.write_str(&self.message())
+    }
+}
+
+impl error::Error for ValidationError {
+    fn description(&self) -> &str {
+        use self::ValidationError::*;
+        match *self {
+            OutOfOrder(_) => "instruction out of order",
+            BadFunctionLayout(_) => "bad function layout",
+            UnterminatedFunction(_) => "unterminated function",
+            UndefinedId(_, _) => "undefined id",
+            NotAType(_, _) => "id is not a type",
+            NotAFunction(_, _) => "entry point is not a function",
+            MissingMemoryModel => "missing memory model",
+            DuplicateMemoryModel(_) => "duplicate memory model"
+        }
+    }
+}
+
+/**
+ * Validate the module, returning all the problems found. An empty list
+ * means the module is valid, as far as these checks go.
+ */
+pub fn validate(module: &RawModule) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    check_layout(module, &mut errors);
+    check_ids(module, &mut errors);
+
+    errors
+}
+
+/// The position within the module's layout
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Capability,
+    Extension,
+    ExtInstImport,
+    MemoryModel,
+    EntryPoint,
+    ExecutionMode,
+    Debug,
+    Annotation,
+    Global,
+    /// Between functions
+    Functions,
+    /// After the `Function`, before the first block
+    FunctionStart,
+    Block,
+    /// Between blocks
+    FunctionBody
+}
+
+fn check_layout(module: &RawModule, errors: &mut Vec<ValidationError>) {
+    use instruction::Instruction::*;
+
+    let mut section = Section::Capability;
+    let mut function_start = 0;
+    let mut memory_models = 0;
+
+    for (idx, inst) in module.instructions().iter().enumerate() {
+        match section {
+            Section::FunctionStart | Section::Block | Section::FunctionBody => {
+                let next = match (section, inst) {
+                    (Section::FunctionStart, &FunctionParameter { .. }) => Section::FunctionStart,
+                    (Section::Block, &Label { .. }) |
+                    (Section::Block, &Function { .. }) |
+                    (Section::Block, &FunctionEnd) |
+                    (_, &FunctionParameter { .. }) => {
+                        errors.push(ValidationError::BadFunctionLayout(idx));
+                        section
+                    }
+                    (_, &Label { .. }) => Section::Block,
+                    (Section::Block, _) if is_terminator(inst) => Section::FunctionBody,
+                    (Section::Block, _) => Section::Block,
+                    (_, &FunctionEnd) => Section::Functions,
+                    // The only other instructions allowed outside of
+                    // blocks are debug line information
+                    (_, &Line { .. }) |
+                    (_, &NoLine) |
+                    (_, &Unknown(..)) => section,
+                    _ => {
+                        errors.push(ValidationError::BadFunctionLayout(idx));
+                        section
+                    }
+                };
+                section = next;
+                continue;
+            }
+            _ => ()
+        }
+
+        let inst_section = match *inst {
+            Capability { .. } => Section::Capability,
+            Extension { .. } => Section::Extension,
+            ExtInstImport { .. } => Section::ExtInstImport,
+            MemoryModel { .. } => {
+                memory_models += 1;
+                if memory_models > 1 {
+                    errors.push(ValidationError::DuplicateMemoryModel(idx));
+                }
+                Section::MemoryModel
+            }
+            EntryPoint { .. } => Section::EntryPoint,
+            ExecutionMode { .. } => Section::ExecutionMode,
+            SourceContinued { .. } | Source { .. } | SourceExtension { .. } |
+            Name { .. } | MemberName { .. } | String { .. } |
+            ModuleProcessed { .. } => Section::Debug,
+            Decorate { .. } | MemberDecorate { .. } | DecorationGroup { .. } |
+            GroupDecorate { .. } | GroupMemberDecorate { .. } => Section::Annotation,
+            Function { .. } => {
+                function_start = idx;
+                section = Section::FunctionStart;
+                continue;
+            }
+            // Lines can be anywhere after the annotations
+            Line { .. } | NoLine => ::std::cmp::max(section, Section::Global),
+            // Nothing is known about where unknown instructions belong
+            Unknown(..) => continue,
+            _ if section == Section::Functions => {
+                errors.push(ValidationError::BadFunctionLayout(idx));
+                continue;
+            }
+            _ => Section::Global
+        };
+
+        if inst_section < section {
+            errors.push(ValidationError::OutOfOrder(idx));
+        } else {
+            section = inst_section;
+        }
+    }
+
+    match section {
+        Section::FunctionStart | Section::Block | Section::FunctionBody => {
+            errors.push(ValidationError::UnterminatedFunction(function_start));
+        }
+        _ => ()
+    }
+
+    if memory_models == 0 {
+        errors.push(ValidationError::MissingMemoryModel);
+    }
+}
+
+fn check_ids(module: &RawModule, errors: &mut Vec<ValidationError>) {
+    let is_type = |id: Id| module.def(id).map(|def| def.defines_type().is_some()).unwrap_or(false);
+
+    for (idx, inst) in module.instructions().iter().enumerate() {
+        if let Instruction::Unknown(..) = *inst { continue; }
+
+        for id in inst.uses_iter() {
+            // Missing optional ids are represented by the invalid id
+            if id.is_valid() && module.def(id).is_none() {
+                errors.push(ValidationError::UndefinedId(idx, id));
+            }
+        }
+
+        if let Some(ty) = inst.type_id_of() {
+            let ty = Id::from(ty);
+            if module.def(ty).is_some() && !is_type(ty) {
+                errors.push(ValidationError::NotAType(idx, ty));
+            }
+        }
+
+        if let Instruction::EntryPoint { func, .. } = *inst {
+            match module.def(func) {
+                Some(&Instruction::Function { .. }) | None => (),
+                Some(_) => errors.push(ValidationError::NotAFunction(idx, func.into()))
+            }
+        }
+    }
+}
+
+fn is_terminator(inst: &Instruction) -> bool {
+    match *inst {
+        Instruction::Branch { .. } |
+        Instruction::BranchConditional { .. } |
+        Instruction::Switch { .. } |
+        Instruction::Return |
+        Instruction::ReturnValue { .. } |
+        Instruction::Kill |
+        Instruction::Unreachable => true,
+        _ => false
+    }
+}
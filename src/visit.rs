@@ -0,0 +1,206 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Visiting the operands of instructions without matching on every
+//! instruction.
+//!
+//! `Visitor`, `VisitorMut` and `Fold` have a hook for each kind of
+//! operand in `core.desc`, such as `visit_value_id` or
+//! `visit_storage_class`, and `visit_instruction` (or `fold_instruction`)
+//! calls the hook for each operand of an instruction. Implement the hooks
+//! for the operands you're interested in, the rest do nothing. Override
+//! `visit_instruction` to look at the whole instruction, calling
+//! `walk_instruction` to carry on to its operands.
+//!
+//! Types are defined by their result type, which is passed to the
+//! `result_id` hook like the ids defined by other instructions, so
+//! `visit_result_type` only sees the types of values. Missing optional
+//! ids aren't visited. `Unknown` instructions have no operands to visit.
+
+use desc::{self, Id, TypeId, ValueId, ResultId};
+use instruction::{Instruction, Decoration, ExecutionMode, ImageOperands};
+
+include!(concat!(env!("OUT_DIR"), "/inst_visit.rs"));
+
+#[cfg(test)]
+mod tests {
+    use desc::{self, Id, TypeId, ValueId, ResultId};
+    use instruction::{Instruction, ImageOperands};
+
+    use super::{Fold, Visitor, VisitorMut};
+
+    /// Records the id and storage class hooks that are called
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>
+    }
+
+    impl Visitor for Recorder {
+        fn visit_result_id(&mut self, id: ResultId) {
+            self.calls.push(format!("result_id {}", id.0));
+        }
+        fn visit_result_type(&mut self, id: TypeId) {
+            self.calls.push(format!("result_type {}", id.0));
+        }
+        fn visit_type_id(&mut self, id: TypeId) {
+            self.calls.push(format!("type_id {}", id.0));
+        }
+        fn visit_value_id(&mut self, id: ValueId) {
+            self.calls.push(format!("value_id {}", id.0));
+        }
+        fn visit_id(&mut self, id: Id) {
+            self.calls.push(format!("id {}", id.0));
+        }
+        fn visit_string(&mut self, s: &str) {
+            self.calls.push(format!("string {}", s));
+        }
+        fn visit_storage_class(&mut self, class: desc::StorageClass) {
+            self.calls.push(format!("storage_class {:?}", class));
+        }
+    }
+
+    fn record(inst: &Instruction) -> Vec<String> {
+        let mut recorder = Recorder::default();
+        recorder.visit_instruction(inst);
+        recorder.calls
+    }
+
+    fn variable(init: u32) -> Instruction {
+        Instruction::Variable {
+            result_type: TypeId(2),
+            result_id: ResultId(3),
+            storage_class: desc::StorageClass::Function,
+            init: ValueId(init)
+        }
+    }
+
+    fn sample() -> Instruction {
+        let mut operands = ImageOperands::new();
+        operands.set(desc::ImgOpBias, ValueId(7));
+        Instruction::ImageSampleImplicitLod {
+            result_type: TypeId(1),
+            result_id: ResultId(4),
+            image: ValueId(5),
+            coord: ValueId(6),
+            image_operands: operands
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        // Types are defined through their result type
+        assert_eq!(record(&Instruction::TypePointer {
+            result_type: TypeId(2),
+            storage_class: desc::StorageClass::Function,
+            pointee: TypeId(1)
+        }), ["result_id 2", "storage_class Function", "type_id 1"]);
+
+        // A missing initializer isn't visited
+        assert_eq!(record(&variable(0)),
+                   ["result_type 2", "result_id 3", "storage_class Function"]);
+        assert_eq!(record(&variable(8)),
+                   ["result_type 2", "result_id 3", "storage_class Function", "value_id 8"]);
+
+        assert_eq!(record(&Instruction::Name { id: Id(3), name: "x".to_owned() }),
+                   ["id 3", "string x"]);
+
+        // Image operand values are visited as values
+        assert_eq!(record(&sample()),
+                   ["result_type 1", "result_id 4", "value_id 5", "value_id 6", "value_id 7"]);
+
+        assert!(record(&Instruction::Unknown(0xFFFF, vec![1, 2].into_boxed_slice())).is_empty());
+    }
+
+    /// Adds 100 to every value id and result id
+    struct Offset;
+
+    impl VisitorMut for Offset {
+        fn visit_result_id(&mut self, id: &mut ResultId) {
+            id.0 += 100;
+        }
+        fn visit_value_id(&mut self, id: &mut ValueId) {
+            id.0 += 100;
+        }
+    }
+
+    impl Fold for Offset {
+        fn fold_result_id(&mut self, id: ResultId) -> ResultId {
+            ResultId(id.0 + 100)
+        }
+        fn fold_value_id(&mut self, id: ValueId) -> ValueId {
+            ValueId(id.0 + 100)
+        }
+    }
+
+    fn check_offset(inst: &Instruction, init: u32) {
+        match *inst {
+            Instruction::Variable { result_type, result_id, init: i, .. } => {
+                assert_eq!(result_type, TypeId(2));
+                assert_eq!(result_id, ResultId(103));
+                assert_eq!(i, ValueId(init));
+            }
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+    }
+
+    #[test]
+    fn visitor_mut() {
+        let mut inst = variable(0);
+        Offset.visit_instruction(&mut inst);
+        check_offset(&inst, 0);
+
+        let mut inst = variable(8);
+        Offset.visit_instruction(&mut inst);
+        check_offset(&inst, 108);
+
+        // The type is changed through the result id hook
+        let mut inst = Instruction::TypeInt { result_type: TypeId(1), width: 32, signed: true };
+        Offset.visit_instruction(&mut inst);
+        match inst {
+            Instruction::TypeInt { result_type, .. } => assert_eq!(result_type, TypeId(101)),
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+
+        let mut inst = sample();
+        Offset.visit_instruction(&mut inst);
+        match inst {
+            Instruction::ImageSampleImplicitLod { result_id, image, ref image_operands, .. } => {
+                assert_eq!(result_id, ResultId(104));
+                assert_eq!(image, ValueId(105));
+                assert_eq!(image_operands.values(), [ValueId(107)]);
+            }
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+    }
+
+    #[test]
+    fn fold() {
+        check_offset(&Offset.fold_instruction(variable(0)), 0);
+        check_offset(&Offset.fold_instruction(variable(8)), 108);
+
+        match Offset.fold_instruction(Instruction::TypeInt {
+            result_type: TypeId(1),
+            width: 32,
+            signed: true
+        }) {
+            Instruction::TypeInt { result_type, width, .. } => {
+                assert_eq!(result_type, TypeId(101));
+                assert_eq!(width, 32);
+            }
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+
+        match Offset.fold_instruction(sample()) {
+            Instruction::ImageSampleImplicitLod { coord, ref image_operands, .. } => {
+                assert_eq!(coord, ValueId(106));
+                assert_eq!(image_operands.values(), [ValueId(107)]);
+            }
+            ref inst => panic!("unexpected {:?}", inst)
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use desc::{self, Id, ValueId, TypeId, ResultId, Op};
+use instruction::{Instruction, Decoration, ExecutionMode, ImageOperands};
+
+include!(concat!(env!("OUT_DIR"), "/inst_writer.rs"));
+
+trait ParamEncode {
+    fn encode(&self, words: &mut Vec<u32>);
+}
+
+impl ParamEncode for Id {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(self.0);
+    }
+}
+
+impl ParamEncode for ValueId {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(self.0);
+    }
+}
+
+impl ParamEncode for TypeId {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(self.0);
+    }
+}
+
+impl ParamEncode for ResultId {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(self.0);
+    }
+}
+
+impl ParamEncode for String {
+    fn encode(&self, words: &mut Vec<u32>) {
+        // Strings are nul-terminated and padded out to a whole number of
+        // words, so a string with a length that is a multiple of 4 still
+        // needs an extra word for the terminator
+        let bytes = self.as_bytes();
+        for chunk in bytes.chunks(4) {
+            let mut w = 0;
+            for (i, &b) in chunk.iter().enumerate() {
+                w |= (b as u32) << (i * 8);
+            }
+            words.push(w);
+        }
+
+        if bytes.len() % 4 == 0 {
+            words.push(0);
+        }
+    }
+}
+
+impl ParamEncode for u32 {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(*self);
+    }
+}
+
+impl ParamEncode for bool {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(*self as u32);
+    }
+}
+
+impl<P: ParamEncode> ParamEncode for Option<P> {
+    fn encode(&self, words: &mut Vec<u32>) {
+        if let Some(ref p) = *self {
+            p.encode(words);
+        }
+    }
+}
+
+impl<P: ParamEncode> ParamEncode for Box<[P]> {
+    fn encode(&self, words: &mut Vec<u32>) {
+        for p in self.iter() {
+            p.encode(words);
+        }
+    }
+}
+
+impl ParamEncode for ExecutionMode {
+    fn encode(&self, words: &mut Vec<u32>) {
+        use instruction::ExecutionMode::*;
+
+        words.push(self.to_desc() as u32);
+        match *self {
+            Invocations(n) |
+            OutputVertices(n) |
+            VecTypeHint(n) => words.push(n),
+            LocalSize(x, y, z) |
+            LocalSizeHint(x, y, z) => {
+                words.push(x);
+                words.push(y);
+                words.push(z);
+            }
+            _ => ()
+        }
+    }
+}
+
+impl ParamEncode for Decoration {
+    fn encode(&self, words: &mut Vec<u32>) {
+        use instruction::Decoration::*;
+
+        words.push(self.to_desc() as u32);
+        match *self {
+            SpecId(n) |
+            ArrayStride(n) |
+            MatrixStride(n) |
+            Stream(n) |
+            Location(n) |
+            Component(n) |
+            Index(n) |
+            Binding(n) |
+            DescriptorSet(n) |
+            Offset(n) |
+            XfbBuffer(n) |
+            XfbStride(n) |
+            InputAttachmentIndex(n) |
+            Alignment(n) => words.push(n),
+            BuiltIn(b) => b.encode(words),
+            FuncParamAttr(attr) => attr.encode(words),
+            FPRoundingMode(mode) => mode.encode(words),
+            FPFastMathMode(mode) => mode.encode(words),
+            LinkageAttributes(ref name, ty) => {
+                name.encode(words);
+                ty.encode(words);
+            }
+            _ => ()
+        }
+    }
+}
+
+impl ParamEncode for ImageOperands {
+    fn encode(&self, words: &mut Vec<u32>) {
+        words.push(self.mask().bits());
+        for v in self.values() {
+            v.encode(words);
+        }
+    }
+}
+
+macro_rules! impl_param_encode_word(
+    (enum $($name:ident),+) => (
+        $(impl ParamEncode for ::desc::$name {
+            fn encode(&self, words: &mut Vec<u32>) {
+                words.push(*self as u32);
+            }
+        })+
+    );
+    (bitset $($name:ident),+) => (
+        $(impl ParamEncode for ::desc::$name {
+            fn encode(&self, words: &mut Vec<u32>) {
+                words.push(self.bits());
+            }
+        })+
+    );
+);
+
+impl_param_encode_word!(enum
+    SrcLang,
+    ExecutionModel,
+    AddressingModel,
+    MemoryModel,
+    ExecutionMode,
+    StorageClass,
+    Dim,
+    SamplerAddressingMode,
+    SamplerFilterMode,
+    ImageFormat,
+    ImageChannelOrder,
+    ImageChannelDataType,
+    FPRoundingMode,
+    LinkageType,
+    AccessQualifier,
+    FuncParamAttr,
+    Decoration,
+    BuiltIn,
+    Scope,
+    GroupOperation,
+    KernelEnqueueFlags,
+    Capability
+);
+
+impl_param_encode_word!(bitset
+    FPFastMathMode,
+    SelectionControl,
+    LoopControl,
+    FunctionControl,
+    MemoryOrdering,
+    // MemoryAccess, // Handled below
+    KernelProfilingInfo
+);
+
+impl ParamEncode for desc::MemoryAccess {
+    fn encode(&self, words: &mut Vec<u32>) {
+        // The parser treats a missing operand as an empty set, so
+        // only write it out when it's needed
+        if !self.is_empty() {
+            words.push(self.bits());
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2016 James Miller
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{self, Write};
+
+use instruction::Instruction;
+use parse::Header;
+
+mod encoder;
+
+pub use self::encoder::encode_instruction;
+
+const MAGIC_NUMBER : u32 = 0x07230203;
+
+/**
+ * Writes SPIR-V words to the underlying writer. Words are always
+ * written in little-endian order.
+ */
+pub struct Writer<W: ?Sized + Write> {
+    buf: Vec<u32>,
+    writer: W
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W) -> Writer<W> {
+        Writer {
+            buf: Vec::new(),
+            writer: writer
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: ?Sized + Write> Writer<W> {
+    fn write_words(&mut self, words: &[u32]) -> io::Result<()> {
+        for &w in words {
+            let bytes = [
+                w as u8,
+                (w >> 8) as u8,
+                (w >> 16) as u8,
+                (w >> 24) as u8
+            ];
+            try!(self.writer.write_all(&bytes));
+        }
+        Ok(())
+    }
+
+    pub fn write_header(&mut self, header: &Header) -> io::Result<()> {
+        let words = header_words(header);
+        self.write_words(&words)
+    }
+
+    pub fn write_instruction(&mut self, inst: &Instruction) -> io::Result<()> {
+        let mut buf = ::std::mem::replace(&mut self.buf, Vec::new());
+        buf.clear();
+        encode_instruction(inst, &mut buf);
+
+        let res = self.write_words(&buf);
+        self.buf = buf;
+        res
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/**
+ * Gets the words making up the module header
+ */
+pub fn header_words(header: &Header) -> [u32; 5] {
+    let (major, minor) = header.version;
+    let version = ((major as u32) << 16) | ((minor as u32) << 8);
+
+    [MAGIC_NUMBER, version, header.generator_id, header.id_bound, 0]
+}